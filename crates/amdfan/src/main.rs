@@ -2,7 +2,9 @@ use std::io;
 use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-use amdgpu_config::fan::{TempPoint, DEFAULT_FAN_CONFIG_PATH};
+use amdgpu::environment::Environment;
+use amdgpu::utils::{atomic_write, CONFIG_BACKUPS};
+use amdgpu_config::fan::{TempPoint, FAN_CONFIG_FILE};
 use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode};
 use crossterm::execute;
 use crossterm::terminal::{
@@ -59,10 +61,10 @@ fn main() -> Result<(), io::Error> {
     let opts: Opts = Options::parse_args_default_or_exit();
     let config_path = opts
         .config
-        .as_deref()
-        .and_then(|p| p.to_str())
-        .unwrap_or(DEFAULT_FAN_CONFIG_PATH);
-    let config = amdgpu_config::fan::load_config(config_path).unwrap();
+        .unwrap_or_else(|| Environment::from_env().config_file(FAN_CONFIG_FILE));
+    // edited config is saved back, drop-in files and env overrides must not
+    // be copied to it
+    let config = amdgpu_config::fan::load_base_config(config_path.to_str().unwrap()).unwrap();
 
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -120,8 +122,11 @@ fn handle_event(app: &mut App) -> io::Result<Status> {
         match key.code {
             KeyCode::Char('q') => return Ok(Status::Exit),
             KeyCode::Char('w') => {
-                let s = toml::to_string_pretty(&app.config).unwrap();
-                std::fs::write(app.config.path(), s)?;
+                let s = app
+                    .config
+                    .to_toml_preserving()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                atomic_write(app.config.path(), s, CONFIG_BACKUPS)?;
             }
             KeyCode::Char(' ') => {
                 if let Some(index) = app.table_state.selected() {
@@ -1,3 +1,4 @@
+use amdgpu::environment::Environment;
 use amdgpu::hw_mon::SysFs;
 use amdgpu::utils::hw_mons;
 use amdgpu_config::fan::Config;
@@ -8,8 +9,13 @@ use crate::command::Fan;
 use crate::{AmdFanError, FanMode};
 
 /// Change card fan mode to either automatic or manual
-pub fn run(switcher: Switcher, mode: FanMode, config: Config) -> crate::Result<()> {
-    let mut hw_mons = Fan::<SysFs>::wrap_all(hw_mons(true)?, &config);
+pub fn run(
+    switcher: Switcher,
+    mode: FanMode,
+    env: &Environment,
+    config: Config,
+) -> crate::Result<()> {
+    let mut hw_mons = Fan::<SysFs>::wrap_all(hw_mons(env, true)?, &config);
 
     let cards = match switcher.card {
         Some(card_id) => match hw_mons.iter().position(|hw_mon| **hw_mon.card() == card_id) {
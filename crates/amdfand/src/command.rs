@@ -1,13 +1,13 @@
 use amdgpu::hw_mon::HwMon;
 use amdgpu::utils::{linear_map, load_temp_inputs};
 use amdgpu::{
-    utils, TempInput, PULSE_WIDTH_MODULATION_MANUAL, PULSE_WIDTH_MODULATION_MAX,
-    PULSE_WIDTH_MODULATION_MIN, PULSE_WIDTH_MODULATION_MODE,
+    utils, TempInput, FAN_INPUT, FAN_MAX, FAN_MIN, PULSE_WIDTH_MODULATION_MANUAL,
+    PULSE_WIDTH_MODULATION_MAX, PULSE_WIDTH_MODULATION_MIN, PULSE_WIDTH_MODULATION_MODE,
 };
 use amdgpu_config::fan::Config;
 use gumdrop::Options;
 
-use crate::{change_mode, service};
+use crate::{change_mode, config, control, curve, eval, service, simulate};
 
 #[derive(Debug, Options)]
 pub struct AvailableCards {
@@ -25,6 +25,20 @@ pub enum FanCommand {
     SetManual(change_mode::Switcher),
     #[options(help = "Print available cards")]
     Available(AvailableCards),
+    #[options(help = "Inspect config file")]
+    Config(config::ConfigCommand),
+    #[options(help = "Print state of running service")]
+    Status(control::StatusCommand),
+    #[options(help = "Force fan speed in running service for given time")]
+    Override(control::OverrideCommand),
+    #[options(help = "Go back to fan curves in running service")]
+    ClearOverride(control::ClearOverrideCommand),
+    #[options(help = "Run fan curves against recorded sensors trace")]
+    Simulate(simulate::Simulate),
+    #[options(help = "Print fan speed for temperature and usage from config")]
+    Eval(eval::Eval),
+    #[options(help = "Edit temperature and usage curves in config file")]
+    Curve(curve::CurveCommand),
 }
 
 #[derive(Debug, onlyerror::Error)]
@@ -46,6 +60,11 @@ pub enum FanError {
     },
 }
 
+/// Map speed percent to modulation between `pwm_min` and `pwm_max`
+pub fn pwm_for_speed(speed: f64, pwm_min: u32, pwm_max: u32) -> u64 {
+    linear_map(speed, 0f64, 100f64, pwm_min as f64, pwm_max as f64).round() as u64
+}
+
 pub struct Fan<Root: amdgpu::hw_mon::RootPath> {
     pub hw_mon: HwMon<Root>,
     /// List of available temperature inputs for current HW MOD
@@ -74,6 +93,30 @@ impl<Root: amdgpu::hw_mon::RootPath> std::ops::DerefMut for Fan<Root> {
 
 const MODULATION_ENABLED_FILE: &str = "pwm1_enable";
 
+/// Readings above this value are treated as garbage
+const MAX_PLAUSIBLE_TEMP: f64 = 200.0;
+
+/// Single temperature sensor with its kernel limits
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sensor {
+    /// Input name (ex. temp1_input)
+    pub name: String,
+    /// Current temperature in Celsius, `None` if sensor can't be read
+    pub temp: Option<f64>,
+    /// Critical temperature in Celsius (`temp*_crit` or `temp*_emergency`)
+    pub crit: Option<f64>,
+}
+
+/// All values required to compute fan speed in single tick
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Reading {
+    /// Temperature used by temperature curve, `None` if it can't be read
+    pub temp: Option<f64>,
+    /// GPU usage percent
+    pub usage: f64,
+    pub sensors: Vec<Sensor>,
+}
+
 impl<Root: amdgpu::hw_mon::RootPath> Fan<Root> {
     pub fn wrap(hw_mon: HwMon<Root>, config: &Config) -> Self {
         Self {
@@ -90,13 +133,16 @@ impl<Root: amdgpu::hw_mon::RootPath> Fan<Root> {
     }
 
     /// Change fan speed to given value if it's between minimal and maximal
-    /// value
-    pub fn set_speed(&mut self, speed: f64) -> crate::Result<()> {
-        let min = self.pwm_min() as f64;
-        let max = self.pwm_max() as f64;
-        let pwm = linear_map(speed, 0f64, 100f64, min, max).round() as u64;
+    /// value. Returns written modulation
+    pub fn set_speed(&mut self, speed: f64) -> crate::Result<u64> {
+        let pwm = self.speed_to_pwm(speed);
         self.write_pwm(pwm)?;
-        Ok(())
+        Ok(pwm)
+    }
+
+    /// Modulation for given speed percent
+    pub fn speed_to_pwm(&mut self, speed: f64) -> u64 {
+        pwm_for_speed(speed, self.pwm_min(), self.pwm_max())
     }
 
     /// Change gpu fan speed management to manual (amdfand will manage speed)
@@ -132,25 +178,48 @@ impl<Root: amdgpu::hw_mon::RootPath> Fan<Root> {
             .unwrap_or_default()
     }
 
-    /// Get maximal GPU temperature from all inputs.
-    /// This is not recommended since GPU can heat differently in different
-    /// parts and usually only temp1 should be taken for consideration.
-    pub fn max_gpu_temp(&self) -> crate::Result<f64> {
-        if let Some(input) = self.temp_input.as_ref() {
-            let value = self.read_gpu_temp(&input.as_string())?;
-            return Ok(value as f64 / 1000f64);
-        }
-        let mut results = Vec::with_capacity(self.temp_inputs.len());
-        for name in self.temp_inputs.iter() {
-            results.push(self.read_gpu_temp(name).unwrap_or(0));
+    /// Read all sensors. Missing or malformed values are reported as `None`
+    pub fn reading(&self) -> Reading {
+        let sensors = self
+            .temp_inputs
+            .iter()
+            .map(|name| Sensor {
+                name: name.clone(),
+                temp: self.read_celsius(name),
+                crit: self.critical_temp(name),
+            })
+            .collect::<Vec<_>>();
+        let temp = match self.temp_input.as_ref() {
+            Some(input) => self.read_celsius(&input.as_string()),
+            None => sensors
+                .iter()
+                .filter_map(|sensor| sensor.temp)
+                .fold(None, |max: Option<f64>, temp| {
+                    Some(max.map_or(temp, |max| max.max(temp)))
+                }),
+        };
+        Reading {
+            temp,
+            usage: self.gpu_usage().unwrap_or(0.0),
+            sensors,
         }
-        results.sort_unstable();
-        let value = results
-            .last()
-            .copied()
+    }
+
+    /// Read temperature in Celsius, implausible values are rejected
+    fn read_celsius(&self, name: &str) -> Option<f64> {
+        self.read_gpu_temp(name)
+            .ok()
             .map(|temp| temp as f64 / 1000f64)
-            .ok_or(FanError::EmptyTempSet)?;
-        Ok(value)
+            .filter(|temp| *temp <= MAX_PLAUSIBLE_TEMP)
+    }
+
+    /// Critical temperature for given input (`temp1_input` -> `temp1_crit`).
+    /// If critical temperature is not available emergency temperature is used
+    pub fn critical_temp(&self, input: &str) -> Option<f64> {
+        let base = input.strip_suffix("_input")?;
+        self.read_celsius(&format!("{}_crit", base))
+            .or_else(|| self.read_celsius(&format!("{}_emergency", base)))
+            .filter(|temp| *temp > 0.0)
     }
 
     /// Read temperature from given input sensor
@@ -162,6 +231,30 @@ impl<Root: amdgpu::hw_mon::RootPath> Fan<Root> {
         Ok(value)
     }
 
+    /// Read fan speed reported by tachometer. `None` if card has no
+    /// tachometer
+    pub fn rpm(&self) -> Option<u32> {
+        self.hw_mon_read(FAN_INPUT).ok()?.parse().ok()
+    }
+
+    /// Fan speed expected for given modulation based on `fan1_min` and
+    /// `fan1_max`. `None` if card does not expose RPM range
+    pub fn expected_rpm(&mut self, pwm: u64) -> Option<u32> {
+        let rpm_max: u32 = self.value_or(FAN_MAX, 0);
+        if rpm_max == 0 {
+            return None;
+        }
+        let rpm_min: u32 = self.value_or(FAN_MIN, 0);
+        let rpm = linear_map(
+            pwm as f64,
+            self.pwm_min() as f64,
+            self.pwm_max() as f64,
+            rpm_min as f64,
+            rpm_max as f64,
+        );
+        Some(rpm.round() as u32)
+    }
+
     /// Read minimal fan speed. Usually this is 0
     pub fn pwm_min(&mut self) -> u32 {
         if self.pwm_min.is_none() {
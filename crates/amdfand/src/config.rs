@@ -0,0 +1,207 @@
+use std::path::{Path, PathBuf};
+
+use amdgpu::environment::Environment;
+use amdgpu::utils::{atomic_write, backup_path, list_backups, CONFIG_BACKUPS};
+use amdgpu_config::fan::{load_layers, Config, ConfigError};
+use amdgpu_config::layers::{Layered, Source};
+use gumdrop::Options;
+use toml::value::Table;
+use toml::Value;
+
+use crate::AmdFanError;
+
+#[derive(Debug, Options)]
+pub struct ConfigCommand {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(command)]
+    command: Option<ConfigSubCommand>,
+}
+
+#[derive(Debug, Options)]
+pub enum ConfigSubCommand {
+    #[options(help = "Print config file")]
+    Show(Show),
+    #[options(help = "Roll config file back to previous version")]
+    Restore(Restore),
+}
+
+#[derive(Debug, Options)]
+pub struct Show {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Print config merged with drop-in files and environment variables")]
+    effective: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct Restore {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(free, help = "Backup to restore, 1 is the newest (default)")]
+    backup: Option<usize>,
+    #[options(help = "List available backups")]
+    list: bool,
+    #[options(help = "Reload config of running service")]
+    reload: bool,
+}
+
+pub fn run(
+    command: ConfigCommand,
+    config: &Config,
+    env: &Environment,
+    name: &str,
+) -> crate::Result<()> {
+    match command.command {
+        Some(ConfigSubCommand::Show(show)) if show.effective => {
+            let layers = load_layers(config.path())?;
+            print!("{}", effective(&layers)?);
+        }
+        Some(ConfigSubCommand::Show(_)) => {
+            let content = std::fs::read_to_string(config.path())?;
+            print!("{}", content);
+        }
+        Some(ConfigSubCommand::Restore(restore)) if restore.list => {
+            for (n, path) in list_backups(config.path()) {
+                println!("{:>2} {}", n, path.display());
+            }
+        }
+        Some(ConfigSubCommand::Restore(restore)) => {
+            let backup = restore_backup(config.path(), restore.backup.unwrap_or(1))?;
+            println!("Restored {} from {}", config.path(), backup.display());
+            if restore.reload {
+                crate::curve::reload_service(env, name)?;
+                println!("Service reloaded config");
+            }
+        }
+        None => {
+            println!("{}", ConfigCommand::usage());
+        }
+    }
+    Ok(())
+}
+
+/// Replace config with its `n`-th backup. Backup must be valid config.
+/// Current content becomes the newest backup so restore can be undone
+pub fn restore_backup<P: AsRef<Path>>(path: P, n: usize) -> crate::Result<PathBuf> {
+    let backup = backup_path(&path, n);
+    if !backup.exists() {
+        return Err(AmdFanError::NoBackup(backup));
+    }
+    let content = std::fs::read_to_string(&backup)?;
+    toml::from_str::<Config>(&content)
+        .map_err(ConfigError::from)?
+        .validate()?;
+    atomic_write(&path, content, CONFIG_BACKUPS)?;
+    Ok(backup)
+}
+
+/// Render merged config as toml with source of each value as comment
+fn effective(layers: &Layered) -> crate::Result<String> {
+    let config: Config = layers.deserialize().map_err(ConfigError::from)?;
+    let table = match Value::try_from(&config) {
+        Ok(Value::Table(table)) => table,
+        _ => return Err(AmdFanError::ConfigSerialize),
+    };
+    // toml requires plain values to be placed before tables
+    let (values, tables): (Vec<_>, Vec<_>) = table
+        .into_iter()
+        .partition(|(_, value)| !is_table_like(value));
+
+    let mut buffer = String::with_capacity(1024);
+    for (key, value) in values.into_iter().chain(tables) {
+        let sources = layers.origins_of(&key);
+        let sources = if sources.is_empty() {
+            Source::Default.to_string()
+        } else {
+            sources
+                .into_iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        buffer.push_str(&format!("# from: {}\n", sources));
+
+        let mut single = Table::new();
+        single.insert(key, value);
+        let fragment = toml::to_string(&single).map_err(|_| AmdFanError::ConfigSerialize)?;
+        buffer.push_str(fragment.trim_start());
+        if !fragment.ends_with('\n') {
+            buffer.push('\n');
+        }
+    }
+    Ok(buffer)
+}
+
+fn is_table_like(value: &Value) -> bool {
+    match value {
+        Value::Table(_) => true,
+        Value::Array(items) => items.first().map(|v| v.is_table()).unwrap_or_default(),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use amdgpu::utils::{atomic_write, backup_path, CONFIG_BACKUPS};
+    use amdgpu_config::layers::{Layered, Source};
+
+    use crate::config::{effective, restore_backup};
+    use crate::AmdFanError;
+
+    #[test]
+    fn effective_config_has_sources() {
+        let mut layers = Layered::default();
+        layers.merge(
+            toml::from_str("log_level = 'Error'\nupdate_rate = 4000").unwrap(),
+            Source::File(PathBuf::from("/etc/amdfand/mapping.toml")),
+        );
+        layers.merge_env(
+            "AMDFAND_",
+            vec![("AMDFAND_UPDATE_RATE".to_string(), "1000".to_string())],
+        );
+
+        let s = effective(&layers).unwrap();
+        assert!(s.contains("# from: /etc/amdfand/mapping.toml\nlog_level = \"Error\"\n"));
+        assert!(s.contains("# from: env AMDFAND_UPDATE_RATE\nupdate_rate = 1000\n"));
+        assert!(s.contains("# from: default\n[[temp_matrix]]\n"));
+        assert!(toml::from_str::<amdgpu_config::fan::Config>(&s).is_ok());
+    }
+
+    #[test]
+    fn restore_previous_version() {
+        let dir = tempdir::TempDir::new("restore").unwrap();
+        let path = dir.path().join("mapping.toml");
+        let read = |path: &std::path::Path| std::fs::read_to_string(path).unwrap();
+        for rate in [1000, 2000, 3000] {
+            atomic_write(
+                &path,
+                format!("log_level = 'Error'\nupdate_rate = {}\n", rate),
+                CONFIG_BACKUPS,
+            )
+            .unwrap();
+        }
+        std::fs::write(
+            backup_path(&path, 1),
+            "log_level = 'Error'\nupdate_rate = 'fast'",
+        )
+        .unwrap();
+
+        assert!(matches!(
+            restore_backup(&path, 5),
+            Err(AmdFanError::NoBackup(_))
+        ));
+        // broken backup must not replace working config
+        assert!(restore_backup(&path, 1).is_err());
+        assert_eq!(read(&path), "log_level = 'Error'\nupdate_rate = 3000\n");
+
+        assert_eq!(restore_backup(&path, 2).unwrap(), backup_path(&path, 2));
+        assert_eq!(read(&path), "log_level = 'Error'\nupdate_rate = 1000\n");
+        assert_eq!(
+            read(&backup_path(&path, 1)),
+            "log_level = 'Error'\nupdate_rate = 3000\n"
+        );
+    }
+}
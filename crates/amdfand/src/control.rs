@@ -0,0 +1,318 @@
+//! Control socket of running service. Status is available for everyone,
+//! speed override requires root.
+
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use amdgpu::environment::Environment;
+use amdgpu::pidfile::fan_cmd::{
+    send_command, sock_file, Command, FanStatus, OverrideStatus, Response, Status,
+};
+use amdgpu::pidfile::{handle_connection, Pid};
+use gumdrop::Options;
+use nix::sys::socket::{getsockopt, sockopt};
+use tracing::{info, warn};
+
+pub type Service = amdgpu::pidfile::Service<Response>;
+
+struct SpeedOverride {
+    speed: f64,
+    until: Instant,
+}
+
+/// State shared between service loop and control socket
+pub struct ControlState {
+    status: Status,
+    speed_override: Option<SpeedOverride>,
+}
+
+pub type SharedState = Arc<Mutex<ControlState>>;
+
+impl ControlState {
+    pub fn new(config: &str) -> Self {
+        Self {
+            status: Status {
+                pid: Pid(std::process::id() as i32),
+                config: config.to_string(),
+                speed_override: None,
+                fans: vec![],
+            },
+            speed_override: None,
+        }
+    }
+
+    /// Speed forced with [Command::Override]. Expired override is removed
+    pub fn override_speed(&mut self, now: Instant) -> Option<f64> {
+        match self.speed_override.as_ref() {
+            Some(o) if o.until <= now => {
+                info!("Fan speed override expired, going back to curves");
+                self.speed_override = None;
+                None
+            }
+            Some(o) => Some(o.speed),
+            None => None,
+        }
+    }
+
+    pub fn update(&mut self, config: &str, fans: Vec<FanStatus>) {
+        self.status.config = config.to_string();
+        self.status.fans = fans;
+    }
+
+    fn status(&self, now: Instant) -> Status {
+        let mut status = self.status.clone();
+        status.speed_override = self.speed_override.as_ref().map(|o| OverrideStatus {
+            speed: o.speed,
+            remaining_secs: o.until.saturating_duration_since(now).as_secs(),
+        });
+        status
+    }
+
+    /// Execute command sent by user with given uid
+    fn execute(&mut self, cmd: Command, uid: Option<u32>, now: Instant) -> Response {
+        match cmd {
+            Command::Status => Response::Status(self.status(now)),
+            Command::Override { .. } | Command::ClearOverride if uid != Some(0) => {
+                Response::Rejected(String::from("Only root can change fan speed"))
+            }
+            Command::Override { speed, .. } if !(0.0..=100.0).contains(&speed) => {
+                Response::Rejected(format!("Speed {} is not between 0 and 100", speed))
+            }
+            Command::Override { duration: 0, .. } => {
+                Response::Rejected(String::from("Duration must be greater than 0"))
+            }
+            Command::Override { speed, duration } => {
+                info!("Fan speed overridden to {}% for {}s", speed, duration);
+                self.speed_override = Some(SpeedOverride {
+                    speed,
+                    until: now + Duration::from_secs(duration),
+                });
+                Response::OverrideSet
+            }
+            Command::ClearOverride => {
+                info!("Fan speed override cleared");
+                self.speed_override = None;
+                Response::OverrideCleared
+            }
+        }
+    }
+}
+
+/// Serve control socket in background thread
+pub fn serve(state_dir: &Path, name: &str, state: SharedState) -> std::io::Result<()> {
+    let sock_path = sock_file(state_dir, name);
+    let _ = std::fs::remove_file(&sock_path);
+    let listener = UnixListener::bind(&sock_path)?;
+    std::fs::set_permissions(&sock_path, Permissions::from_mode(0o666))?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Control socket connection failed. {:?}", e);
+                    continue;
+                }
+            };
+            let uid = peer_uid(&stream);
+            let state = &*state;
+            handle_connection::<_, Command, Response>(stream, move |service, cmd| {
+                handle_command(service, cmd, state, uid)
+            });
+        }
+    });
+    Ok(())
+}
+
+fn peer_uid(stream: &UnixStream) -> Option<u32> {
+    getsockopt(stream.as_raw_fd(), sockopt::PeerCredentials)
+        .ok()
+        .map(|credentials| credentials.uid())
+}
+
+fn handle_command(
+    mut service: Service,
+    cmd: Command,
+    state: &Mutex<ControlState>,
+    uid: Option<u32>,
+) {
+    let response =
+        state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .execute(cmd, uid, Instant::now());
+    service.write_response(response);
+}
+
+#[derive(Debug, Options)]
+pub struct StatusCommand {
+    #[options(help = "Help message")]
+    help: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct OverrideCommand {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Fan speed in percent", required)]
+    speed: f64,
+    #[options(help = "How long override is active in seconds", default = "600")]
+    duration: u64,
+}
+
+#[derive(Debug, Options)]
+pub struct ClearOverrideCommand {
+    #[options(help = "Help message")]
+    help: bool,
+}
+
+/// Send command to running service with given pid file name
+pub fn send(env: &Environment, name: &str, cmd: Command) -> crate::Result<()> {
+    let sock_path: PathBuf = sock_file(env.fan_state_dir(), name);
+    match send_command(sock_path, cmd)? {
+        Response::Status(status) => print!("{}", format_status(&status)),
+        Response::OverrideSet => println!("Fan speed overridden"),
+        Response::OverrideCleared => println!("Fan speed override cleared"),
+        Response::Rejected(msg) => eprintln!("Rejected: {}", msg),
+        Response::NoOp => eprintln!("Invalid command"),
+    }
+    Ok(())
+}
+
+pub fn status(_command: StatusCommand, env: &Environment, name: &str) -> crate::Result<()> {
+    send(env, name, Command::Status)
+}
+
+pub fn set_override(command: OverrideCommand, env: &Environment, name: &str) -> crate::Result<()> {
+    send(
+        env,
+        name,
+        Command::Override {
+            speed: command.speed,
+            duration: command.duration,
+        },
+    )
+}
+
+pub fn clear_override(
+    _command: ClearOverrideCommand,
+    env: &Environment,
+    name: &str,
+) -> crate::Result<()> {
+    send(env, name, Command::ClearOverride)
+}
+
+fn format_status(status: &Status) -> String {
+    fn opt<T: std::fmt::Display>(v: Option<T>, suffix: &str) -> String {
+        v.map_or_else(|| String::from("-"), |v| format!("{}{}", v, suffix))
+    }
+
+    let mut buffer = format!("amdfand {} ({})\n", *status.pid, status.config);
+    if let Some(o) = status.speed_override {
+        buffer.push_str(&format!(
+            "Override: {:.1}% for {}s\n",
+            o.speed, o.remaining_secs
+        ));
+    }
+    for fan in status.fans.iter() {
+        buffer.push_str(&format!("{}\n", fan.card));
+        buffer.push_str(&format!(
+            "  source: {:?} (temperature {} -> {:.1}%, usage {:.1}% -> {:.1}%)\n",
+            fan.source,
+            opt(fan.temp.map(|t| format!("{:.1}", t)), "°C"),
+            fan.temp_speed,
+            fan.usage,
+            fan.usage_speed,
+        ));
+        buffer.push_str(&format!(
+            "  target: {} (pwm {}), written pwm {}\n",
+            opt(fan.target_speed.map(|s| format!("{:.1}", s)), "%"),
+            opt(fan.target_pwm, ""),
+            opt(fan.written_pwm, ""),
+        ));
+        buffer.push_str(&format!("  health: {}\n", fan.health));
+        for sensor in fan.sensors.iter() {
+            buffer.push_str(&format!(
+                "  {:14} {:>8} (critical {})\n",
+                sensor.name,
+                opt(sensor.temp.map(|t| format!("{:.1}", t)), "°C"),
+                opt(sensor.crit.map(|t| format!("{:.1}", t)), "°C"),
+            ));
+        }
+    }
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use amdgpu::pidfile::fan_cmd::{Command, Response};
+
+    use super::ControlState;
+
+    #[test]
+    fn override_expires() {
+        let now = Instant::now();
+        let mut state = ControlState::new("/etc/amdfand/mapping.toml");
+        assert!(matches!(
+            state.execute(
+                Command::Override {
+                    speed: 100.0,
+                    duration: 60
+                },
+                Some(0),
+                now
+            ),
+            Response::OverrideSet
+        ));
+        assert_eq!(state.override_speed(now), Some(100.0));
+        match state.execute(Command::Status, None, now + Duration::from_secs(20)) {
+            Response::Status(status) => {
+                assert_eq!(status.speed_override.unwrap().remaining_secs, 40)
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(state.override_speed(now + Duration::from_secs(60)), None);
+        assert_eq!(state.override_speed(now), None);
+    }
+
+    #[test]
+    fn only_root_changes_speed() {
+        let now = Instant::now();
+        let mut state = ControlState::new("/etc/amdfand/mapping.toml");
+        let cmd = || Command::Override {
+            speed: 0.0,
+            duration: 60,
+        };
+        assert!(matches!(
+            state.execute(cmd(), Some(1000), now),
+            Response::Rejected(_)
+        ));
+        assert!(matches!(
+            state.execute(cmd(), None, now),
+            Response::Rejected(_)
+        ));
+        assert!(matches!(
+            state.execute(
+                Command::Override {
+                    speed: 120.0,
+                    duration: 60
+                },
+                Some(0),
+                now
+            ),
+            Response::Rejected(_)
+        ));
+        assert_eq!(state.override_speed(now), None);
+        assert!(matches!(
+            state.execute(Command::ClearOverride, Some(0), now),
+            Response::OverrideCleared
+        ));
+    }
+}
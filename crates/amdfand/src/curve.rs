@@ -0,0 +1,430 @@
+//! Non-interactive editing of `temp_matrix` and `usage_matrix`.
+//!
+//! Only base config file is changed, drop-in files and environment overrides
+//! are not written into it.
+
+use std::time::Duration;
+
+use amdgpu::environment::Environment;
+use amdgpu::pidfile::reload::{clear_outcome, wait_for_outcome, ReloadOutcome};
+use amdgpu::pidfile::Pid;
+use amdgpu::utils::{atomic_write, CONFIG_BACKUPS};
+use amdgpu_config::fan::{load_base_config, Config, TempPoint, UsagePoint};
+use gumdrop::Options;
+
+/// How long running service may take to report reload outcome
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, onlyerror::Error)]
+pub enum CurveError {
+    #[error("Curve has no point {index}, it has {len} points")]
+    NoPoint { index: usize, len: usize },
+    #[error("Curve already has point at {0}, use `curve set` to change it")]
+    Duplicate(f64),
+    #[error("Fan speed {0} is not between 0 and 100")]
+    InvalidSpeed(f64),
+    #[error("Either --temp or --usage is required")]
+    MissingPoint,
+    #[error("Unknown matrix {0:?}, expected temp or usage")]
+    UnknownMatrix(String),
+    #[error("Curve must have at least one point")]
+    Empty,
+    #[error("amdfand {0} is not running")]
+    NotRunning(String),
+    #[error("amdfand did not report config reload outcome")]
+    NoReloadOutcome,
+    #[error("amdfand rejected new config. {0}")]
+    Rejected(String),
+}
+
+#[derive(Debug, Options)]
+pub struct CurveCommand {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(command)]
+    command: Option<CurveSubCommand>,
+}
+
+#[derive(Debug, Options)]
+pub enum CurveSubCommand {
+    #[options(help = "Print temperature and usage curves")]
+    List(List),
+    #[options(help = "Add point to curve")]
+    Add(Add),
+    #[options(help = "Remove point from curve")]
+    Remove(Remove),
+    #[options(help = "Change speed of curve point")]
+    Set(Set),
+    #[options(help = "Replace whole curve with points from CSV file")]
+    Replace(Replace),
+}
+
+/// Curve selected with `--matrix`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Matrix {
+    Temp,
+    Usage,
+}
+
+impl std::str::FromStr for Matrix {
+    type Err = CurveError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "temp" => Ok(Self::Temp),
+            "usage" => Ok(Self::Usage),
+            _ => Err(CurveError::UnknownMatrix(s.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Options)]
+pub struct List {
+    #[options(help = "Help message")]
+    help: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct Add {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Temperature of new temperature curve point")]
+    temp: Option<f64>,
+    #[options(help = "Usage of new usage curve point")]
+    usage: Option<f64>,
+    #[options(help = "Fan speed in percent", required)]
+    speed: f64,
+    #[options(help = "Reload config of running service")]
+    reload: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct Remove {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Curve: temp or usage", default = "temp")]
+    matrix: Matrix,
+    #[options(help = "Point index from `curve list`", required)]
+    index: usize,
+    #[options(help = "Reload config of running service")]
+    reload: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct Set {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Curve: temp or usage", default = "temp")]
+    matrix: Matrix,
+    #[options(help = "Point index from `curve list`", required)]
+    index: usize,
+    #[options(help = "Fan speed in percent", required)]
+    speed: f64,
+    #[options(help = "Reload config of running service")]
+    reload: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct Replace {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Curve: temp or usage", default = "temp")]
+    matrix: Matrix,
+    #[options(
+        help = "CSV file with header and 2 columns: temperature (or usage) and speed",
+        required
+    )]
+    from_csv: String,
+    #[options(help = "Reload config of running service")]
+    reload: bool,
+}
+
+/// Point of temperature or usage curve
+pub trait CurvePoint: Copy {
+    fn new(x: f64, speed: f64) -> Self;
+    /// Temperature or usage
+    fn x(&self) -> f64;
+    fn speed_mut(&mut self) -> &mut f64;
+}
+
+impl CurvePoint for TempPoint {
+    fn new(temp: f64, speed: f64) -> Self {
+        TempPoint { temp, speed }
+    }
+
+    fn x(&self) -> f64 {
+        self.temp
+    }
+
+    fn speed_mut(&mut self) -> &mut f64 {
+        &mut self.speed
+    }
+}
+
+impl CurvePoint for UsagePoint {
+    fn new(usage: f64, speed: f64) -> Self {
+        UsagePoint { usage, speed }
+    }
+
+    fn x(&self) -> f64 {
+        self.usage
+    }
+
+    fn speed_mut(&mut self) -> &mut f64 {
+        &mut self.speed
+    }
+}
+
+fn check_speed(speed: f64) -> Result<(), CurveError> {
+    if (0.0..=100.0).contains(&speed) {
+        Ok(())
+    } else {
+        Err(CurveError::InvalidSpeed(speed))
+    }
+}
+
+fn sort<P: CurvePoint>(points: &mut [P]) {
+    points.sort_by(|a, b| a.x().total_cmp(&b.x()));
+}
+
+/// Insert point keeping curve sorted
+pub fn add_point<P: CurvePoint>(points: &mut Vec<P>, x: f64, speed: f64) -> Result<(), CurveError> {
+    check_speed(speed)?;
+    if points.iter().any(|p| p.x() == x) {
+        return Err(CurveError::Duplicate(x));
+    }
+    points.push(P::new(x, speed));
+    sort(points);
+    Ok(())
+}
+
+pub fn remove_point<P: CurvePoint>(points: &mut Vec<P>, index: usize) -> Result<(), CurveError> {
+    if index >= points.len() {
+        return Err(CurveError::NoPoint {
+            index,
+            len: points.len(),
+        });
+    }
+    if points.len() == 1 {
+        return Err(CurveError::Empty);
+    }
+    points.remove(index);
+    Ok(())
+}
+
+pub fn set_speed<P: CurvePoint>(
+    points: &mut [P],
+    index: usize,
+    speed: f64,
+) -> Result<(), CurveError> {
+    check_speed(speed)?;
+    let len = points.len();
+    let point = points
+        .get_mut(index)
+        .ok_or(CurveError::NoPoint { index, len })?;
+    *point.speed_mut() = speed;
+    Ok(())
+}
+
+/// Replace all points with points from CSV. First row is header
+pub fn replace_points<P: CurvePoint, R: std::io::Read>(
+    points: &mut Vec<P>,
+    csv: R,
+) -> crate::Result<()> {
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(csv);
+    let mut new = vec![];
+    for row in reader.deserialize::<(f64, f64)>() {
+        let (x, speed) = row?;
+        add_point(&mut new, x, speed)?;
+    }
+    if new.is_empty() {
+        return Err(CurveError::Empty.into());
+    }
+    *points = new;
+    Ok(())
+}
+
+pub fn run(
+    command: CurveCommand,
+    config: &Config,
+    env: &Environment,
+    name: &str,
+) -> crate::Result<()> {
+    let mut config = load_base_config(config.path())?;
+    let reload = match command.command {
+        None => {
+            println!("{}", CurveCommand::usage());
+            return Ok(());
+        }
+        Some(CurveSubCommand::List(_)) => {
+            print!("{}", format_curves(&config));
+            return Ok(());
+        }
+        Some(CurveSubCommand::Add(add)) => {
+            match (add.temp, add.usage) {
+                (Some(temp), None) => add_point(config.temp_matrix_vec_mut(), temp, add.speed)?,
+                (None, Some(usage)) => add_point(config.usage_matrix_vec_mut(), usage, add.speed)?,
+                _ => return Err(CurveError::MissingPoint.into()),
+            }
+            add.reload
+        }
+        Some(CurveSubCommand::Remove(remove)) => {
+            match remove.matrix {
+                Matrix::Temp => remove_point(config.temp_matrix_vec_mut(), remove.index)?,
+                Matrix::Usage => remove_point(config.usage_matrix_vec_mut(), remove.index)?,
+            }
+            remove.reload
+        }
+        Some(CurveSubCommand::Set(set)) => {
+            match set.matrix {
+                Matrix::Temp => set_speed(config.temp_matrix_mut(), set.index, set.speed)?,
+                Matrix::Usage => set_speed(config.usage_matrix_vec_mut(), set.index, set.speed)?,
+            }
+            set.reload
+        }
+        Some(CurveSubCommand::Replace(replace)) => {
+            let csv = std::fs::File::open(&replace.from_csv)?;
+            match replace.matrix {
+                Matrix::Temp => replace_points(config.temp_matrix_vec_mut(), csv)?,
+                Matrix::Usage => replace_points(config.usage_matrix_vec_mut(), csv)?,
+            }
+            replace.reload
+        }
+    };
+
+    config.validate()?;
+    write_config(&config)?;
+    print!("{}", format_curves(&config));
+    if reload {
+        reload_service(env, name)?;
+        println!("Service reloaded config");
+    }
+    Ok(())
+}
+
+fn format_curves(config: &Config) -> String {
+    let mut buffer = String::from("temp_matrix\n");
+    for (idx, point) in config.temp_matrix().iter().enumerate() {
+        buffer.push_str(&format!(
+            "{:>4}: {:>6.1}°C -> {:>5.1}%\n",
+            idx, point.temp, point.speed
+        ));
+    }
+    buffer.push_str("usage_matrix\n");
+    for (idx, point) in config.usage_matrix().iter().enumerate() {
+        buffer.push_str(&format!(
+            "{:>4}: {:>6.1}%  -> {:>5.1}%\n",
+            idx, point.usage, point.speed
+        ));
+    }
+    buffer
+}
+
+/// Replace config file at once so service never reads partial file
+fn write_config(config: &Config) -> crate::Result<()> {
+    let content = config.to_toml_preserving()?;
+    atomic_write(config.path(), content, CONFIG_BACKUPS)?;
+    Ok(())
+}
+
+/// Send SIGHUP to running service with given pid file name and wait for
+/// outcome
+pub fn reload_service(env: &Environment, name: &str) -> crate::Result<()> {
+    let state_dir = env.fan_state_dir();
+    let pid = std::fs::read_to_string(state_dir.join(format!("{}.pid", name)))
+        .ok()
+        .and_then(|s| s.trim().parse::<i32>().ok())
+        .map(Pid)
+        .ok_or_else(|| CurveError::NotRunning(name.to_string()))?;
+    clear_outcome(&state_dir, pid);
+    if unsafe { nix::libc::kill(*pid, nix::sys::signal::Signal::SIGHUP as i32) } != 0 {
+        return Err(CurveError::NotRunning(name.to_string()).into());
+    }
+    match wait_for_outcome(&state_dir, pid, RELOAD_TIMEOUT) {
+        Some(ReloadOutcome::Reloaded) => Ok(()),
+        Some(ReloadOutcome::Failed(msg)) => Err(CurveError::Rejected(msg).into()),
+        None => Err(CurveError::NoReloadOutcome.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_config::fan::{TempPoint, UsagePoint};
+
+    use super::{add_point, remove_point, replace_points, set_speed, CurveError};
+
+    fn points() -> Vec<TempPoint> {
+        vec![
+            TempPoint {
+                temp: 30.0,
+                speed: 20.0,
+            },
+            TempPoint {
+                temp: 60.0,
+                speed: 60.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn add_keeps_curve_sorted() {
+        let mut points = points();
+        add_point(&mut points, 45.0, 40.0).unwrap();
+        add_point(&mut points, 80.0, 100.0).unwrap();
+        let temps: Vec<f64> = points.iter().map(|p| p.temp).collect();
+        assert_eq!(temps, vec![30.0, 45.0, 60.0, 80.0]);
+
+        assert!(matches!(
+            add_point(&mut points, 45.0, 50.0),
+            Err(CurveError::Duplicate(_))
+        ));
+        assert!(matches!(
+            add_point(&mut points, 90.0, 120.0),
+            Err(CurveError::InvalidSpeed(_))
+        ));
+    }
+
+    #[test]
+    fn remove_and_set() {
+        let mut points = points();
+        set_speed(&mut points, 1, 70.0).unwrap();
+        assert_eq!(points[1].speed, 70.0);
+        assert!(matches!(
+            set_speed(&mut points, 2, 70.0),
+            Err(CurveError::NoPoint { index: 2, len: 2 })
+        ));
+
+        remove_point(&mut points, 0).unwrap();
+        assert_eq!(points.len(), 1);
+        assert!(matches!(
+            remove_point(&mut points, 0),
+            Err(CurveError::Empty)
+        ));
+    }
+
+    #[test]
+    fn replace_from_csv() {
+        let mut points: Vec<UsagePoint> = vec![];
+        let csv = "usage,speed\n60,50\n 20 , 30\n";
+        replace_points(&mut points, csv.as_bytes()).unwrap();
+        assert_eq!(
+            points,
+            vec![
+                UsagePoint {
+                    usage: 20.0,
+                    speed: 30.0
+                },
+                UsagePoint {
+                    usage: 60.0,
+                    speed: 50.0
+                }
+            ]
+        );
+
+        assert!(replace_points(&mut points, "usage,speed\n".as_bytes()).is_err());
+        assert!(replace_points(&mut points, "usage,speed\n10,200\n".as_bytes()).is_err());
+        assert_eq!(points.len(), 2);
+    }
+}
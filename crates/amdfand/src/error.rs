@@ -2,6 +2,7 @@ use amdgpu::{utils, AmdGpuError};
 use amdgpu_config::fan::ConfigError;
 
 use crate::command::FanError;
+use crate::curve::CurveError;
 
 #[derive(Debug, onlyerror::Error)]
 pub enum AmdFanError {
@@ -11,12 +12,22 @@ pub enum AmdFanError {
     NoHwMonFound,
     #[error("No AMD Card has been found in sysfs")]
     NoAmdCardFound,
+    #[error("Failed to serialize config")]
+    ConfigSerialize,
+    #[error("Config backup {0:?} does not exist")]
+    NoBackup(std::path::PathBuf),
     #[error("{0}")]
     AmdGpu(#[from] AmdGpuError),
     #[error("{0}")]
     Fan(#[from] FanError),
     #[error("{0}")]
     Config(#[from] ConfigError),
+    #[error("{0}")]
+    Curve(#[from] CurveError),
+    #[error("Malformed CSV file. {0}")]
+    Csv(#[from] csv::Error),
+    #[error("{0}")]
+    Json(#[from] serde_json::Error),
     #[error("{0:}")]
     Io(#[from] std::io::Error),
     #[error("{0:}")]
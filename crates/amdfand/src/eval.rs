@@ -0,0 +1,327 @@
+//! Preview of fan curves from config without touching GPU.
+
+use amdgpu::pidfile::fan_cmd::SpeedSource;
+use amdgpu_config::fan::{load_config, Config};
+use gumdrop::Options;
+
+use crate::command::{pwm_for_speed, Reading};
+use crate::service::diagnose;
+
+const MAX_TEMP: u32 = 110;
+const MAX_USAGE: u32 = 100;
+const PLOT_HEIGHT: u32 = 20;
+
+#[derive(Debug, Options)]
+pub struct Eval {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Config location, by default config of service is used")]
+    config: Option<String>,
+    #[options(help = "Minimal modulation (pwm1_min)", default = "0")]
+    pwm_min: u32,
+    #[options(help = "Maximal modulation (pwm1_max)", default = "255")]
+    pwm_max: u32,
+    #[options(help = "Distance between table rows", default = "5")]
+    step: u32,
+    #[options(help = "Evaluate single point, ex. temp=65,usage=30")]
+    at: Option<Point>,
+}
+
+/// Sensors state to evaluate
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Point {
+    pub temp: f64,
+    pub usage: f64,
+}
+
+impl std::str::FromStr for Point {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut point = Point::default();
+        for pair in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected key=value, got {:?}", pair))?;
+            let value = value
+                .trim()
+                .parse::<f64>()
+                .map_err(|e| format!("Invalid value of {}. {}", key, e))?;
+            match key.trim() {
+                "temp" => point.temp = value,
+                "usage" => point.usage = value,
+                key => return Err(format!("Unknown key {:?}, expected temp or usage", key)),
+            }
+        }
+        Ok(point)
+    }
+}
+
+/// Curves result for single point
+#[derive(Debug, PartialEq)]
+pub struct Evaluation {
+    pub temp_speed: f64,
+    pub usage_speed: f64,
+    pub speed: f64,
+    pub pwm: u64,
+    /// Curve which gave used speed
+    pub source: SpeedSource,
+}
+
+/// Evaluate curves the same way service does
+pub fn evaluate(config: &Config, point: Point, pwm_min: u32, pwm_max: u32) -> Evaluation {
+    let diagnostic = diagnose(
+        config,
+        &Reading {
+            temp: Some(point.temp),
+            usage: point.usage,
+            sensors: vec![],
+        },
+    );
+    Evaluation {
+        temp_speed: diagnostic.temp_fan_speed,
+        usage_speed: diagnostic.usage_fan_speed,
+        speed: diagnostic.value,
+        pwm: pwm_for_speed(diagnostic.value, pwm_min, pwm_max),
+        source: diagnostic.source(),
+    }
+}
+
+pub fn run(command: Eval, config: Config) -> crate::Result<()> {
+    let config = match command.config.as_deref() {
+        Some(path) => load_config(path)?,
+        None => config,
+    };
+    match command.at {
+        Some(point) => print!(
+            "{}",
+            format_point(&config, point, command.pwm_min, command.pwm_max)
+        ),
+        None => {
+            print!(
+                "{}",
+                format_tables(
+                    &config,
+                    command.step.max(1),
+                    command.pwm_min,
+                    command.pwm_max
+                )
+            );
+            print!("{}", plot(&config));
+        }
+    }
+    Ok(())
+}
+
+fn format_point(config: &Config, point: Point, pwm_min: u32, pwm_max: u32) -> String {
+    let e = evaluate(config, point, pwm_min, pwm_max);
+    format!(
+        "temperature {:.1}°C -> {:.1}%\nusage {:.1}% -> {:.1}%\nspeed {:.1}% ({} curve), pwm {}\n",
+        point.temp,
+        e.temp_speed,
+        point.usage,
+        e.usage_speed,
+        e.speed,
+        if e.source == SpeedSource::Usage {
+            "usage"
+        } else {
+            "temperature"
+        },
+        e.pwm
+    )
+}
+
+/// Speed for each temperature (rows) and usage (columns). Cells where usage
+/// curve dominates are marked with `*`
+fn format_tables(config: &Config, step: u32, pwm_min: u32, pwm_max: u32) -> String {
+    let mut buffer = String::from("Temperature curve\n  temp   speed  pwm\n");
+    for temp in (0..=MAX_TEMP).step_by(step as usize) {
+        let speed = config.fan_speed_for_temp(temp as f64);
+        buffer.push_str(&format!(
+            "{:>4}°C {:>6.1}% {:>4}\n",
+            temp,
+            speed,
+            pwm_for_speed(speed, pwm_min, pwm_max)
+        ));
+    }
+
+    buffer.push_str("\nUsage curve\n usage   speed  pwm\n");
+    for usage in (0..=MAX_USAGE).step_by(step as usize) {
+        let speed = config.fan_speed_for_usage(usage as f64);
+        buffer.push_str(&format!(
+            "{:>5}% {:>6.1}% {:>4}\n",
+            usage,
+            speed,
+            pwm_for_speed(speed, pwm_min, pwm_max)
+        ));
+    }
+
+    let usages: Vec<u32> = (0..=MAX_USAGE).step_by((step * 2) as usize).collect();
+    buffer.push_str("\nEffective speed (* usage curve dominates)\ntemp\\usage");
+    for usage in usages.iter() {
+        buffer.push_str(&format!("{:>7}", format!("{}%", usage)));
+    }
+    buffer.push('\n');
+    for temp in (0..=MAX_TEMP).step_by((step * 2) as usize) {
+        buffer.push_str(&format!("{:>8}°C", temp));
+        for usage in usages.iter() {
+            let point = Point {
+                temp: temp as f64,
+                usage: *usage as f64,
+            };
+            let e = evaluate(config, point, pwm_min, pwm_max);
+            let mark = if e.source == SpeedSource::Usage {
+                "*"
+            } else {
+                " "
+            };
+            buffer.push_str(&format!("{:>6.1}{}", e.speed, mark));
+        }
+        buffer.push('\n');
+    }
+    buffer
+}
+
+/// ASCII plot of both curves, `T` temperature curve (x in °C), `U` usage
+/// curve (x in %), `#` both
+fn plot(config: &Config) -> String {
+    let row = |speed: f64| -> u32 {
+        ((speed.clamp(0.0, 100.0) / 100.0) * PLOT_HEIGHT as f64).round() as u32
+    };
+    // row of each curve for every second degree / percent
+    let columns: Vec<(u32, Option<u32>)> = (0..=MAX_TEMP)
+        .step_by(2)
+        .map(|x| {
+            let usage = (x <= MAX_USAGE).then(|| row(config.fan_speed_for_usage(x as f64)));
+            (row(config.fan_speed_for_temp(x as f64)), usage)
+        })
+        .collect();
+    let width = columns.len();
+
+    let mut buffer = String::from("\nspeed (T temperature °C, U usage %)\n");
+    for idx in (0..=PLOT_HEIGHT).rev() {
+        let label = if idx % 5 == 0 {
+            format!("{:>4}%", idx * 100 / PLOT_HEIGHT)
+        } else {
+            String::from("     ")
+        };
+        let line: String = columns
+            .iter()
+            .map(|(temp, usage)| match (*temp == idx, *usage == Some(idx)) {
+                (true, true) => '#',
+                (true, false) => 'T',
+                (false, true) => 'U',
+                (false, false) => ' ',
+            })
+            .collect();
+        buffer.push_str(&format!("{} |{}\n", label, line.trim_end()));
+    }
+    buffer.push_str(&format!("      +{}\n", "-".repeat(width)));
+    let mut axis = String::from("       ");
+    for x in (0..=MAX_TEMP).step_by(20) {
+        axis.push_str(&format!("{:<10}", x));
+    }
+    buffer.push_str(axis.trim_end());
+    buffer.push('\n');
+    buffer
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu::pidfile::fan_cmd::SpeedSource;
+    use amdgpu_config::fan::Config;
+
+    use super::{evaluate, format_point, plot, Point};
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+log_level = 'Error'
+
+[[temp_matrix]]
+temp = 30.0
+speed = 0.0
+
+[[temp_matrix]]
+temp = 80.0
+speed = 100.0
+
+[[usage_matrix]]
+usage = 0.0
+speed = 0.0
+
+[[usage_matrix]]
+usage = 100.0
+speed = 50.0
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_point() {
+        assert_eq!(
+            "temp=65,usage=30".parse::<Point>(),
+            Ok(Point {
+                temp: 65.0,
+                usage: 30.0
+            })
+        );
+        assert_eq!(
+            "usage=10".parse::<Point>(),
+            Ok(Point {
+                temp: 0.0,
+                usage: 10.0
+            })
+        );
+        assert!("temp=hot".parse::<Point>().is_err());
+        assert!("fan=1".parse::<Point>().is_err());
+    }
+
+    #[test]
+    fn dominant_curve() {
+        let config = config();
+        let hot = evaluate(
+            &config,
+            Point {
+                temp: 55.0,
+                usage: 40.0,
+            },
+            0,
+            255,
+        );
+        assert_eq!(hot.speed, 50.0);
+        assert_eq!(hot.source, SpeedSource::Temperature);
+        assert_eq!(hot.pwm, 127);
+
+        let busy = evaluate(
+            &config,
+            Point {
+                temp: 30.0,
+                usage: 80.0,
+            },
+            50,
+            250,
+        );
+        assert_eq!(busy.speed, 40.0);
+        assert_eq!(busy.source, SpeedSource::Usage);
+        assert_eq!(busy.pwm, 130);
+
+        assert_eq!(
+            format_point(&config, Point { temp: 30.0, usage: 80.0 }, 50, 250),
+            "temperature 30.0°C -> 0.0%\nusage 80.0% -> 40.0%\nspeed 40.0% (usage curve), pwm 130\n"
+        );
+    }
+
+    #[test]
+    fn plot_curves() {
+        let plot = plot(&config());
+        let lines: Vec<&str> = plot.lines().collect();
+        // both curves start at 0%
+        let bottom = lines.iter().find(|l| l.starts_with("   0%")).unwrap();
+        assert!(bottom.contains('#'));
+        // full speed from 80°C
+        let top = lines.iter().find(|l| l.starts_with(" 100%")).unwrap();
+        assert_eq!(top.matches('T').count(), (110 - 80) / 2 + 1);
+    }
+}
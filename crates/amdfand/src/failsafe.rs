@@ -0,0 +1,173 @@
+//! Safety layer on top of fan curves. Fan is forced to full speed when GPU is
+//! close to its critical temperature and when temperature can't be read for
+//! too long.
+
+use amdgpu_config::fan::{Failsafe, SensorFailureAction};
+use tracing::{error, info, warn};
+
+use crate::command::Reading;
+
+/// Decision made for single tick
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Use fan curves for given temperature
+    Curve(f64),
+    /// Keep last written speed
+    Hold,
+    /// Force 100% fan speed
+    FullSpeed,
+    /// Give control back to GPU firmware
+    Firmware,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum State {
+    #[default]
+    Normal,
+    Critical,
+    SensorFailure,
+}
+
+#[derive(Debug, Default)]
+pub struct FailsafeState {
+    state: State,
+    /// Consecutive ticks without readable temperature
+    failed_reads: u32,
+}
+
+impl FailsafeState {
+    pub fn check(&mut self, config: &Failsafe, reading: &Reading) -> Action {
+        let critical = reading
+            .sensors
+            .iter()
+            .find(|sensor| match (sensor.temp, sensor.crit) {
+                (Some(temp), Some(crit)) => temp >= crit - config.crit_margin,
+                _ => false,
+            });
+        if let Some(sensor) = critical {
+            if self.state != State::Critical {
+                error!(
+                    "FAILSAFE: {} reached {:.1}°C which is within {:.1}°C of critical {:.1}°C. Forcing full fan speed!",
+                    sensor.name,
+                    sensor.temp.unwrap_or_default(),
+                    config.crit_margin,
+                    sensor.crit.unwrap_or_default(),
+                );
+            }
+            self.state = State::Critical;
+            if reading.temp.is_some() {
+                self.failed_reads = 0;
+            }
+            return Action::FullSpeed;
+        }
+
+        match reading.temp {
+            Some(temp) => {
+                if self.state != State::Normal {
+                    info!("FAILSAFE: temperature is back to normal ({:.1}°C)", temp);
+                }
+                self.state = State::Normal;
+                self.failed_reads = 0;
+                Action::Curve(temp)
+            }
+            None => {
+                self.failed_reads = self.failed_reads.saturating_add(1);
+                if self.failed_reads < config.max_read_failures.max(1) {
+                    warn!(
+                        "Unable to read GPU temperature ({}/{}), keeping last fan speed",
+                        self.failed_reads, config.max_read_failures
+                    );
+                    return Action::Hold;
+                }
+                let action = match config.on_sensor_failure {
+                    SensorFailureAction::FullSpeed => Action::FullSpeed,
+                    SensorFailureAction::Firmware => Action::Firmware,
+                };
+                if self.state != State::SensorFailure {
+                    error!(
+                        "FAILSAFE: GPU temperature can't be read for {} consecutive checks! {}",
+                        self.failed_reads,
+                        match action {
+                            Action::Firmware => "Giving fan control back to GPU firmware",
+                            _ => "Forcing full fan speed",
+                        }
+                    );
+                }
+                self.state = State::SensorFailure;
+                action
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_config::fan::{Failsafe, SensorFailureAction};
+
+    use super::{Action, FailsafeState};
+    use crate::command::{Reading, Sensor};
+
+    fn reading(temp: Option<f64>, crit: Option<f64>) -> Reading {
+        Reading {
+            temp,
+            usage: 0.0,
+            sensors: vec![Sensor {
+                name: "temp1_input".into(),
+                temp,
+                crit,
+            }],
+        }
+    }
+
+    #[test]
+    fn close_to_critical() {
+        let config = Failsafe::default();
+        let mut state = FailsafeState::default();
+        assert_eq!(
+            state.check(&config, &reading(Some(80.0), Some(100.0))),
+            Action::Curve(80.0)
+        );
+        assert_eq!(
+            state.check(&config, &reading(Some(95.0), Some(100.0))),
+            Action::FullSpeed
+        );
+        assert_eq!(
+            state.check(&config, &reading(Some(90.0), Some(100.0))),
+            Action::Curve(90.0)
+        );
+    }
+
+    #[test]
+    fn unreadable_sensor() {
+        let config = Failsafe {
+            max_read_failures: 2,
+            ..Default::default()
+        };
+        let mut state = FailsafeState::default();
+        assert_eq!(state.check(&config, &reading(None, None)), Action::Hold);
+        assert_eq!(
+            state.check(&config, &reading(None, None)),
+            Action::FullSpeed
+        );
+        assert_eq!(
+            state.check(&config, &reading(None, None)),
+            Action::FullSpeed
+        );
+        assert_eq!(
+            state.check(&config, &reading(Some(40.0), None)),
+            Action::Curve(40.0)
+        );
+        assert_eq!(state.check(&config, &reading(None, None)), Action::Hold);
+    }
+
+    #[test]
+    fn unreadable_sensor_firmware() {
+        let config = Failsafe {
+            max_read_failures: 1,
+            on_sensor_failure: SensorFailureAction::Firmware,
+            ..Default::default()
+        };
+        let mut state = FailsafeState::default();
+        assert_eq!(state.check(&config, &reading(None, None)), Action::Firmware);
+    }
+}
@@ -1,6 +1,7 @@
+use amdgpu::environment::{Environment, Overrides};
 use amdgpu::lock_file::PidLock;
 use amdgpu::utils::{ensure_config_dir, hw_mons};
-use amdgpu_config::fan::{load_config, Config, DEFAULT_FAN_CONFIG_PATH};
+use amdgpu_config::fan::{load_config, Config, FAN_CONFIG_FILE};
 use gumdrop::Options;
 use tracing::level_filters::LevelFilter;
 use tracing::{error, warn};
@@ -11,9 +12,19 @@ use crate::error::AmdFanError;
 
 mod change_mode;
 mod command;
+mod config;
+mod control;
+mod curve;
 mod error;
+mod eval;
+mod failsafe;
 mod panic_handler;
 mod service;
+mod simulate;
+mod stall;
+mod suspend;
+mod takeover;
+mod watcher;
 
 #[cfg(feature = "static")]
 extern crate eyra;
@@ -37,13 +48,45 @@ pub struct Opts {
         help = "Pid file name (exp. card1). This should not be path, only file name without extension"
     )]
     pid_file: Option<String>,
+    #[options(
+        no_short,
+        help = "sysfs mount point, by default /sys (env AMDGPU_SYSFS_ROOT)"
+    )]
+    sysfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "procfs mount point, by default /proc (env AMDGPU_PROCFS_ROOT)"
+    )]
+    procfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "Services state directory, by default /var/lib (env AMDGPU_STATE_DIR)"
+    )]
+    state_dir: Option<String>,
+    #[options(
+        no_short,
+        help = "Config directory, by default /etc/amdfand (env AMDGPU_CONFIG_DIR)"
+    )]
+    config_dir: Option<String>,
     #[options(command)]
     command: Option<command::FanCommand>,
 }
 
+impl Opts {
+    fn environment(&self) -> Environment {
+        Environment::from_env().with_overrides(Overrides {
+            sysfs_root: self.sysfs_root.clone(),
+            procfs_root: self.procfs_root.clone(),
+            state_dir: self.state_dir.clone(),
+            config_dir: self.config_dir.clone(),
+            ..Default::default()
+        })
+    }
+}
+
 static DEFAULT_PID_FILE_NAME: &str = "amdfand";
 
-fn run(config: Config) -> Result<()> {
+fn run(env: &Environment, config: Config) -> Result<()> {
     let opts: Opts = Opts::parse_args_default_or_exit();
 
     if opts.version {
@@ -55,18 +98,22 @@ fn run(config: Config) -> Result<()> {
         warn!("cards config field is no longer supported");
     }
 
+    let name = opts
+        .pid_file
+        .clone()
+        .unwrap_or_else(|| String::from(DEFAULT_PID_FILE_NAME));
     match opts.command {
-        None => run_service(config, opts),
-        Some(FanCommand::Service(_)) => run_service(config, opts),
+        None => run_service(env, config, opts),
+        Some(FanCommand::Service(_)) => run_service(env, config, opts),
         Some(FanCommand::SetAutomatic(switcher)) => {
-            change_mode::run(switcher, FanMode::Automatic, config)
+            change_mode::run(switcher, FanMode::Automatic, env, config)
         }
         Some(FanCommand::SetManual(switcher)) => {
-            change_mode::run(switcher, FanMode::Manual, config)
+            change_mode::run(switcher, FanMode::Manual, env, config)
         }
         Some(FanCommand::Available(_)) => {
             println!("Available cards");
-            hw_mons(false)?.into_iter().for_each(|hw_mon| {
+            hw_mons(env, false)?.into_iter().for_each(|hw_mon| {
                 println!(
                     " * {:6>} - {}",
                     hw_mon.card(),
@@ -75,50 +122,60 @@ fn run(config: Config) -> Result<()> {
             });
             Ok(())
         }
+        Some(FanCommand::Config(command)) => config::run(command, &config, env, &name),
+        Some(FanCommand::Status(command)) => control::status(command, env, &name),
+        Some(FanCommand::Override(command)) => control::set_override(command, env, &name),
+        Some(FanCommand::ClearOverride(command)) => control::clear_override(command, env, &name),
+        Some(FanCommand::Simulate(command)) => simulate::run(command, config),
+        Some(FanCommand::Eval(command)) => eval::run(command, config),
+        Some(FanCommand::Curve(command)) => curve::run(command, &config, env, &name),
     }
 }
 
-fn run_service(config: Config, opts: Opts) -> Result<()> {
-    let mut pid_file = PidLock::new(
-        "amdfand",
-        opts.pid_file
-            .unwrap_or_else(|| String::from(DEFAULT_PID_FILE_NAME)),
-    )?;
+fn run_service(env: &Environment, config: Config, opts: Opts) -> Result<()> {
+    let name = opts
+        .pid_file
+        .unwrap_or_else(|| String::from(DEFAULT_PID_FILE_NAME));
+    let mut pid_file = PidLock::new(env, "amdfand", name.clone())?;
     pid_file.acquire()?;
-    let res = service::run(config);
+    let res = service::run(config, env, &name);
     pid_file.release()?;
     res
 }
 
-fn setup() -> Result<(String, Config)> {
+fn setup() -> Result<(Environment, Config)> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "DEBUG");
     }
-    ensure_config_dir()?;
+    let opts = Opts::parse_args_default_or_exit();
+    let env = opts.environment();
+    ensure_config_dir(&env)?;
 
-    let config_path = Opts::parse_args_default_or_exit()
-        .config
-        .unwrap_or_else(|| DEFAULT_FAN_CONFIG_PATH.to_string());
+    let config_path = opts.config.unwrap_or_else(|| {
+        env.config_file(FAN_CONFIG_FILE)
+            .to_string_lossy()
+            .into_owned()
+    });
     let config = load_config(&config_path)?;
     tracing_subscriber::fmt::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .with_max_level(config.log_level().as_str().parse::<LevelFilter>().unwrap())
         .init();
-    Ok((config_path, config))
+    Ok((env, config))
 }
 
 fn main() -> Result<()> {
-    let (_config_path, config) = match setup() {
+    let (env, config) = match setup() {
         Ok(config) => config,
         Err(e) => {
             error!("{}", e);
             std::process::exit(1);
         }
     };
-    match run(config) {
+    match run(&env, config) {
         Ok(()) => Ok(()),
         Err(e) => {
-            panic_handler::restore_automatic();
+            panic_handler::restore_automatic(&env);
             error!("{}", e);
             std::process::exit(1);
         }
@@ -1,10 +1,14 @@
+use std::path::PathBuf;
+
+use amdgpu::environment::Environment;
+use amdgpu::pidfile::cards::{remove_cards, restore_firmware};
 use amdgpu::utils::hw_mons;
 use tracing::error;
 
 use crate::command::Fan;
 
-pub fn restore_automatic() {
-    for hw in hw_mons(true).unwrap_or_default() {
+pub fn restore_automatic(env: &Environment) {
+    for hw in hw_mons(env, true).unwrap_or_default() {
         if let Err(error) = (Fan {
             hw_mon: hw,
             temp_inputs: vec![],
@@ -18,3 +22,17 @@ pub fn restore_automatic() {
         }
     }
 }
+
+/// Give controlled fans back to firmware when service panics. State of service
+/// can't be trusted after panic so process exits and service manager should
+/// restart it
+pub fn install(state_dir: PathBuf, name: String, hw_mons: Vec<PathBuf>) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        error!("amdfand panicked, giving fan control back to firmware");
+        restore_firmware(&hw_mons);
+        remove_cards(&state_dir, &name);
+        std::process::exit(101);
+    }));
+}
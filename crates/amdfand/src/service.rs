@@ -1,97 +1,585 @@
 use std::fmt::Formatter;
-
-use amdgpu::utils::hw_mons;
-use amdgpu::{config_reloaded, is_reload_required, listen_unix_signal};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use amdgpu::environment::Environment;
+use amdgpu::fan_health::{remove_health, write_health, FanHealth, FanHealthReport};
+use amdgpu::pidfile::cards::{remove_cards, write_cards, ControlledCards};
+use amdgpu::pidfile::fan_cmd::{sock_file, FanStatus, SensorStatus, SpeedSource};
+use amdgpu::pidfile::reload::{clear_outcome, write_outcome, ReloadOutcome};
+use amdgpu::pidfile::Pid;
+use amdgpu::procfs::file_holders;
+use amdgpu::sd_notify::Notifier;
+use amdgpu::utils::{hw_mons, linear_map};
+use amdgpu::{
+    config_reloaded, is_reload_required, is_stop_requested, listen_stop_signal, listen_unix_signal,
+    request_reload, PULSE_WIDTH_MODULATION, PULSE_WIDTH_MODULATION_MODE,
+};
 use amdgpu_config::fan::Config;
 use gumdrop::Options;
-use tracing::{debug, error, info};
-
-use crate::command::Fan;
+use tracing::{debug, error, info, warn};
+
+use crate::command::{Fan, Reading};
+use crate::control::{self, ControlState};
+use crate::failsafe::{Action, FailsafeState};
+use crate::stall::{Sample, StallDetector, Verdict};
+use crate::suspend::SleepDetector;
+use crate::takeover::{self, Observed, TakeoverGuard};
+use crate::watcher::ConfigWatcher;
 use crate::AmdFanError;
 
+/// Fan managed by service with state kept between ticks
+pub struct Controlled<Root: amdgpu::hw_mon::RootPath> {
+    pub fan: Fan<Root>,
+    /// Last written speed, `None` forces next write
+    last_speed: Option<f64>,
+    /// Last written modulation, `None` if fan is not controlled by service
+    last_pwm: Option<u64>,
+    failsafe: FailsafeState,
+    stall: StallDetector,
+    takeover: TakeoverGuard,
+    /// Fan was handed over to GPU firmware by failsafe or stall detection
+    firmware: bool,
+    /// Directory where fan health is published
+    health_dir: Option<PathBuf>,
+    /// Used to find processes which took over fan
+    proc_dir: PathBuf,
+    /// Speed forced through control socket
+    speed_override: Option<f64>,
+    /// Last sensors reading and curves result
+    last_reading: Option<(Reading, Diagnostic)>,
+    source: SpeedSource,
+    /// Speed service wants to have, `None` if fan is not controlled
+    target_speed: Option<f64>,
+}
+
+impl<Root: amdgpu::hw_mon::RootPath> Controlled<Root> {
+    pub fn new(fan: Fan<Root>) -> Self {
+        Self {
+            fan,
+            last_speed: None,
+            last_pwm: None,
+            failsafe: FailsafeState::default(),
+            stall: StallDetector::default(),
+            takeover: TakeoverGuard::default(),
+            firmware: false,
+            health_dir: None,
+            proc_dir: Environment::default().procfs_root,
+            speed_override: None,
+            last_reading: None,
+            source: SpeedSource::None,
+            target_speed: None,
+        }
+    }
+
+    /// Snapshot of fan state for control socket
+    pub fn status(&mut self) -> FanStatus {
+        let (reading, diagnostic) = self.last_reading.clone().unwrap_or_default();
+        let target_pwm = self.target_speed.map(|speed| self.fan.speed_to_pwm(speed));
+        FanStatus {
+            card: *self.fan.card(),
+            sensors: reading
+                .sensors
+                .into_iter()
+                .map(|sensor| SensorStatus {
+                    name: sensor.name,
+                    temp: sensor.temp,
+                    crit: sensor.crit,
+                })
+                .collect(),
+            temp: reading.temp,
+            usage: reading.usage,
+            temp_speed: diagnostic.temp_fan_speed,
+            usage_speed: diagnostic.usage_fan_speed,
+            source: self.source,
+            target_speed: self.target_speed,
+            target_pwm,
+            written_pwm: self
+                .fan
+                .hw_mon_read(PULSE_WIDTH_MODULATION)
+                .ok()
+                .and_then(|s| s.parse().ok()),
+            health: self.stall.health(),
+        }
+    }
+
+    /// Publish fan health in given directory each time it changes. Current
+    /// health is published immediately so report left by previous run is
+    /// replaced
+    pub fn with_health_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.health_dir = Some(dir.into());
+        let pwm = self
+            .fan
+            .hw_mon_read(PULSE_WIDTH_MODULATION)
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .unwrap_or_default();
+        let sample = self.sample(pwm);
+        self.publish_health(&sample);
+        self
+    }
+
+    /// Remove published fan health, fan is no longer controlled by service
+    pub fn remove_health(&self) {
+        if let Some(dir) = self.health_dir.as_ref() {
+            remove_health(dir, self.fan.card());
+        }
+    }
+
+    /// Look for processes which took over fan in given procfs
+    pub fn with_proc_dir<P: Into<PathBuf>>(mut self, dir: P) -> Self {
+        self.proc_dir = dir.into();
+        self
+    }
+
+    /// Check if fan spins as expected for last written modulation
+    fn check_stall(&mut self, config: &Config) -> Verdict {
+        if self.stall.health() == FanHealth::Stalled {
+            return Verdict::Stalled;
+        }
+        let pwm = match self.last_pwm {
+            Some(pwm) => pwm,
+            None => return Verdict::Ok,
+        };
+        let sample = self.sample(pwm);
+        let before = self.stall.health();
+        let verdict = self.stall.check(config.stall(), self.fan.card(), &sample);
+        if before != self.stall.health() {
+            self.publish_health(&sample);
+        }
+        verdict
+    }
+
+    fn sample(&mut self, pwm: u64) -> Sample {
+        Sample {
+            pwm,
+            speed: linear_map(
+                pwm as f64,
+                self.fan.pwm_min() as f64,
+                self.fan.pwm_max() as f64,
+                0f64,
+                100f64,
+            ),
+            rpm: self.fan.rpm(),
+            expected_rpm: self.fan.expected_rpm(pwm),
+        }
+    }
+
+    fn publish_health(&self, sample: &Sample) {
+        let dir = match self.health_dir.as_ref() {
+            Some(dir) => dir,
+            None => return,
+        };
+        let report = FanHealthReport {
+            card: *self.fan.card(),
+            health: self.stall.health(),
+            pwm: sample.pwm,
+            rpm: sample.rpm,
+            expected_rpm: sample.expected_rpm,
+        };
+        if let Err(e) = write_health(dir, &report) {
+            warn!("Failed to publish fan health of {}. {:?}", report.card, e);
+        }
+    }
+
+    /// Check if fan mode and modulation were not changed by someone else
+    fn verify_ownership(&mut self, config: &Config, pwm: u64) -> takeover::Verdict {
+        let observed = Observed {
+            mode: self.fan.hw_mon_read(PULSE_WIDTH_MODULATION_MODE).ok(),
+            pwm: self
+                .fan
+                .hw_mon_read(PULSE_WIDTH_MODULATION)
+                .ok()
+                .and_then(|s| s.parse().ok()),
+        };
+        let mon_dir = self.fan.mon_dir();
+        let proc_dir = &self.proc_dir;
+        let verdict =
+            self.takeover
+                .verify(config.takeover(), self.fan.card(), pwm, &observed, || {
+                    file_holders(proc_dir, mon_dir)
+                });
+        if verdict != takeover::Verdict::Ok {
+            // speed must be written again once fan can be touched
+            self.last_speed = None;
+            self.last_pwm = None;
+        }
+        verdict
+    }
+
+    /// Initialize fan again after GPU driver reset (ex. after resume).
+    /// Modulation range is read again, manual mode is restored and speed will
+    /// be written in next tick
+    pub fn reinitialize(&mut self) {
+        self.fan.pwm_min = None;
+        self.fan.pwm_max = None;
+        self.last_speed = None;
+        self.last_pwm = None;
+        self.takeover = TakeoverGuard::default();
+        let result = if self.firmware {
+            self.fan.write_automatic()
+        } else {
+            self.fan.write_manual()
+        };
+        if let Err(e) = result {
+            error!(
+                "Failed to restore fan mode of {} after resume. {:?}",
+                self.fan.card(),
+                e
+            );
+        }
+    }
+
+    /// Give control back to GPU firmware if it's not already there
+    fn hand_over_to_firmware(&mut self) {
+        if !self.firmware {
+            if let Err(e) = self.fan.write_automatic() {
+                error!("Failed to give fan control back to firmware. {:?}", e);
+            }
+            self.firmware = true;
+            self.last_speed = None;
+            self.last_pwm = None;
+        }
+        self.source = SpeedSource::Firmware;
+        self.target_speed = None;
+    }
+}
+
 /// Start service which will change fan speed according to config and GPU
 /// temperature
-pub fn run(mut config: Config) -> crate::Result<()> {
+pub fn run(mut config: Config, env: &Environment, name: &str) -> crate::Result<()> {
     listen_unix_signal();
+    listen_stop_signal();
+    let notifier = Notifier::from_env();
+    let state_dir = env.fan_state_dir();
+
+    let mut fans = Fan::wrap_all(hw_mons(env, true)?, &config)
+        .into_iter()
+        .map(|fan| {
+            Controlled::new(fan)
+                .with_health_dir(&state_dir)
+                .with_proc_dir(&env.procfs_root)
+        })
+        .collect::<Vec<_>>();
 
-    let mut hw_mons = Fan::wrap_all(hw_mons(true)?, &config);
-
-    if hw_mons.is_empty() {
+    if fans.is_empty() {
         return Err(AmdFanError::NoHwMonFound);
     }
-    hw_mons.iter().for_each(|fan| {
-        if let Err(e) = fan.write_manual() {
+    let hw_mons: Vec<PathBuf> = fans.iter().map(|c| c.fan.hw_mon.mon_dir()).collect();
+    let cards = ControlledCards {
+        pid: Pid(std::process::id() as i32),
+        hw_mons: hw_mons.clone(),
+    };
+    if let Err(e) = write_cards(&state_dir, name, &cards) {
+        warn!(
+            "Failed to save controlled cards, supervisor won't restore them. {:?}",
+            e
+        );
+    }
+    crate::panic_handler::install(state_dir.clone(), name.to_string(), hw_mons);
+
+    fans.iter().for_each(|controlled| {
+        if let Err(e) = controlled.fan.write_manual() {
             debug!(
                 "Failed to switch to manual fan manipulation for fan {:?}. {:?}",
-                fan.hw_mon, e
+                controlled.fan.hw_mon, e
             );
         }
     });
 
-    let mut cache = std::collections::HashMap::new();
-    loop {
+    if let Err(e) = notifier.ready(&format!("Controlling {} fan(s)", fans.len())) {
+        warn!("Failed to notify service manager. {:?}", e);
+    }
+    check_watchdog(&notifier, &config);
+
+    let control = Arc::new(Mutex::new(ControlState::new(config.path())));
+    if let Err(e) = control::serve(&state_dir, name, control.clone()) {
+        warn!("Failed to start control socket. {:?}", e);
+    }
+
+    let mut watcher = None;
+    sync_watcher(&config, &mut watcher);
+    let mut sleep = SleepDetector::default();
+    #[cfg(feature = "logind")]
+    crate::suspend::listen_logind();
+
+    while !is_stop_requested() {
+        if sleep.resumed() {
+            info!("Resumed from sleep, initializing fans again");
+            fans.iter_mut().for_each(Controlled::reinitialize);
+        }
         if is_reload_required() {
-            info!("Reloading config...");
-            config = config.reload()?;
-            info!("  config reloaded");
+            let outcome = reload(&mut config);
+            if outcome == ReloadOutcome::Reloaded {
+                // stalled fans get another chance after reload
+                fans.iter_mut()
+                    .for_each(|controlled| controlled.stall = StallDetector::default());
+                sync_watcher(&config, &mut watcher);
+                check_watchdog(&notifier, &config);
+            }
+            let pid = Pid(std::process::id() as i32);
+            if let Err(e) = write_outcome(&state_dir, pid, &outcome) {
+                warn!("Failed to publish config reload outcome. {:?}", e);
+            }
             config_reloaded();
         }
-        for hw_mon in hw_mons.iter_mut() {
-            let speed = highest_speed(&config, hw_mon);
-            let last = *cache.entry(**hw_mon.card()).or_insert(-1_f64);
-            if (last - speed).abs() < 0.001f64 {
-                continue;
-            }
-            debug!("Changing speed to {speed:0.2}");
+        let speed_override = control
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .override_speed(Instant::now());
+        let mut alive = false;
+        for controlled in fans.iter_mut() {
+            controlled.speed_override = speed_override;
+            alive |= tick(&config, controlled);
+        }
+        let statuses: Vec<FanStatus> = fans.iter_mut().map(Controlled::status).collect();
+        // watchdog is not reset when no fan could be controlled so service
+        // manager can restart service
+        let notified = if alive {
+            notifier.watchdog(&sd_status(&statuses))
+        } else {
+            notifier.status(&sd_status(&statuses))
+        };
+        if let Err(e) = notified {
+            warn!("Failed to notify service manager. {:?}", e);
+        }
+        control
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .update(config.path(), statuses);
+        wait(Duration::from_millis(config.update_rate()));
+    }
 
-            if let Err(e) = hw_mon.set_speed(speed) {
-                error!("Failed to change speed to {}. {:?}", speed, e);
-            }
-            cache.insert(**hw_mon.card(), speed);
+    info!("Stopping, giving fan control back to firmware");
+    let _ = notifier.stopping();
+    for controlled in fans.iter_mut() {
+        controlled.hand_over_to_firmware();
+        controlled.remove_health();
+    }
+    let _ = std::fs::remove_file(sock_file(&state_dir, name));
+    remove_cards(&state_dir, name);
+    clear_outcome(&state_dir, Pid(std::process::id() as i32));
+    Ok(())
+}
+
+/// Sleep until next tick unless service is stopped
+fn wait(duration: Duration) {
+    const STEP: Duration = Duration::from_millis(100);
+    let start = Instant::now();
+    while !is_stop_requested() {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            break;
         }
-        std::thread::sleep(std::time::Duration::from_millis(config.update_rate()));
+        std::thread::sleep(STEP.min(duration - elapsed));
     }
 }
 
-fn highest_speed<Root: amdgpu::hw_mon::RootPath>(config: &Config, hw_mon: &Fan<Root>) -> f64 {
-    let gpu_temp = config
-        .temp_input()
-        .and_then(|input| {
-            hw_mon
-                .read_gpu_temp(&input.as_string())
-                .map(|temp| temp as f64 / 1000f64)
-                .ok()
+/// Warn when ticks are too rare for watchdog configured in service unit
+fn check_watchdog(notifier: &Notifier, config: &Config) {
+    if let Some(timeout) = notifier.watchdog_timeout() {
+        let update_rate = Duration::from_millis(config.update_rate());
+        if update_rate * 2 > timeout {
+            warn!(
+                "update_rate {}ms is too long for watchdog timeout {}ms, service may be restarted",
+                update_rate.as_millis(),
+                timeout.as_millis()
+            );
+        }
+    }
+}
+
+/// Status line reported to service manager, ex. `card0 45.0°C 50.0%
+/// (Temperature)`
+fn sd_status(statuses: &[FanStatus]) -> String {
+    statuses
+        .iter()
+        .map(|fan| {
+            format!(
+                "{} {} {} ({:?})",
+                fan.card,
+                fan.temp
+                    .map_or_else(|| String::from("-"), |t| format!("{:.1}°C", t)),
+                fan.target_speed
+                    .map_or_else(|| String::from("-"), |s| format!("{:.1}%", s)),
+                fan.source
+            )
         })
-        .or_else(|| hw_mon.max_gpu_temp().ok())
-        .unwrap_or_default();
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Start or pause config file watcher depending on `watch_config`
+fn sync_watcher(config: &Config, watcher: &mut Option<ConfigWatcher>) {
+    match watcher {
+        Some(watcher) => watcher.set_enabled(config.watch_config()),
+        None if config.watch_config() => {
+            match ConfigWatcher::start(config.path(), request_reload) {
+                Ok(started) => {
+                    info!("Watching {} for changes", config.path());
+                    *watcher = Some(started);
+                }
+                Err(e) => warn!("Failed to watch config file {}. {}", config.path(), e),
+            }
+        }
+        None => {}
+    }
+}
+
+/// Replace config with new one only if it's valid, otherwise previous config
+/// is kept
+fn reload(config: &mut Config) -> ReloadOutcome {
+    info!("Reloading config...");
+    match config.reload() {
+        Ok(new) => {
+            *config = new;
+            info!("  config reloaded");
+            ReloadOutcome::Reloaded
+        }
+        Err(e) => {
+            error!(
+                "Failed to reload config, previous config is still used. {}",
+                e
+            );
+            ReloadOutcome::Failed(e.to_string())
+        }
+    }
+}
+
+/// Read sensors and change fan speed if needed. Returns `false` when sensors
+/// could not be read or fan speed could not be written
+pub(crate) fn tick<Root: amdgpu::hw_mon::RootPath>(
+    config: &Config,
+    controlled: &mut Controlled<Root>,
+) -> bool {
+    if controlled.takeover.backing_off() {
+        controlled.source = SpeedSource::BackOff;
+        controlled.target_speed = None;
+        return true;
+    }
+    if let Some(pwm) = controlled.last_pwm {
+        if controlled.verify_ownership(config, pwm) == takeover::Verdict::BackOff {
+            controlled.source = SpeedSource::BackOff;
+            controlled.target_speed = None;
+            return true;
+        }
+    }
+
+    match controlled.check_stall(config) {
+        Verdict::Ok => {}
+        Verdict::KickStart => {
+            let written = match controlled.fan.set_speed(100.0) {
+                Ok(pwm) => {
+                    controlled.last_pwm = Some(pwm);
+                    true
+                }
+                Err(e) => {
+                    error!("Failed to kick-start fan. {:?}", e);
+                    false
+                }
+            };
+            // curve speed must be restored in next tick
+            controlled.last_speed = None;
+            controlled.source = SpeedSource::KickStart;
+            controlled.target_speed = Some(100.0);
+            return written;
+        }
+        Verdict::Stalled => {
+            controlled.hand_over_to_firmware();
+            return true;
+        }
+    }
+
+    let reading = controlled.fan.reading();
+    let diagnostic = diagnose(config, &reading);
+    let action = controlled.failsafe.check(config.failsafe(), &reading);
+    controlled.last_reading = Some((reading, diagnostic));
+    let (speed, source) = match action {
+        Action::Hold => {
+            controlled.source = SpeedSource::Hold;
+            return false;
+        }
+        Action::Firmware => {
+            controlled.hand_over_to_firmware();
+            return true;
+        }
+        Action::FullSpeed => (100.0, SpeedSource::Failsafe),
+        Action::Curve(_) => match controlled.speed_override {
+            Some(speed) => (speed, SpeedSource::Override),
+            None => (diagnostic.value, diagnostic.source()),
+        },
+    };
+    controlled.firmware = false;
+    controlled.source = source;
+    controlled.target_speed = Some(speed);
+
+    if let Some(last) = controlled.last_speed {
+        if (last - speed).abs() < 0.001f64 {
+            return true;
+        }
+    }
+    debug!("Changing speed to {speed:0.2}");
+
+    match controlled.fan.set_speed(speed) {
+        Ok(pwm) => {
+            controlled.last_speed = Some(speed);
+            controlled.last_pwm = Some(pwm);
+            controlled.verify_ownership(config, pwm);
+            true
+        }
+        Err(e) => {
+            error!("Failed to change speed to {}. {:?}", speed, e);
+            controlled.last_speed = None;
+            controlled.last_pwm = None;
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+fn highest_speed(config: &Config, reading: &Reading) -> f64 {
+    diagnose(config, reading).value
+}
 
-    let gpu_usage = hw_mon.gpu_usage().unwrap_or(0.0);
+/// Compute speed from both curves
+pub(crate) fn diagnose(config: &Config, reading: &Reading) -> Diagnostic {
+    let gpu_temp = reading.temp.unwrap_or_default();
+    let gpu_usage = reading.usage;
 
     let temp_fan_speed = config.fan_speed_for_temp(gpu_temp);
     let usage_fan_speed = config.fan_speed_for_usage(gpu_usage);
     let value = temp_fan_speed.max(usage_fan_speed);
 
-    debug!(
-        "{:?}",
-        Diagnostic {
-            gpu_temp,
-            gpu_usage,
-            temp_fan_speed,
-            usage_fan_speed,
-            value,
-        }
-    );
+    let diagnostic = Diagnostic {
+        gpu_temp,
+        gpu_usage,
+        temp_fan_speed,
+        usage_fan_speed,
+        value,
+    };
+    debug!("{:?}", diagnostic);
+    diagnostic
+}
 
-    value
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Diagnostic {
+    pub gpu_temp: f64,
+    pub gpu_usage: f64,
+    pub temp_fan_speed: f64,
+    pub usage_fan_speed: f64,
+    pub value: f64,
 }
 
-#[allow(dead_code)]
-struct Diagnostic {
-    gpu_temp: f64,
-    gpu_usage: f64,
-    temp_fan_speed: f64,
-    usage_fan_speed: f64,
-    value: f64,
+impl Diagnostic {
+    /// Curve which gave higher speed
+    pub fn source(&self) -> SpeedSource {
+        if self.temp_fan_speed >= self.usage_fan_speed {
+            SpeedSource::Temperature
+        } else {
+            SpeedSource::Usage
+        }
+    }
 }
 
 impl std::fmt::Debug for Diagnostic {
@@ -112,78 +600,372 @@ pub struct Service {
 
 #[cfg(test)]
 mod tests {
-    use std::path::{Path, PathBuf};
-
-    use amdgpu::hw_mon::{HwMon, HwMonName, RootPath};
+    use amdgpu::fan_health::{read_health, write_health, FanHealth, FanHealthReport};
+    use amdgpu::pidfile::fan_cmd::SpeedSource;
+    use amdgpu::pidfile::reload::ReloadOutcome;
     use amdgpu::Card;
-    use amdgpu_config::fan::Config;
+    use amdgpu_config::fan::{Config, SensorFailureAction};
+    use amdgpu_testkit::{FakeGpu, FakeRoot};
 
     use crate::command::Fan;
-    use crate::service::highest_speed;
+    use crate::service::{highest_speed, reload, tick, Controlled};
 
-    struct FakeSysFs<'root> {
-        root: &'root Path,
-    }
+    #[test]
+    fn precent_is_higher() {
+        let gpu = FakeGpu::navi21().with_temp("edge", 0.012).with_usage(33);
 
-    impl<'root> RootPath for FakeSysFs<'root> {
-        fn root_dir(&self) -> PathBuf {
-            self.root.to_path_buf()
-        }
+        let config = Config::default();
+        let hw = gpu.hw_mon();
 
-        fn device_dir(&self, card: &Card) -> PathBuf {
-            self.root_dir().join(card.to_string())
-        }
+        let usage = hw.read_gpu_usage();
+        assert_eq!(usage.unwrap(), 33);
 
-        fn mon_dir(&self, card: &Card, name: &HwMonName) -> PathBuf {
-            self.device_dir(card).join(name.as_str())
-        }
+        let fan = Fan::wrap(hw, &config);
+
+        let value = highest_speed(&config, &fan.reading());
+        assert_eq!(value, 33.0);
     }
 
     #[test]
-    fn precent_is_higher() {
-        let dir = tempdir::TempDir::new("foo").unwrap();
-        let fs = FakeSysFs { root: dir.path() };
-
-        let card = Card(0);
-        let name = HwMonName("a".into());
-        std::fs::create_dir_all(fs.mon_dir(&card, &name)).unwrap();
-
-        std::fs::write(fs.device_dir(&card).join("gpu_busy_percent"), "33").unwrap();
-        std::fs::write(fs.mon_dir(&card, &name).join("temp1_input"), "12").unwrap();
+    fn temp_is_higher() {
+        let gpu = FakeGpu::navi21().with_temp("edge", 0.12).with_usage(33);
 
         let config = Config::default();
-        let hw = HwMon::<FakeSysFs>::new(&card, name, fs);
+        let hw = gpu.hw_mon();
 
         let usage = hw.read_gpu_usage();
         assert_eq!(usage.unwrap(), 33);
 
         let fan = Fan::wrap(hw, &config);
 
-        let value = highest_speed(&config, &fan);
+        let value = highest_speed(&config, &fan.reading());
         assert_eq!(value, 33.0);
     }
 
+    fn controlled(gpu: &FakeGpu) -> Controlled<FakeRoot> {
+        Controlled::new(Fan::wrap(gpu.hw_mon(), &Config::default()))
+            .with_health_dir(gpu.env().fan_state_dir())
+    }
+
+    /// Fake card with edge sensor at 40°C, critical temperature at 100°C and
+    /// fan without tachometer in manual mode
+    fn failsafe() -> FakeGpu {
+        let gpu = FakeGpu::navi21()
+            .with_temp("edge", 40.0)
+            .with_fan(0, 0)
+            .without_tachometer();
+        gpu.set_mon_file("pwm1_enable", "1");
+        gpu
+    }
+
     #[test]
-    fn temp_is_higher() {
-        let dir = tempdir::TempDir::new("foo").unwrap();
-        let fs = FakeSysFs { root: dir.path() };
+    fn critical_temperature_forces_full_speed() {
+        let gpu = failsafe();
+        let config = toml::from_str::<Config>(
+            "log_level = 'Error'\n[[temp_matrix]]\ntemp = 0.0\nspeed = 0.0\n[[temp_matrix]]\ntemp = 100.0\nspeed = 50.0",
+        )
+        .unwrap();
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "51");
 
-        let card = Card(0);
-        let name = HwMonName("a".into());
-        std::fs::create_dir_all(fs.mon_dir(&card, &name)).unwrap();
+        gpu.set_mon_file("temp1_input", "96000");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "255");
 
-        std::fs::write(fs.device_dir(&card).join("gpu_busy_percent"), "33").unwrap();
-        std::fs::write(fs.mon_dir(&card, &name).join("temp1_input"), "120").unwrap();
+        gpu.set_mon_file("temp1_input", "60000");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "77");
+        assert_eq!(gpu.writes_to("pwm1"), vec!["51", "255", "77"]);
+    }
 
+    #[test]
+    fn emergency_is_used_without_critical() {
+        let gpu = failsafe();
+        gpu.remove_mon_file("temp1_crit");
+        gpu.set_mon_file("temp1_emergency", "90000");
+        gpu.set_mon_file("temp1_input", "88000");
+        let mut controlled = controlled(&gpu);
+
+        tick(&Config::default(), &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "255");
+    }
+
+    #[test]
+    fn disappeared_sensor_forces_full_speed() {
+        let gpu = failsafe();
         let config = Config::default();
-        let hw = HwMon::<FakeSysFs>::new(&card, name, fs);
+        let mut controlled = controlled(&gpu);
 
-        let usage = hw.read_gpu_usage();
-        assert_eq!(usage.unwrap(), 33);
+        assert!(tick(&config, &mut controlled));
+        let normal = gpu.mon_file("pwm1").unwrap();
+        assert_ne!(normal, "255");
 
-        let fan = Fan::wrap(hw, &config);
+        gpu.remove_mon_file("temp1_input");
+        for _ in 1..config.failsafe().max_read_failures {
+            // failed reads don't reset watchdog
+            assert!(!tick(&config, &mut controlled));
+            assert_eq!(gpu.mon_file("pwm1").unwrap(), normal);
+        }
+        assert!(tick(&config, &mut controlled));
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "255");
 
-        let value = highest_speed(&config, &fan);
-        assert_eq!(value, 33.0);
+        gpu.set_mon_file("temp1_input", "40000");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), normal);
+    }
+
+    #[test]
+    fn garbage_sensor_gives_control_to_firmware() {
+        let gpu = failsafe();
+        let mut config = Config::default();
+        config.failsafe_mut().on_sensor_failure = SensorFailureAction::Firmware;
+        config.failsafe_mut().max_read_failures = 2;
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        let normal = gpu.mon_file("pwm1").unwrap();
+
+        gpu.set_mon_file("temp1_input", "not a number");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "1");
+
+        gpu.set_mon_file("temp1_input", "4294967295000");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "2");
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), normal);
+
+        gpu.set_mon_file("temp1_input", "40000");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "1");
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), normal);
+    }
+
+    /// Fan curve with constant 50% speed and stall detection without grace
+    /// period
+    fn stall_config() -> Config {
+        let mut config = toml::from_str::<Config>(
+            "log_level = 'Error'\n[[temp_matrix]]\ntemp = 0.0\nspeed = 50.0\n[[temp_matrix]]\ntemp = 100.0\nspeed = 50.0",
+        )
+        .unwrap();
+        config.stall_mut().grace_ticks = 1;
+        config
+    }
+
+    #[test]
+    fn stalled_fan_is_kicked_then_given_to_firmware() {
+        let gpu = failsafe();
+        gpu.set_mon_file("fan1_input", "0");
+        gpu.set_mon_file("fan1_max", "3000");
+        let config = stall_config();
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "255");
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "1");
+
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "2");
+
+        gpu.set_mon_file("fan1_input", "1500");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "2");
+
+        let report = read_health(gpu.env().fan_state_dir(), &Card(0)).unwrap();
+        assert_eq!(report.health, FanHealth::Stalled);
+        assert_eq!(report.pwm, 255);
+        assert_eq!(report.rpm, Some(0));
+        assert_eq!(report.expected_rpm, Some(3000));
+    }
+
+    #[test]
+    fn health_of_previous_run_is_replaced() {
+        let gpu = failsafe();
+        let dir = gpu.env().fan_state_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let stalled = FanHealthReport {
+            card: Card(0),
+            health: FanHealth::Stalled,
+            pwm: 255,
+            rpm: Some(0),
+            expected_rpm: Some(3000),
+        };
+        write_health(&dir, &stalled).unwrap();
+
+        let controlled = controlled(&gpu);
+        assert_eq!(read_health(&dir, &Card(0)).unwrap().health, FanHealth::Ok);
+
+        controlled.remove_health();
+        assert_eq!(read_health(&dir, &Card(0)), None);
+    }
+
+    #[test]
+    fn kick_start_recovers_fan() {
+        let gpu = failsafe();
+        gpu.set_mon_file("fan1_input", "0");
+        gpu.set_mon_file("fan1_max", "3000");
+        let config = stall_config();
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "255");
+
+        gpu.set_mon_file("fan1_input", "2900");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "1");
+        assert_eq!(
+            read_health(gpu.env().fan_state_dir(), &Card(0))
+                .unwrap()
+                .health,
+            FanHealth::Ok
+        );
+    }
+
+    #[test]
+    fn manual_mode_is_reasserted() {
+        let gpu = failsafe();
+        let config = stall_config();
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+
+        gpu.set_mon_file("pwm1_enable", "2");
+        gpu.set_mon_file("pwm1", "80");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "1");
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+
+        gpu.set_mon_file("pwm1", "60");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+    }
+
+    #[test]
+    fn recurring_conflict_backs_off() {
+        let gpu = failsafe();
+        let mut config = stall_config();
+        config.takeover_mut().max_conflicts = 1;
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        gpu.set_mon_file("pwm1", "60");
+
+        // conflict detected, fan is left alone for 2 ticks
+        for _ in 0..3 {
+            tick(&config, &mut controlled);
+            assert_eq!(gpu.mon_file("pwm1").unwrap(), "60");
+        }
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+    }
+
+    #[test]
+    fn resume_reinitializes_fan() {
+        let gpu = failsafe();
+        let config = stall_config();
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+
+        // driver after resume
+        gpu.set_mon_file("pwm1_enable", "2");
+        gpu.set_mon_file("pwm1_max", "200");
+        controlled.reinitialize();
+        assert_eq!(gpu.mon_file("pwm1_enable").unwrap(), "1");
+
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "100");
+    }
+
+    #[test]
+    fn invalid_config_is_not_loaded() {
+        let dir = tempdir::TempDir::new("reload").unwrap();
+        let path = dir.path().join("mapping.toml");
+        let path = path.to_str().unwrap();
+        std::fs::write(path, "log_level = 'Error'\nupdate_rate = 1000").unwrap();
+        let mut config = amdgpu_config::fan::load_config(path).unwrap();
+
+        std::fs::write(
+            path,
+            "log_level = 'Error'\nupdate_rate = 2000\n[[temp_matrix]]\ntemp = 10.0\nspeed = 120.0",
+        )
+        .unwrap();
+        match reload(&mut config) {
+            ReloadOutcome::Failed(msg) => assert!(msg.contains("too high"), "{}", msg),
+            outcome => panic!("unexpected {:?}", outcome),
+        }
+        assert_eq!(config.update_rate(), 1000);
+
+        std::fs::write(path, "update_rate = [").unwrap();
+        assert!(matches!(reload(&mut config), ReloadOutcome::Failed(_)));
+        assert_eq!(config.update_rate(), 1000);
+
+        std::fs::write(path, "log_level = 'Error'\nupdate_rate = 2000").unwrap();
+        assert_eq!(reload(&mut config), ReloadOutcome::Reloaded);
+        assert_eq!(config.update_rate(), 2000);
+    }
+
+    #[test]
+    fn override_is_used_but_failsafe_wins() {
+        let gpu = failsafe();
+        let config = stall_config();
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        let status = controlled.status();
+        assert_eq!(status.source, SpeedSource::Temperature);
+        assert_eq!(status.target_pwm, Some(127));
+        assert_eq!(status.written_pwm, Some(127));
+        assert_eq!(status.temp, Some(40.0));
+
+        controlled.speed_override = Some(80.0);
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "204");
+        assert_eq!(controlled.status().source, SpeedSource::Override);
+
+        gpu.set_mon_file("temp1_input", "99000");
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "255");
+        assert_eq!(controlled.status().source, SpeedSource::Failsafe);
+
+        gpu.set_mon_file("temp1_input", "40000");
+        controlled.speed_override = None;
+        tick(&config, &mut controlled);
+        assert_eq!(gpu.mon_file("pwm1").unwrap(), "127");
+    }
+
+    #[test]
+    fn status_reports_dominating_curve() {
+        let gpu = failsafe();
+        gpu.set_usage(90);
+        let config = Config::default();
+        let mut controlled = controlled(&gpu);
+
+        tick(&config, &mut controlled);
+        let status = controlled.status();
+        assert_eq!(status.source, SpeedSource::Usage);
+        assert!(status.usage_speed > status.temp_speed);
+        assert_eq!(status.target_speed, Some(status.usage_speed));
+    }
+
+    #[test]
+    fn service_manager_status() {
+        let gpu = failsafe();
+        let config = stall_config();
+        let mut controlled = controlled(&gpu);
+        assert_eq!(super::sd_status(&[controlled.status()]), "card0 - - (None)");
+
+        tick(&config, &mut controlled);
+        let status = super::sd_status(&[controlled.status()]);
+        assert!(status.starts_with("card0 40.0°C "), "{}", status);
+        assert!(status.ends_with("% (Temperature)"), "{}", status);
     }
 }
@@ -0,0 +1,322 @@
+//! Run control loop against recorded sensor trace instead of real GPU.
+//!
+//! Trace is CSV with header, `amdmond log-file` output can be used directly.
+//! Only `temperature` column is required, `time`, `usage`, `rpm` and
+//! `critical` are used when present and other columns are ignored. Empty
+//! value is treated as failed sensor read.
+//!
+//! Each row is written to temporary fake sysfs and service tick is executed
+//! without waiting for `update_rate`, so curves, failsafe, stall detection
+//! etc. behave exactly like in running service.
+
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use amdgpu::hw_mon::{HwMon, HwMonName, RootPath};
+use amdgpu::{
+    Card, TempInput, FAN_INPUT, PULSE_WIDTH_MODULATION, PULSE_WIDTH_MODULATION_MANUAL,
+    PULSE_WIDTH_MODULATION_MAX, PULSE_WIDTH_MODULATION_MIN, PULSE_WIDTH_MODULATION_MODE,
+};
+use amdgpu_config::fan::{load_config, Config};
+use gumdrop::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::command::Fan;
+use crate::service::{tick, Controlled};
+use crate::AmdFanError;
+
+#[derive(Debug, Options)]
+pub struct Simulate {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(
+        help = "CSV file with recorded sensors (ex. amdmond log-file output)",
+        required
+    )]
+    trace: String,
+    #[options(help = "Config location, by default config of service is used")]
+    config: Option<String>,
+    #[options(help = "Output format: csv or json", default = "csv")]
+    format: Format,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl std::str::FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown format {:?}, expected csv or json", s)),
+        }
+    }
+}
+
+/// Single row of recorded trace
+#[derive(Debug, Deserialize)]
+struct Sample {
+    #[serde(default)]
+    time: Option<String>,
+    /// Celsius
+    temperature: Option<f64>,
+    /// Percent
+    #[serde(default)]
+    usage: Option<f64>,
+    #[serde(default)]
+    rpm: Option<u32>,
+    /// Critical temperature in Celsius
+    #[serde(default)]
+    critical: Option<f64>,
+}
+
+/// Decision of control loop for single row
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Step {
+    pub step: usize,
+    pub time: Option<String>,
+    pub temperature: Option<f64>,
+    pub usage: Option<f64>,
+    /// What decided about speed
+    pub source: String,
+    pub speed: Option<f64>,
+    /// Modulation in `pwm1` after tick
+    pub pwm: Option<u64>,
+    /// `pwm1_enable` after tick
+    pub mode: Option<u64>,
+    pub health: String,
+}
+
+struct SimRoot {
+    root: PathBuf,
+}
+
+impl RootPath for SimRoot {
+    fn root_dir(&self) -> PathBuf {
+        self.root.clone()
+    }
+
+    fn device_dir(&self, card: &Card) -> PathBuf {
+        self.root_dir().join(card.to_string())
+    }
+
+    fn mon_dir(&self, card: &Card, name: &HwMonName) -> PathBuf {
+        self.device_dir(card).join(name.as_str())
+    }
+}
+
+/// Fake sysfs removed when dropped
+struct SimDir(PathBuf);
+
+impl Drop for SimDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub fn run(command: Simulate, config: Config) -> crate::Result<()> {
+    let config = match command.config.as_deref() {
+        Some(path) => load_config(path)?,
+        None => config,
+    };
+    let trace = std::fs::File::open(&command.trace)?;
+    let stdout = std::io::stdout();
+    let steps = simulate(&config, trace)?;
+    write_steps(&steps, command.format, stdout.lock())
+}
+
+/// Execute one tick for each trace row
+pub fn simulate<R: Read>(config: &Config, trace: R) -> crate::Result<Vec<Step>> {
+    let dir = SimDir(std::env::temp_dir().join(format!(
+        "amdfand-simulate-{}-{:?}",
+        std::process::id(),
+        std::thread::current().id()
+    )));
+    let card = Card(0);
+    let name = HwMonName("hwmon0".into());
+    let fs = SimRoot {
+        root: dir.0.clone(),
+    };
+    let mon_dir = fs.mon_dir(&card, &name);
+    let device_dir = fs.device_dir(&card);
+    std::fs::create_dir_all(&mon_dir)?;
+
+    let temp_input = config
+        .temp_input()
+        .map(TempInput::as_string)
+        .unwrap_or_else(|| String::from("temp1_input"));
+    let crit_file = temp_input.replace("_input", "_crit");
+    for (file, value) in [
+        (temp_input.as_str(), "0"),
+        (PULSE_WIDTH_MODULATION_MIN, "0"),
+        (PULSE_WIDTH_MODULATION_MAX, "255"),
+        (PULSE_WIDTH_MODULATION, "0"),
+        (PULSE_WIDTH_MODULATION_MODE, PULSE_WIDTH_MODULATION_MANUAL),
+    ] {
+        std::fs::write(mon_dir.join(file), value)?;
+    }
+    std::fs::write(device_dir.join("gpu_busy_percent"), "0")?;
+
+    let hw_mon = HwMon::new(&card, name, fs);
+    let mut controlled = Controlled::new(Fan::wrap(hw_mon, config));
+
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_reader(trace);
+    let mut steps = vec![];
+    for (step, sample) in reader.deserialize::<Sample>().enumerate() {
+        let sample = sample.map_err(AmdFanError::Csv)?;
+        write_sample(&mon_dir, &device_dir, &temp_input, &crit_file, &sample)?;
+        tick(config, &mut controlled);
+
+        let status = controlled.status();
+        let read = |file: &str| {
+            std::fs::read_to_string(mon_dir.join(file))
+                .ok()
+                .and_then(|s| s.trim().parse().ok())
+        };
+        let step = Step {
+            step,
+            time: sample.time,
+            temperature: sample.temperature,
+            usage: sample.usage,
+            source: format!("{:?}", status.source),
+            speed: status.target_speed,
+            pwm: read(PULSE_WIDTH_MODULATION),
+            mode: read(PULSE_WIDTH_MODULATION_MODE),
+            health: status.health.to_string(),
+        };
+        steps.push(step);
+    }
+    Ok(steps)
+}
+
+fn write_sample(
+    mon_dir: &Path,
+    device_dir: &Path,
+    temp_input: &str,
+    crit_file: &str,
+    sample: &Sample,
+) -> std::io::Result<()> {
+    // empty value makes reading fail like broken sensor
+    let milli = |v: Option<f64>| v.map(|v| ((v * 1000.0).round() as i64).to_string());
+    std::fs::write(
+        mon_dir.join(temp_input),
+        milli(sample.temperature).unwrap_or_default(),
+    )?;
+    match milli(sample.critical) {
+        Some(crit) => std::fs::write(mon_dir.join(crit_file), crit)?,
+        None => {
+            let _ = std::fs::remove_file(mon_dir.join(crit_file));
+        }
+    }
+    match sample.rpm {
+        Some(rpm) => std::fs::write(mon_dir.join(FAN_INPUT), rpm.to_string())?,
+        None => {
+            let _ = std::fs::remove_file(mon_dir.join(FAN_INPUT));
+        }
+    }
+    std::fs::write(
+        device_dir.join("gpu_busy_percent"),
+        (sample.usage.unwrap_or_default().round() as u64).to_string(),
+    )
+}
+
+pub fn write_steps<W: Write>(steps: &[Step], format: Format, out: W) -> crate::Result<()> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(out);
+            for step in steps {
+                writer.serialize(step).map_err(AmdFanError::Csv)?;
+            }
+            writer.flush()?;
+        }
+        Format::Json => {
+            let mut out = out;
+            serde_json::to_writer_pretty(&mut out, steps).map_err(AmdFanError::Json)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_config::fan::Config;
+
+    use super::{simulate, write_steps, Format};
+
+    fn config() -> Config {
+        toml::from_str(
+            r#"
+log_level = 'Error'
+temp_input = 'temp1_input'
+
+[[temp_matrix]]
+temp = 30.0
+speed = 0.0
+
+[[temp_matrix]]
+temp = 80.0
+speed = 100.0
+
+[stall]
+enabled = false
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn replay_amdmond_log() {
+        let trace = "\
+time,temperature,modulation,speed_setting,temperature_setting,usage,usage_speed
+2024-01-01T10:00:00+01:00,30.0,0,0.0,30.0,0.0,0.0
+2024-01-01T10:00:05+01:00,55.0,0,50.0,55.0,0.0,0.0
+2024-01-01T10:00:10+01:00,80.0,0,100.0,80.0,0.0,0.0
+";
+        let steps = simulate(&config(), trace.as_bytes()).unwrap();
+        let pwm: Vec<_> = steps.iter().map(|step| step.pwm).collect();
+        assert_eq!(pwm, vec![Some(0), Some(127), Some(255)]);
+        assert_eq!(steps[1].source, "Temperature");
+        assert_eq!(steps[1].time.as_deref(), Some("2024-01-01T10:00:05+01:00"));
+
+        let mut out = vec![];
+        write_steps(&steps, Format::Csv, &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("step,time,temperature,usage,source,speed,pwm,mode,health\n"));
+        assert_eq!(out.lines().count(), 4);
+    }
+
+    #[test]
+    fn incident_regression() {
+        // sensor disappears and then reports critical temperature
+        let trace = "\
+temperature,critical
+40.0,100.0
+,100.0
+,100.0
+,100.0
+,100.0
+97.0,100.0
+";
+        let steps = simulate(&config(), trace.as_bytes()).unwrap();
+        let sources: Vec<_> = steps.iter().map(|step| step.source.as_str()).collect();
+        assert_eq!(sources[0], "Temperature");
+        assert_eq!(sources[1], "Hold");
+        assert_eq!(steps[1].pwm, steps[0].pwm);
+        assert_eq!(steps[5].source, "Failsafe");
+        assert_eq!(steps[5].pwm, Some(255));
+
+        let mut out = vec![];
+        write_steps(&steps, Format::Json, &mut out).unwrap();
+        let json: Vec<serde_json::Value> = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json.len(), 6);
+        assert_eq!(json[5]["pwm"], 255);
+    }
+}
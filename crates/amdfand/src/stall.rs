@@ -0,0 +1,202 @@
+//! Detection of stalled fans and broken tachometers. Fan which does not spin
+//! (or spins way too fast) for written modulation is first kicked with full
+//! speed pulse and if this does not help control is given back to GPU
+//! firmware.
+
+use amdgpu::fan_health::FanHealth;
+use amdgpu::Card;
+use amdgpu_config::fan::Stall;
+use tracing::{error, info, warn};
+
+/// Decision made for single tick
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    /// Fan works or it's too early to tell
+    Ok,
+    /// Force full speed for single tick
+    KickStart,
+    /// Give control back to GPU firmware
+    Stalled,
+}
+
+/// Fan state observed after modulation was written
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    /// Written modulation
+    pub pwm: u64,
+    /// Written modulation as percent of available range
+    pub speed: f64,
+    /// Fan speed reported by tachometer, `None` if there's no tachometer
+    pub rpm: Option<u32>,
+    /// Fan speed expected for written modulation, `None` if unknown
+    pub expected_rpm: Option<u32>,
+}
+
+#[derive(Debug, Default)]
+pub struct StallDetector {
+    health: FanHealth,
+    /// Consecutive ticks with suspicious fan speed
+    suspect_ticks: u32,
+    kick_starts: u32,
+}
+
+impl StallDetector {
+    pub fn health(&self) -> FanHealth {
+        self.health
+    }
+
+    pub fn check(&mut self, config: &Stall, card: &Card, sample: &Sample) -> Verdict {
+        if self.health == FanHealth::Stalled {
+            return Verdict::Stalled;
+        }
+        if !config.enabled || sample.speed < config.min_speed || !is_suspicious(config, sample) {
+            if self.health != FanHealth::Ok {
+                info!("Fan of {} is spinning again ({:?} RPM)", card, sample.rpm);
+            }
+            *self = Self::default();
+            return Verdict::Ok;
+        }
+
+        self.suspect_ticks = self.suspect_ticks.saturating_add(1);
+        if self.suspect_ticks < config.grace_ticks.max(1) {
+            warn!(
+                "Fan of {} reports {:?} RPM for modulation {} (expected {:?} RPM) ({}/{})",
+                card,
+                sample.rpm,
+                sample.pwm,
+                sample.expected_rpm,
+                self.suspect_ticks,
+                config.grace_ticks
+            );
+            self.health = FanHealth::Suspect;
+            return Verdict::Ok;
+        }
+
+        self.suspect_ticks = 0;
+        if self.kick_starts < config.kick_start_attempts {
+            self.kick_starts += 1;
+            warn!(
+                "Fan of {} looks stalled, kick-starting with full speed ({}/{})",
+                card, self.kick_starts, config.kick_start_attempts
+            );
+            self.health = FanHealth::KickStart;
+            return Verdict::KickStart;
+        }
+
+        error!(
+            event = "fan_stall",
+            card = %card,
+            pwm = sample.pwm,
+            rpm = ?sample.rpm,
+            expected_rpm = ?sample.expected_rpm,
+            kick_starts = self.kick_starts,
+            "Fan of {} is stalled or its tachometer is broken! Giving fan control back to GPU firmware",
+            card
+        );
+        self.health = FanHealth::Stalled;
+        Verdict::Stalled
+    }
+}
+
+fn is_suspicious(config: &Stall, sample: &Sample) -> bool {
+    match (sample.rpm, sample.expected_rpm) {
+        (None, _) => false,
+        (Some(0), _) => true,
+        (Some(rpm), Some(expected)) if expected > 0 => {
+            let (rpm, expected) = (rpm as f64, expected as f64);
+            rpm < expected * config.rpm_ratio || rpm * config.rpm_ratio > expected
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu::fan_health::FanHealth;
+    use amdgpu::Card;
+    use amdgpu_config::fan::Stall;
+
+    use super::{Sample, StallDetector, Verdict};
+
+    fn sample(rpm: u32) -> Sample {
+        Sample {
+            pwm: 128,
+            speed: 50.0,
+            rpm: Some(rpm),
+            expected_rpm: Some(1500),
+        }
+    }
+
+    #[test]
+    fn kick_start_then_stall() {
+        let config = Stall {
+            grace_ticks: 2,
+            ..Default::default()
+        };
+        let mut detector = StallDetector::default();
+        let card = Card(0);
+
+        assert_eq!(detector.check(&config, &card, &sample(1400)), Verdict::Ok);
+        assert_eq!(detector.check(&config, &card, &sample(0)), Verdict::Ok);
+        assert_eq!(detector.health(), FanHealth::Suspect);
+        assert_eq!(
+            detector.check(&config, &card, &sample(0)),
+            Verdict::KickStart
+        );
+        assert_eq!(detector.check(&config, &card, &sample(0)), Verdict::Ok);
+        assert_eq!(detector.check(&config, &card, &sample(0)), Verdict::Stalled);
+        assert_eq!(
+            detector.check(&config, &card, &sample(1500)),
+            Verdict::Stalled
+        );
+        assert_eq!(detector.health(), FanHealth::Stalled);
+    }
+
+    #[test]
+    fn recovery_resets_state() {
+        let config = Stall {
+            grace_ticks: 1,
+            ..Default::default()
+        };
+        let mut detector = StallDetector::default();
+        let card = Card(0);
+
+        assert_eq!(
+            detector.check(&config, &card, &sample(9000)),
+            Verdict::KickStart
+        );
+        assert_eq!(detector.check(&config, &card, &sample(1600)), Verdict::Ok);
+        assert_eq!(detector.health(), FanHealth::Ok);
+        assert_eq!(
+            detector.check(&config, &card, &sample(100)),
+            Verdict::KickStart
+        );
+    }
+
+    #[test]
+    fn slow_and_missing_tachometer_are_ignored() {
+        let config = Stall {
+            grace_ticks: 1,
+            ..Default::default()
+        };
+        let mut detector = StallDetector::default();
+        let card = Card(0);
+
+        let slow = Sample {
+            speed: 10.0,
+            ..sample(0)
+        };
+        assert_eq!(detector.check(&config, &card, &slow), Verdict::Ok);
+        let no_tach = Sample {
+            rpm: None,
+            ..sample(0)
+        };
+        assert_eq!(detector.check(&config, &card, &no_tach), Verdict::Ok);
+        let no_range = Sample {
+            expected_rpm: None,
+            ..sample(9000)
+        };
+        assert_eq!(detector.check(&config, &card, &no_range), Verdict::Ok);
+        assert_eq!(detector.health(), FanHealth::Ok);
+    }
+}
@@ -0,0 +1,129 @@
+//! Suspend/resume awareness. GPU driver resets fan to automatic mode after
+//! resume so all fans must be initialized again.
+//!
+//! Resume is detected by comparing monotonic clock (stopped during sleep)
+//! with boot time clock (running during sleep). With `logind` feature
+//! `PrepareForSleep` signal from systemd-logind is used as well.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use nix::time::{clock_gettime, ClockId};
+
+/// Minimal difference between clocks treated as sleep
+const SLEEP_THRESHOLD: Duration = Duration::from_secs(2);
+
+/// Set by logind listener when system wakes up
+static RESUMED: AtomicBool = AtomicBool::new(false);
+
+/// Current value of monotonic and boot time clocks
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Clocks {
+    pub monotonic: Duration,
+    pub boottime: Duration,
+}
+
+impl Clocks {
+    pub fn now() -> Option<Self> {
+        Some(Self {
+            monotonic: clock_gettime(ClockId::CLOCK_MONOTONIC).ok()?.into(),
+            boottime: clock_gettime(ClockId::CLOCK_BOOTTIME).ok()?.into(),
+        })
+    }
+
+    /// Time spent in sleep between `self` and `later`
+    pub fn slept(&self, later: &Clocks) -> Option<Duration> {
+        let monotonic = later.monotonic.checked_sub(self.monotonic)?;
+        let boottime = later.boottime.checked_sub(self.boottime)?;
+        boottime
+            .checked_sub(monotonic)
+            .filter(|slept| *slept >= SLEEP_THRESHOLD)
+    }
+}
+
+#[derive(Debug)]
+pub struct SleepDetector {
+    last: Option<Clocks>,
+}
+
+impl Default for SleepDetector {
+    fn default() -> Self {
+        Self {
+            last: Clocks::now(),
+        }
+    }
+}
+
+impl SleepDetector {
+    /// Returns `true` if system was suspended since last check
+    pub fn resumed(&mut self) -> bool {
+        let now = Clocks::now();
+        let slept = match (self.last, now) {
+            (Some(last), Some(now)) => last.slept(&now),
+            _ => None,
+        };
+        self.last = now;
+        if let Some(slept) = slept {
+            tracing::info!("System was suspended for {:?}", slept);
+        }
+        // both must be consumed, otherwise signal would trigger second
+        // initialization later
+        let signaled = RESUMED.swap(false, Ordering::SeqCst);
+        slept.is_some() || signaled
+    }
+}
+
+/// Listen for systemd-logind `PrepareForSleep` signal in background thread
+#[cfg(feature = "logind")]
+pub fn listen_logind() {
+    std::thread::spawn(|| {
+        if let Err(e) = logind_loop() {
+            tracing::warn!("logind sleep listener stopped. {}", e);
+        }
+    });
+}
+
+#[cfg(feature = "logind")]
+fn logind_loop() -> zbus::Result<()> {
+    let connection = zbus::blocking::Connection::system()?;
+    let proxy = zbus::blocking::Proxy::new(
+        &connection,
+        "org.freedesktop.login1",
+        "/org/freedesktop/login1",
+        "org.freedesktop.login1.Manager",
+    )?;
+    for message in proxy.receive_signal("PrepareForSleep")? {
+        let start: bool = message.body().deserialize()?;
+        if start {
+            tracing::info!("System is going to sleep");
+        } else {
+            tracing::info!("System woke up");
+            RESUMED.store(true, Ordering::SeqCst);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Clocks;
+
+    fn clocks(monotonic: u64, boottime: u64) -> Clocks {
+        Clocks {
+            monotonic: Duration::from_secs(monotonic),
+            boottime: Duration::from_secs(boottime),
+        }
+    }
+
+    #[test]
+    fn detect_sleep() {
+        assert_eq!(clocks(10, 12).slept(&clocks(14, 16)), None);
+        assert_eq!(clocks(10, 12).slept(&clocks(14, 17)), None);
+        assert_eq!(
+            clocks(10, 12).slept(&clocks(14, 116)),
+            Some(Duration::from_secs(100))
+        );
+    }
+}
@@ -0,0 +1,200 @@
+//! Detection of other agents (driver reset, fancontrol, CoreCtrl, LACT...)
+//! changing fan mode or modulation behind amdfand back. Manual mode is
+//! reasserted, but if conflict keeps recurring amdfand backs off
+//! exponentially instead of fighting.
+
+use amdgpu::procfs::FileHolder;
+use amdgpu::{Card, PULSE_WIDTH_MODULATION_MANUAL};
+use amdgpu_config::fan::Takeover;
+use tracing::{info, warn};
+
+/// Number of clean checks after which previous conflicts are forgotten
+const CLEAN_CHECKS_TO_FORGIVE: u32 = 10;
+
+/// Fan state read back from hwmon
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observed {
+    /// `pwm1_enable` content
+    pub mode: Option<String>,
+    /// `pwm1` content
+    pub pwm: Option<u64>,
+}
+
+/// Decision made after verification
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Verdict {
+    /// Fan is still controlled by amdfand
+    Ok,
+    /// Someone else changed fan, manual mode and speed must be written again
+    Reassert,
+    /// Conflict keeps recurring, don't touch fan for a while
+    BackOff,
+}
+
+#[derive(Debug, Default)]
+pub struct TakeoverGuard {
+    /// Conflicts without [CLEAN_CHECKS_TO_FORGIVE] clean checks between them
+    conflicts: u32,
+    clean_checks: u32,
+    /// Remaining ticks without touching fan
+    backoff: u32,
+}
+
+impl TakeoverGuard {
+    /// Consume single backoff tick. Returns `true` if fan must not be touched
+    pub fn backing_off(&mut self) -> bool {
+        if self.backoff == 0 {
+            return false;
+        }
+        self.backoff -= 1;
+        if self.backoff == 0 {
+            info!("Back off period is over, taking fan control again");
+        }
+        true
+    }
+
+    /// Compare fan state with last written modulation. `holders` is called
+    /// only when conflict is detected
+    pub fn verify<Holders>(
+        &mut self,
+        config: &Takeover,
+        card: &Card,
+        expected_pwm: u64,
+        observed: &Observed,
+        holders: Holders,
+    ) -> Verdict
+    where
+        Holders: FnOnce() -> Vec<FileHolder>,
+    {
+        if !config.enabled {
+            return Verdict::Ok;
+        }
+        let reason = match (observed.mode.as_deref(), observed.pwm) {
+            (Some(mode), _) if mode != PULSE_WIDTH_MODULATION_MANUAL => {
+                format!("fan mode was changed to {}", mode)
+            }
+            (_, Some(pwm)) if pwm.abs_diff(expected_pwm) > config.pwm_tolerance => {
+                format!("modulation was changed from {} to {}", expected_pwm, pwm)
+            }
+            _ => {
+                self.clean_checks = self.clean_checks.saturating_add(1);
+                if self.clean_checks >= CLEAN_CHECKS_TO_FORGIVE {
+                    self.conflicts = 0;
+                }
+                return Verdict::Ok;
+            }
+        };
+
+        self.clean_checks = 0;
+        self.conflicts = self.conflicts.saturating_add(1);
+        let holders = holders();
+        let holders = if holders.is_empty() {
+            String::from("no process keeps hwmon files open")
+        } else {
+            holders
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        warn!(
+            "Fan of {} was taken over by another agent, {} (conflict {}). Open hwmon files: {}",
+            card, reason, self.conflicts, holders
+        );
+
+        if self.conflicts < config.max_conflicts.max(1) {
+            return Verdict::Reassert;
+        }
+        let exponent = (self.conflicts - config.max_conflicts.max(1)).min(16);
+        self.backoff = 2u32.pow(exponent + 1).min(config.max_backoff_ticks.max(1));
+        warn!(
+            "Fan of {} is constantly changed by another agent, backing off for {} ticks",
+            card, self.backoff
+        );
+        Verdict::BackOff
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu::Card;
+    use amdgpu_config::fan::Takeover;
+
+    use super::{Observed, TakeoverGuard, Verdict};
+
+    fn observed(mode: &str, pwm: u64) -> Observed {
+        Observed {
+            mode: Some(mode.into()),
+            pwm: Some(pwm),
+        }
+    }
+
+    #[test]
+    fn tolerates_rounding() {
+        let mut guard = TakeoverGuard::default();
+        let config = Takeover::default();
+        let verdict = guard.verify(&config, &Card(0), 100, &observed("1", 102), || {
+            panic!("holders must not be inspected")
+        });
+        assert_eq!(verdict, Verdict::Ok);
+    }
+
+    #[test]
+    fn backs_off_exponentially() {
+        let mut guard = TakeoverGuard::default();
+        let config = Takeover {
+            max_conflicts: 2,
+            max_backoff_ticks: 4,
+            ..Default::default()
+        };
+        let card = Card(0);
+
+        assert_eq!(
+            guard.verify(&config, &card, 100, &observed("2", 100), Vec::new),
+            Verdict::Reassert
+        );
+        assert_eq!(
+            guard.verify(&config, &card, 100, &observed("1", 50), Vec::new),
+            Verdict::BackOff
+        );
+        assert!(guard.backing_off());
+        assert!(guard.backing_off());
+        assert!(!guard.backing_off());
+
+        assert_eq!(
+            guard.verify(&config, &card, 100, &observed("2", 100), Vec::new),
+            Verdict::BackOff
+        );
+        (0..4).for_each(|_| assert!(guard.backing_off()));
+        assert!(!guard.backing_off());
+
+        assert_eq!(
+            guard.verify(&config, &card, 100, &observed("2", 100), Vec::new),
+            Verdict::BackOff
+        );
+        (0..4).for_each(|_| assert!(guard.backing_off()));
+        assert!(!guard.backing_off());
+    }
+
+    #[test]
+    fn clean_checks_forgive_conflicts() {
+        let mut guard = TakeoverGuard::default();
+        let config = Takeover {
+            max_conflicts: 2,
+            ..Default::default()
+        };
+        let card = Card(0);
+
+        assert_eq!(
+            guard.verify(&config, &card, 100, &observed("2", 100), Vec::new),
+            Verdict::Reassert
+        );
+        for _ in 0..super::CLEAN_CHECKS_TO_FORGIVE {
+            guard.verify(&config, &card, 100, &observed("1", 100), Vec::new);
+        }
+        assert_eq!(
+            guard.verify(&config, &card, 100, &observed("2", 100), Vec::new),
+            Verdict::Reassert
+        );
+    }
+}
@@ -0,0 +1,188 @@
+//! Reload config when config file or drop-in files change.
+//!
+//! Parent directory is watched instead of config file itself because most
+//! editors save file by writing new one and renaming it over the old one,
+//! which would silently drop watch on the file.
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use amdgpu_config::layers::drop_in_dir;
+use nix::errno::Errno;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use tracing::{debug, info, warn};
+
+/// Events closer than this are merged into single reload
+const DEBOUNCE: Duration = Duration::from_millis(500);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Merges bursts of file events into single notification
+#[derive(Debug, Default)]
+pub struct Debouncer {
+    last_event: Option<Instant>,
+}
+
+impl Debouncer {
+    pub fn event(&mut self, now: Instant) {
+        self.last_event = Some(now);
+    }
+
+    /// Returns `true` once when no event was received for [DEBOUNCE]
+    pub fn ready(&mut self, now: Instant) -> bool {
+        match self.last_event {
+            Some(last) if now.duration_since(last) >= DEBOUNCE => {
+                self.last_event = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Handle to background watcher
+pub struct ConfigWatcher {
+    enabled: Arc<AtomicBool>,
+}
+
+impl ConfigWatcher {
+    /// Start watching config file and its drop-in directory. `on_change` is
+    /// called from watcher thread
+    pub fn start<F>(config_path: &str, on_change: F) -> nix::Result<Self>
+    where
+        F: Fn() + Send + 'static,
+    {
+        let path = PathBuf::from(config_path);
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let file_name = path.file_name().map(OsString::from).unwrap_or_default();
+        let drop_ins = drop_in_dir(config_path);
+        let drop_ins_name = drop_ins.file_name().map(OsString::from).unwrap_or_default();
+
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC)?;
+        inotify.add_watch(&dir, watch_flags())?;
+        let mut drop_ins_wd = watch_drop_ins(&inotify, &drop_ins);
+
+        let enabled = Arc::new(AtomicBool::new(true));
+        let thread_enabled = enabled.clone();
+        std::thread::spawn(move || {
+            let mut debouncer = Debouncer::default();
+            loop {
+                match inotify.read_events() {
+                    Ok(events) => {
+                        for event in events {
+                            let name = event.name.unwrap_or_default();
+                            let relevant = if Some(event.wd) == drop_ins_wd {
+                                Path::new(&name).extension().and_then(|e| e.to_str())
+                                    == Some("toml")
+                            } else if name == drop_ins_name {
+                                // drop-in directory was created or replaced
+                                drop_ins_wd = watch_drop_ins(&inotify, &drop_ins);
+                                true
+                            } else {
+                                name == file_name
+                            };
+                            if relevant {
+                                debug!("Config file event {:?} {:?}", event.mask, name);
+                                debouncer.event(Instant::now());
+                            }
+                        }
+                    }
+                    Err(Errno::EAGAIN) => {}
+                    Err(e) => {
+                        warn!("Config watcher stopped. {}", e);
+                        return;
+                    }
+                }
+                // handle was dropped
+                if Arc::strong_count(&thread_enabled) == 1 {
+                    return;
+                }
+                if debouncer.ready(Instant::now()) && thread_enabled.load(Ordering::SeqCst) {
+                    info!("Config file changed");
+                    on_change();
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        });
+        Ok(Self { enabled })
+    }
+
+    /// Pause or resume reloading without stopping watcher thread
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::SeqCst);
+    }
+}
+
+fn watch_flags() -> AddWatchFlags {
+    AddWatchFlags::IN_CLOSE_WRITE
+        | AddWatchFlags::IN_MOVED_TO
+        | AddWatchFlags::IN_MOVED_FROM
+        | AddWatchFlags::IN_CREATE
+        | AddWatchFlags::IN_DELETE
+}
+
+/// Drop-in directory is optional so missing directory is not an error
+fn watch_drop_ins(inotify: &Inotify, dir: &Path) -> Option<WatchDescriptor> {
+    if !dir.is_dir() {
+        return None;
+    }
+    inotify.add_watch(dir, watch_flags()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use std::time::{Duration, Instant};
+
+    use super::{ConfigWatcher, Debouncer, DEBOUNCE};
+
+    #[test]
+    fn debounce_burst() {
+        let start = Instant::now();
+        let mut debouncer = Debouncer::default();
+        assert!(!debouncer.ready(start));
+
+        debouncer.event(start);
+        debouncer.event(start + DEBOUNCE / 2);
+        assert!(!debouncer.ready(start + DEBOUNCE));
+        assert!(debouncer.ready(start + DEBOUNCE * 2));
+        assert!(!debouncer.ready(start + DEBOUNCE * 3));
+    }
+
+    #[test]
+    fn detect_rename_and_drop_ins() {
+        let dir = tempdir::TempDir::new("watch").unwrap();
+        let config_path = dir.path().join("mapping.toml");
+        std::fs::write(&config_path, "log_level = 'Error'").unwrap();
+
+        let (tx, rx) = channel();
+        let _watcher = ConfigWatcher::start(config_path.to_str().unwrap(), move || {
+            tx.send(()).unwrap();
+        })
+        .unwrap();
+        let timeout = Duration::from_secs(5);
+
+        // unrelated file is ignored
+        std::fs::write(dir.path().join("other.toml"), "").unwrap();
+        assert!(rx.recv_timeout(DEBOUNCE * 3).is_err());
+
+        // editor style save
+        let tmp = dir.path().join(".mapping.toml.swp");
+        std::fs::write(&tmp, "log_level = 'Info'").unwrap();
+        std::fs::rename(&tmp, &config_path).unwrap();
+        rx.recv_timeout(timeout).unwrap();
+
+        let drop_ins = dir.path().join("mapping.d");
+        std::fs::create_dir(&drop_ins).unwrap();
+        rx.recv_timeout(timeout).unwrap();
+
+        std::fs::write(drop_ins.join("10-silent.toml"), "update_rate = 2000").unwrap();
+        rx.recv_timeout(timeout).unwrap();
+        assert!(rx.recv_timeout(DEBOUNCE * 2).is_err());
+    }
+}
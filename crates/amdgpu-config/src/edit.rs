@@ -0,0 +1,284 @@
+//! Save config by patching existing file instead of writing it from scratch.
+//!
+//! Only values which were changed are written. Comments, order of keys and
+//! formatting of untouched values are kept, and defaults which were left out
+//! of file are not added.
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use toml::Value;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table};
+
+#[derive(Debug, thiserror::Error)]
+pub enum EditError {
+    #[error("Existing config file is not valid toml. {0}")]
+    Document(#[from] toml_edit::TomlError),
+    #[error("Existing config file is not valid config. {0}")]
+    Deserialize(#[from] toml::de::Error),
+    #[error("Failed to serialize config. {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Config must serialize to table")]
+    NotTable,
+}
+
+/// Apply `new` config to `original` file content. Values which are equal to
+/// ones read from `original` (including defaults) stay untouched
+pub fn patch<T>(original: &str, new: &T) -> Result<String, EditError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let mut document = original.parse::<DocumentMut>()?;
+    let old: T = toml::from_str(original)?;
+    let old = as_table(Value::try_from(&old)?)?;
+    // values are compared as plain toml values, new items are taken from
+    // serialized document so they keep order of struct fields
+    let fresh = toml::to_string(new)?.parse::<DocumentMut>()?;
+    let new = as_table(Value::try_from(new)?)?;
+    patch_table(
+        document.as_table_mut(),
+        Some(&old),
+        &new,
+        Some(fresh.as_table()),
+    );
+    Ok(document.to_string())
+}
+
+fn as_table(value: Value) -> Result<toml::value::Table, EditError> {
+    match value {
+        Value::Table(table) => Ok(table),
+        _ => Err(EditError::NotTable),
+    }
+}
+
+fn patch_table(
+    table: &mut Table,
+    old: Option<&toml::value::Table>,
+    new: &toml::value::Table,
+    fresh: Option<&Table>,
+) {
+    // iterate in order of fresh document so new keys are appended in order of
+    // struct fields
+    let keys: Vec<&str> = match fresh {
+        Some(fresh) => fresh
+            .iter()
+            .map(|(key, _)| key)
+            .filter(|key| new.contains_key(*key))
+            .chain(
+                new.keys()
+                    .map(String::as_str)
+                    .filter(|key| !fresh.contains_key(key)),
+            )
+            .collect(),
+        None => new.keys().map(String::as_str).collect(),
+    };
+    for key in keys {
+        let new_value = &new[key];
+        let old_value = old.and_then(|old| old.get(key));
+        let fresh_item = fresh.and_then(|fresh| fresh.get(key));
+        match table.get_mut(key) {
+            Some(item) => patch_item(item, old_value, new_value, fresh_item),
+            // missing value equal to old one is default, keep it out of file
+            None if old_value == Some(new_value) => {}
+            None => {
+                table.insert(key, new_item(new_value, fresh_item));
+            }
+        }
+    }
+    if let Some(old) = old {
+        for key in old.keys().filter(|key| !new.contains_key(*key)) {
+            table.remove(key);
+        }
+    }
+}
+
+fn patch_item(item: &mut Item, old: Option<&Value>, new: &Value, fresh: Option<&Item>) {
+    if old == Some(new) {
+        return;
+    }
+    match (item, new) {
+        (Item::Table(table), Value::Table(new)) => {
+            let old = match old {
+                Some(Value::Table(old)) => Some(old),
+                _ => None,
+            };
+            patch_table(table, old, new, fresh.and_then(Item::as_table));
+        }
+        (Item::ArrayOfTables(array), Value::Array(new)) if all_tables(new) => {
+            let old = match old {
+                Some(Value::Array(old)) => Some(old),
+                _ => None,
+            };
+            patch_array_of_tables(array, old, new, fresh.and_then(Item::as_array_of_tables));
+        }
+        (Item::Value(value), new) if !is_table_like(new) => {
+            let decor = value.decor().clone();
+            *value = match fresh.and_then(Item::as_value) {
+                Some(fresh) => fresh.clone(),
+                None => to_value(new),
+            };
+            *value.decor_mut() = decor;
+        }
+        (item, new) => *item = new_item(new, fresh),
+    }
+}
+
+/// Tables are patched by position so comments attached to points stay in
+/// place
+fn patch_array_of_tables(
+    array: &mut ArrayOfTables,
+    old: Option<&Vec<Value>>,
+    new: &[Value],
+    fresh: Option<&ArrayOfTables>,
+) {
+    for (idx, new_table) in new.iter().enumerate() {
+        let new_table = match new_table {
+            Value::Table(table) => table,
+            _ => continue,
+        };
+        let old_table = match old.and_then(|old| old.get(idx)) {
+            Some(Value::Table(table)) => Some(table),
+            _ => None,
+        };
+        let fresh_table = fresh.and_then(|fresh| fresh.get(idx));
+        match array.get_mut(idx) {
+            Some(table) => patch_table(table, old_table, new_table, fresh_table),
+            None => match fresh_table {
+                Some(fresh) => array.push(fresh.clone()),
+                None => {
+                    let mut table = Table::new();
+                    patch_table(&mut table, None, new_table, None);
+                    array.push(table);
+                }
+            },
+        }
+    }
+    while array.len() > new.len() {
+        array.remove(array.len() - 1);
+    }
+}
+
+fn all_tables(values: &[Value]) -> bool {
+    !values.is_empty() && values.iter().all(|v| matches!(v, Value::Table(_)))
+}
+
+fn is_table_like(value: &Value) -> bool {
+    match value {
+        Value::Table(_) => true,
+        Value::Array(values) => all_tables(values),
+        _ => false,
+    }
+}
+
+fn new_item(value: &Value, fresh: Option<&Item>) -> Item {
+    fresh.cloned().unwrap_or_else(|| to_item(value))
+}
+
+fn to_item(value: &Value) -> Item {
+    match value {
+        Value::Table(values) => {
+            let mut table = Table::new();
+            for (key, value) in values {
+                table.insert(key, to_item(value));
+            }
+            Item::Table(table)
+        }
+        Value::Array(values) if all_tables(values) => {
+            let mut array = ArrayOfTables::new();
+            for value in values {
+                if let Item::Table(table) = to_item(value) {
+                    array.push(table);
+                }
+            }
+            Item::ArrayOfTables(array)
+        }
+        value => Item::Value(to_value(value)),
+    }
+}
+
+fn to_value(value: &Value) -> toml_edit::Value {
+    match value {
+        Value::String(s) => s.as_str().into(),
+        Value::Integer(i) => (*i).into(),
+        Value::Float(f) => (*f).into(),
+        Value::Boolean(b) => (*b).into(),
+        Value::Datetime(d) => d
+            .to_string()
+            .parse::<toml_edit::Value>()
+            .unwrap_or_else(|_| d.to_string().into()),
+        Value::Array(values) => values
+            .iter()
+            .map(to_value)
+            .collect::<toml_edit::Array>()
+            .into(),
+        Value::Table(values) => values
+            .iter()
+            .map(|(key, value)| (key.clone(), to_value(value)))
+            .collect::<toml_edit::InlineTable>()
+            .into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::{Deserialize, Serialize};
+
+    use super::patch;
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Point {
+        temp: f64,
+        speed: f64,
+    }
+
+    #[derive(Debug, Deserialize, Serialize)]
+    struct Sample {
+        #[serde(default = "Sample::default_rate")]
+        rate: u64,
+        name: Option<String>,
+        points: Vec<Point>,
+    }
+
+    impl Sample {
+        fn default_rate() -> u64 {
+            4000
+        }
+    }
+
+    #[test]
+    fn untouched_file_is_not_changed() {
+        let original = include_str!("../tests/golden/annotated.toml");
+        let config: Sample = toml::from_str(original).unwrap();
+        assert_eq!(patch(original, &config).unwrap(), original);
+    }
+
+    #[test]
+    fn comments_survive_curve_change() {
+        let original = include_str!("../tests/golden/annotated.toml");
+        let mut config: Sample = toml::from_str(original).unwrap();
+        config.points[1].speed = 70.0;
+        config.points.push(Point {
+            temp: 90.0,
+            speed: 100.0,
+        });
+        config.name = Some(String::from("quiet"));
+        assert_eq!(
+            patch(original, &config).unwrap(),
+            include_str!("../tests/golden/annotated.expected.toml")
+        );
+    }
+
+    #[test]
+    fn removed_points() {
+        let original = include_str!("../tests/golden/annotated.toml");
+        let mut config: Sample = toml::from_str(original).unwrap();
+        config.points.truncate(1);
+        config.rate = 1000;
+        config.name = None;
+        let patched = patch(original, &config).unwrap();
+        // removed value takes its comments with it
+        assert_eq!(
+            patched,
+            "rate = 1000\n\n# idle\n[[points]]\ntemp = 30.0\nspeed = 20.0\n"
+        );
+    }
+}
@@ -2,8 +2,18 @@ use amdgpu::utils::{ensure_config, linear_map};
 use amdgpu::{LogLevel, TempInput};
 use tracing::error;
 
+use crate::edit::{patch, EditError};
+use crate::layers::{drop_in_dir, LayerError, Layered};
+
 pub static DEFAULT_FAN_CONFIG_PATH: &str = "/etc/amdfand/mapping.toml";
 
+/// Name of config file in config directory of
+/// [amdgpu::environment::Environment]
+pub static FAN_CONFIG_FILE: &str = "mapping.toml";
+
+/// Prefix of environment variables which overrides config values
+pub static FAN_CONFIG_ENV_PREFIX: &str = "AMDFAND_";
+
 #[derive(Clone, Copy, Debug, Default, serde::Deserialize, PartialEq, serde::Serialize)]
 pub struct TempPoint {
     pub temp: f64,
@@ -46,6 +56,152 @@ impl UsagePoint {
     }
 }
 
+/// What should be done when temperature can't be read
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, PartialEq, serde::Serialize)]
+pub enum SensorFailureAction {
+    /// Force 100% fan speed
+    #[default]
+    FullSpeed,
+    /// Give fan control back to GPU firmware (`pwm1_enable=2`)
+    Firmware,
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, PartialEq, serde::Serialize)]
+pub struct Failsafe {
+    /// Force full speed when any sensor is this many degrees below its
+    /// critical temperature (`temp*_crit`)
+    #[serde(default = "Failsafe::default_crit_margin")]
+    pub crit_margin: f64,
+    /// Number of consecutive checks with unreadable temperature after which
+    /// `on_sensor_failure` action is taken
+    #[serde(default = "Failsafe::default_max_read_failures")]
+    pub max_read_failures: u32,
+    #[serde(default)]
+    pub on_sensor_failure: SensorFailureAction,
+}
+
+impl Failsafe {
+    fn default_crit_margin() -> f64 {
+        5.0
+    }
+
+    fn default_max_read_failures() -> u32 {
+        3
+    }
+}
+
+impl Default for Failsafe {
+    fn default() -> Self {
+        Self {
+            crit_margin: Self::default_crit_margin(),
+            max_read_failures: Self::default_max_read_failures(),
+            on_sensor_failure: SensorFailureAction::default(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, PartialEq, serde::Serialize)]
+pub struct Stall {
+    #[serde(default = "Stall::default_enabled")]
+    pub enabled: bool,
+    /// Number of consecutive checks with suspicious RPM after which fan is
+    /// considered stalled
+    #[serde(default = "Stall::default_grace_ticks")]
+    pub grace_ticks: u32,
+    /// RPM lower than this fraction of expected RPM (or higher than expected
+    /// divided by it) is suspicious
+    #[serde(default = "Stall::default_rpm_ratio")]
+    pub rpm_ratio: f64,
+    /// Fan speeds (percent) below this value are not checked since fan may
+    /// not spin at all
+    #[serde(default = "Stall::default_min_speed")]
+    pub min_speed: f64,
+    /// Number of full speed pulses before fan control is given back to GPU
+    /// firmware
+    #[serde(default = "Stall::default_kick_start_attempts")]
+    pub kick_start_attempts: u32,
+}
+
+impl Stall {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_grace_ticks() -> u32 {
+        3
+    }
+
+    fn default_rpm_ratio() -> f64 {
+        0.2
+    }
+
+    fn default_min_speed() -> f64 {
+        20.0
+    }
+
+    fn default_kick_start_attempts() -> u32 {
+        1
+    }
+}
+
+impl Default for Stall {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            grace_ticks: Self::default_grace_ticks(),
+            rpm_ratio: Self::default_rpm_ratio(),
+            min_speed: Self::default_min_speed(),
+            kick_start_attempts: Self::default_kick_start_attempts(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, serde::Deserialize, PartialEq, serde::Serialize)]
+pub struct Takeover {
+    #[serde(default = "Takeover::default_enabled")]
+    pub enabled: bool,
+    /// Allowed difference between written and read back modulation, driver
+    /// may round written value
+    #[serde(default = "Takeover::default_pwm_tolerance")]
+    pub pwm_tolerance: u64,
+    /// Number of conflicts in a row after which amdfand stops fighting for
+    /// fan control for a while
+    #[serde(default = "Takeover::default_max_conflicts")]
+    pub max_conflicts: u32,
+    /// Maximal number of ticks without writing to fan when backing off
+    #[serde(default = "Takeover::default_max_backoff_ticks")]
+    pub max_backoff_ticks: u32,
+}
+
+impl Takeover {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_pwm_tolerance() -> u64 {
+        2
+    }
+
+    fn default_max_conflicts() -> u32 {
+        3
+    }
+
+    fn default_max_backoff_ticks() -> u32 {
+        64
+    }
+}
+
+impl Default for Takeover {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            pwm_tolerance: Self::default_pwm_tolerance(),
+            max_conflicts: Self::default_max_conflicts(),
+            max_backoff_ticks: Self::default_max_backoff_ticks(),
+        }
+    }
+}
+
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub struct Config {
     #[serde(skip)]
@@ -60,10 +216,19 @@ pub struct Config {
     cards: Option<Vec<String>>,
     #[serde(default = "Config::default_refresh_delay")]
     update_rate: u64,
+    /// Reload config when config file or drop-in files are changed
+    #[serde(default)]
+    watch_config: bool,
     #[serde(default = "Config::default_temp_matrix")]
     temp_matrix: Vec<TempPoint>,
     #[serde(default = "Config::default_usage_matrix")]
     usage_matrix: Vec<UsagePoint>,
+    #[serde(default)]
+    failsafe: Failsafe,
+    #[serde(default)]
+    stall: Stall,
+    #[serde(default)]
+    takeover: Takeover,
 }
 
 impl Config {
@@ -75,9 +240,73 @@ impl Config {
         self.cards.as_ref()
     }
 
-    pub fn reload(self) -> Result<Config, ConfigError> {
-        let config = load_config(&self.path)?;
-        Ok(config)
+    /// Check if curves are valid
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let monotonical = self.monotonical;
+
+        self.temp_matrix.iter().enumerate().try_fold(
+            Option::<&TempPoint>::None,
+            |last_point, (index, matrix_point)| match (matrix_point.speed, last_point) {
+                (f, _) if f < 0.0f64 => {
+                    error!("Fan speed can't be below 0.0 found {}", matrix_point.speed);
+                    Err(ConfigError::FanSpeedTooLow {
+                        value: matrix_point.speed,
+                        index,
+                    })
+                }
+                (f, _) if f > 100f64 => {
+                    error!(
+                        "Fan speed can't be above 100.0 found {}",
+                        matrix_point.speed
+                    );
+                    Err(ConfigError::FanSpeedTooHigh {
+                        value: matrix_point.speed,
+                        index,
+                    })
+                }
+                (current, Some(last)) if monotonical && current < last.speed => {
+                    error!(
+                        "Curve fan speeds should be monotonically increasing, found {} then {}",
+                        last.speed, matrix_point.speed
+                    );
+
+                    Err(ConfigError::UnsortedFanSpeed {
+                        current: matrix_point.speed,
+                        last: last.speed,
+                        index,
+                    })
+                }
+                (current, Some(last)) if monotonical && current < last.temp => {
+                    error!(
+                        "Curve fan temps should be monotonically increasing, found {} then {}",
+                        last.temp, matrix_point.temp
+                    );
+
+                    Err(ConfigError::UnsortedFanTemp {
+                        current: matrix_point.temp,
+                        last: last.temp,
+                        index,
+                    })
+                }
+                _ => Ok(Some(matrix_point)),
+            },
+        )?;
+        Ok(())
+    }
+
+    /// Serialize config for saving. When config file exists only changed
+    /// values are written so comments and formatting of file are kept
+    pub fn to_toml_preserving(&self) -> Result<String, ConfigError> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(original) => Ok(patch(&original, self)?),
+            Err(_) => Ok(toml::to_string(self).map_err(EditError::from)?),
+        }
+    }
+
+    /// Load config file again. Current config is not changed so it can be
+    /// still used if new one is not valid
+    pub fn reload(&self) -> Result<Config, ConfigError> {
+        load_config(&self.path)
     }
 
     pub fn temp_matrix(&self) -> &[TempPoint] {
@@ -161,6 +390,34 @@ impl Config {
         self.update_rate
     }
 
+    pub fn failsafe(&self) -> &Failsafe {
+        &self.failsafe
+    }
+
+    pub fn failsafe_mut(&mut self) -> &mut Failsafe {
+        &mut self.failsafe
+    }
+
+    pub fn stall(&self) -> &Stall {
+        &self.stall
+    }
+
+    pub fn stall_mut(&mut self) -> &mut Stall {
+        &mut self.stall
+    }
+
+    pub fn watch_config(&self) -> bool {
+        self.watch_config
+    }
+
+    pub fn takeover(&self) -> &Takeover {
+        &self.takeover
+    }
+
+    pub fn takeover_mut(&mut self) -> &mut Takeover {
+        &mut self.takeover
+    }
+
     fn min_speed_for_temp(&self) -> f64 {
         self.temp_matrix.first().map(|p| p.speed).unwrap_or(0f64)
     }
@@ -248,6 +505,10 @@ impl Default for Config {
             temp_input: Some(TempInput(1)),
             usage_matrix: Self::default_usage_matrix(),
             update_rate: Self::default_refresh_delay(),
+            watch_config: false,
+            failsafe: Failsafe::default(),
+            stall: Stall::default(),
+            takeover: Takeover::default(),
         }
     }
 }
@@ -276,61 +537,42 @@ pub enum ConfigError {
     },
     #[error("{0}")]
     Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Layer(#[from] LayerError),
+    #[error("Merged config is not valid. {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("{0}")]
+    Edit(#[from] EditError),
+}
+
+/// Read base config file (it's created if it does not exists), drop-in files
+/// and environment overrides
+pub fn load_layers(config_path: &str) -> Result<Layered, ConfigError> {
+    if !std::path::Path::new(config_path).exists() {
+        ensure_config::<Config, ConfigError, _>(config_path)?;
+    }
+    let mut layers = Layered::default();
+    layers.merge_file(config_path)?;
+    layers.merge_drop_ins(drop_in_dir(config_path))?;
+    layers.merge_env(FAN_CONFIG_ENV_PREFIX, std::env::vars());
+    Ok(layers)
 }
 
 pub fn load_config(config_path: &str) -> Result<Config, ConfigError> {
-    let mut config = ensure_config::<Config, ConfigError, _>(config_path)?;
+    let mut config: Config = load_layers(config_path)?.deserialize()?;
     config.path = String::from(config_path);
+    config.validate()?;
+    Ok(config)
+}
 
-    let monotonical = config.monotonical;
-
-    config.temp_matrix.iter().enumerate().try_fold(
-        Option::<&TempPoint>::None,
-        |last_point, (index, matrix_point)| match (matrix_point.speed, last_point) {
-            (f, _) if f < 0.0f64 => {
-                error!("Fan speed can't be below 0.0 found {}", matrix_point.speed);
-                Err(ConfigError::FanSpeedTooLow {
-                    value: matrix_point.speed,
-                    index,
-                })
-            }
-            (f, _) if f > 100f64 => {
-                error!(
-                    "Fan speed can't be above 100.0 found {}",
-                    matrix_point.speed
-                );
-                Err(ConfigError::FanSpeedTooHigh {
-                    value: matrix_point.speed,
-                    index,
-                })
-            }
-            (current, Some(last)) if monotonical && current < last.speed => {
-                error!(
-                    "Curve fan speeds should be monotonically increasing, found {} then {}",
-                    last.speed, matrix_point.speed
-                );
-
-                Err(ConfigError::UnsortedFanSpeed {
-                    current: matrix_point.speed,
-                    last: last.speed,
-                    index,
-                })
-            }
-            (current, Some(last)) if monotonical && current < last.temp => {
-                error!(
-                    "Curve fan temps should be monotonically increasing, found {} then {}",
-                    last.temp, matrix_point.temp
-                );
-
-                Err(ConfigError::UnsortedFanTemp {
-                    current: matrix_point.temp,
-                    last: last.temp,
-                    index,
-                })
-            }
-            _ => Ok(Some(matrix_point)),
-        },
-    )?;
+/// Load only base config file, without drop-in files and environment
+/// overrides. Used when config is edited so overrides are not written to base
+/// file
+pub fn load_base_config(config_path: &str) -> Result<Config, ConfigError> {
+    let content = std::fs::read_to_string(config_path)?;
+    let mut config: Config = toml::from_str(&content)?;
+    config.path = String::from(config_path);
+    config.validate()?;
     Ok(config)
 }
 
@@ -450,7 +692,7 @@ mod speed_for_temp {
 
 #[cfg(test)]
 mod serde_tests {
-    use crate::fan::Config;
+    use crate::fan::{Config, Failsafe, SensorFailureAction};
 
     #[test]
     fn serialize() {
@@ -463,4 +705,54 @@ mod serde_tests {
         let res = toml::from_str::<Config>(&toml::to_string(&Config::default()).unwrap());
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn failsafe_defaults() {
+        let config = toml::from_str::<Config>(
+            "log_level = 'Error'\n[failsafe]\non_sensor_failure = 'Firmware'",
+        )
+        .unwrap();
+        assert_eq!(
+            config.failsafe(),
+            &Failsafe {
+                crit_margin: 5.0,
+                max_read_failures: 3,
+                on_sensor_failure: SensorFailureAction::Firmware,
+            }
+        );
+    }
+
+    #[test]
+    fn save_keeps_comments() {
+        let dir = tempdir::TempDir::new("fan-config").unwrap();
+        let path = dir.path().join("mapping.toml");
+        std::fs::write(&path, include_str!("../tests/golden/mapping.toml")).unwrap();
+
+        let mut config = crate::fan::load_base_config(path.to_str().unwrap()).unwrap();
+        config.temp_matrix_mut()[1].speed = 60.0;
+        assert_eq!(
+            config.to_toml_preserving().unwrap(),
+            include_str!("../tests/golden/mapping.expected.toml")
+        );
+    }
+
+    #[test]
+    fn save_skips_drop_ins() {
+        let dir = tempdir::TempDir::new("fan-config").unwrap();
+        let path = dir.path().join("mapping.toml");
+        std::fs::write(&path, include_str!("../tests/golden/mapping.toml")).unwrap();
+        let drop_ins = crate::layers::drop_in_dir(&path);
+        std::fs::create_dir_all(&drop_ins).unwrap();
+        std::fs::write(drop_ins.join("10-fast.toml"), "update_rate = 500").unwrap();
+
+        let merged = crate::fan::load_config(path.to_str().unwrap()).unwrap();
+        assert_eq!(merged.update_rate(), 500);
+
+        let mut config = crate::fan::load_base_config(path.to_str().unwrap()).unwrap();
+        assert_eq!(config.update_rate(), 2000);
+        config.temp_matrix_mut()[1].speed = 60.0;
+        let saved = config.to_toml_preserving().unwrap();
+        assert!(!saved.contains("500"));
+        assert_eq!(saved, include_str!("../tests/golden/mapping.expected.toml"));
+    }
 }
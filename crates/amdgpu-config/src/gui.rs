@@ -5,6 +5,8 @@ use amdgpu::LogLevel;
 pub enum ConfigError {
     #[error("{0}")]
     Io(#[from] std::io::Error),
+    #[error("Config file is malformed. {0}")]
+    Parse(#[from] toml::de::Error),
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
@@ -0,0 +1,381 @@
+//! Layered configuration loading.
+//!
+//! Configuration is assembled from a base file, then every `*.toml` file from
+//! drop-in directory (`/etc/amdfand/mapping.d` for
+//! `/etc/amdfand/mapping.toml`) in lexical order and finally from environment
+//! variables with given prefix (ex. `AMDFAND_UPDATE_RATE=1000`).
+//!
+//! Tables are merged deeply, any other value (including arrays) is replaced
+//! by later layer.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use toml::value::Table;
+use toml::Value;
+
+#[derive(Debug, thiserror::Error)]
+pub enum LayerError {
+    #[error("Failed to read config layer {path:?}. {io}")]
+    Io { io: std::io::Error, path: PathBuf },
+    #[error("Config layer {path:?} is not valid toml. {err}")]
+    Parse { err: toml::de::Error, path: PathBuf },
+    #[error("Config layer {path:?} must be a table")]
+    NotTable { path: PathBuf },
+}
+
+/// Place from which value was taken
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    /// Value wasn't set by any layer
+    Default,
+    /// Base file or drop-in file
+    File(PathBuf),
+    /// Environment variable name
+    Env(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Default => f.write_str("default"),
+            Source::File(path) => write!(f, "{}", path.display()),
+            Source::Env(name) => write!(f, "env {}", name),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Layered {
+    table: Table,
+    /// Dotted key path to layer which set it last
+    origins: BTreeMap<String, Source>,
+}
+
+impl Layered {
+    /// Merged table of all layers
+    pub fn table(&self) -> &Table {
+        &self.table
+    }
+
+    /// Deserialize merged layers
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&self) -> Result<T, toml::de::Error> {
+        Value::Table(self.table.clone()).try_into()
+    }
+
+    /// Layer which set value for given dotted key
+    pub fn origin(&self, key: &str) -> &Source {
+        self.origins.get(key).unwrap_or(&Source::Default)
+    }
+
+    /// All distinct layers which set given key or any key nested in it
+    pub fn origins_of(&self, key: &str) -> Vec<&Source> {
+        let nested = format!("{}.", key);
+        self.origins
+            .iter()
+            .filter(|(k, _)| k.as_str() == key || k.starts_with(&nested))
+            .fold(Vec::with_capacity(2), |mut v, (_, source)| {
+                if !v.contains(&source) {
+                    v.push(source);
+                }
+                v
+            })
+    }
+
+    /// Merge table on top of existing layers
+    pub fn merge(&mut self, table: Table, source: Source) {
+        merge_table(&mut self.table, table, "", &source, &mut self.origins);
+    }
+
+    /// Read and merge toml file
+    pub fn merge_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), LayerError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(|io| LayerError::Io {
+            io,
+            path: path.to_path_buf(),
+        })?;
+        let table = match toml::from_str::<Value>(&content) {
+            Ok(Value::Table(table)) => table,
+            Ok(_) => {
+                return Err(LayerError::NotTable {
+                    path: path.to_path_buf(),
+                })
+            }
+            Err(err) => {
+                return Err(LayerError::Parse {
+                    err,
+                    path: path.to_path_buf(),
+                })
+            }
+        };
+        self.merge(table, Source::File(path.to_path_buf()));
+        Ok(())
+    }
+
+    /// Merge all `*.toml` files from directory in lexical order. Missing
+    /// directory is not an error
+    pub fn merge_drop_ins<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), LayerError> {
+        for path in drop_in_files(dir.as_ref())? {
+            self.merge_file(path)?;
+        }
+        Ok(())
+    }
+
+    /// Merge variables which starts with prefix. Name after prefix is
+    /// lowercased and `__` is used as nested table separator, so
+    /// `AMDFAND_UPDATE_RATE` sets `update_rate`. Value is parsed as toml
+    /// value and if it's not valid it's taken as string
+    pub fn merge_env<Vars>(&mut self, prefix: &str, vars: Vars)
+    where
+        Vars: IntoIterator<Item = (String, String)>,
+    {
+        let mut vars = vars
+            .into_iter()
+            .filter(|(name, _)| name.len() > prefix.len() && name.starts_with(prefix))
+            .collect::<Vec<_>>();
+        vars.sort();
+
+        for (name, raw) in vars {
+            let path = name[prefix.len()..]
+                .split("__")
+                .map(str::to_lowercase)
+                .collect::<Vec<_>>();
+            let value = parse_env_value(&raw);
+            let table = path.iter().rev().fold(value, |value, key| {
+                let mut table = Table::new();
+                table.insert(key.clone(), value);
+                Value::Table(table)
+            });
+            if let Value::Table(table) = table {
+                self.merge(table, Source::Env(name));
+            }
+        }
+    }
+}
+
+/// Drop-in directory for given config file (`mapping.toml` -> `mapping.d`)
+pub fn drop_in_dir<P: AsRef<Path>>(config_path: P) -> PathBuf {
+    let path = config_path.as_ref();
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default();
+    path.with_file_name(format!("{}.d", stem))
+}
+
+/// Lexically sorted `*.toml` files from drop-in directory
+pub fn drop_in_files(dir: &Path) -> Result<Vec<PathBuf>, LayerError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(io) if io.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(io) => {
+            return Err(LayerError::Io {
+                io,
+                path: dir.to_path_buf(),
+            })
+        }
+    };
+    let mut files = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("toml"))
+        .collect::<Vec<_>>();
+    files.sort();
+    Ok(files)
+}
+
+fn parse_env_value(raw: &str) -> Value {
+    toml::from_str::<Table>(&format!("value = {}", raw))
+        .ok()
+        .and_then(|mut table| table.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()))
+}
+
+fn merge_table(
+    target: &mut Table,
+    source: Table,
+    prefix: &str,
+    layer: &Source,
+    origins: &mut BTreeMap<String, Source>,
+) {
+    for (key, value) in source {
+        let path = if prefix.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", prefix, key)
+        };
+        match (target.get_mut(&key), value) {
+            (Some(Value::Table(current)), Value::Table(next)) => {
+                merge_table(current, next, &path, layer, origins);
+            }
+            (_, value) => {
+                // replaced value can't keep origins of its previous children
+                let nested = format!("{}.", path);
+                origins.retain(|k, _| !k.starts_with(&nested));
+                if let Value::Table(table) = &value {
+                    mark_nested(table, &path, layer, origins);
+                }
+                origins.insert(path, layer.clone());
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+fn mark_nested(
+    table: &Table,
+    prefix: &str,
+    layer: &Source,
+    origins: &mut BTreeMap<String, Source>,
+) {
+    for (key, value) in table {
+        let path = format!("{}.{}", prefix, key);
+        if let Value::Table(table) = value {
+            mark_nested(table, &path, layer, origins);
+        }
+        origins.insert(path, layer.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use toml::Value;
+
+    use super::*;
+
+    fn table(s: &str) -> Table {
+        toml::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn scalars_are_replaced() {
+        let mut layers = Layered::default();
+        layers.merge(
+            table("update_rate = 4000\nlog_level = 'Error'"),
+            Source::Default,
+        );
+        layers.merge(
+            table("update_rate = 1000"),
+            Source::File(PathBuf::from("10.toml")),
+        );
+        assert_eq!(
+            layers.table().get("update_rate"),
+            Some(&Value::Integer(1000))
+        );
+        assert_eq!(
+            layers.table().get("log_level"),
+            Some(&Value::String("Error".into()))
+        );
+        assert_eq!(
+            layers.origin("update_rate"),
+            &Source::File(PathBuf::from("10.toml"))
+        );
+    }
+
+    #[test]
+    fn arrays_are_replaced() {
+        let mut layers = Layered::default();
+        layers.merge(
+            table("[[temp_matrix]]\ntemp = 1.0\nspeed = 1.0\n[[temp_matrix]]\ntemp = 2.0\nspeed = 2.0"),
+            Source::Default,
+        );
+        layers.merge(
+            table("[[temp_matrix]]\ntemp = 5.0\nspeed = 5.0"),
+            Source::Env("X".into()),
+        );
+        let matrix = layers
+            .table()
+            .get("temp_matrix")
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(matrix.len(), 1);
+        assert_eq!(layers.origin("temp_matrix"), &Source::Env("X".into()));
+    }
+
+    #[test]
+    fn tables_are_merged_deeply() {
+        let mut layers = Layered::default();
+        let base = Source::File(PathBuf::from("base.toml"));
+        let drop_in = Source::File(PathBuf::from("drop-in.toml"));
+        layers.merge(table("[a]\nb = 1\nc = 2\n[a.d]\ne = 3"), base.clone());
+        layers.merge(table("[a]\nc = 20\n[a.d]\nf = 4"), drop_in.clone());
+
+        assert_eq!(
+            layers.table().get("a"),
+            Some(&Value::Table(table("b = 1\nc = 20\n[d]\ne = 3\nf = 4")))
+        );
+        assert_eq!(layers.origin("a.b"), &base);
+        assert_eq!(layers.origin("a.c"), &drop_in);
+        assert_eq!(layers.origin("a.d.e"), &base);
+        assert_eq!(layers.origin("a.d.f"), &drop_in);
+        assert_eq!(layers.origins_of("a"), vec![&base, &drop_in]);
+    }
+
+    #[test]
+    fn env_overrides() {
+        let mut layers = Layered::default();
+        layers.merge(table("update_rate = 4000"), Source::Default);
+        layers.merge_env(
+            "AMDFAND_",
+            vec![
+                ("AMDFAND_UPDATE_RATE".to_string(), "1000".to_string()),
+                ("AMDFAND_LOG_LEVEL".to_string(), "Debug".to_string()),
+                ("AMDFAND_FAILSAFE__MARGIN".to_string(), "2.5".to_string()),
+                ("OTHER_UPDATE_RATE".to_string(), "1".to_string()),
+            ],
+        );
+        assert_eq!(
+            layers.table().get("update_rate"),
+            Some(&Value::Integer(1000))
+        );
+        assert_eq!(
+            layers.table().get("log_level"),
+            Some(&Value::String("Debug".into()))
+        );
+        assert_eq!(
+            layers.table().get("failsafe"),
+            Some(&Value::Table(table("margin = 2.5")))
+        );
+        assert_eq!(
+            layers.origin("update_rate"),
+            &Source::Env("AMDFAND_UPDATE_RATE".into())
+        );
+    }
+
+    #[test]
+    fn drop_ins_are_sorted() {
+        let dir = tempdir::TempDir::new("layers").unwrap();
+        let base = dir.path().join("mapping.toml");
+        let drop_ins = drop_in_dir(&base);
+        assert_eq!(drop_ins, dir.path().join("mapping.d"));
+
+        std::fs::create_dir_all(&drop_ins).unwrap();
+        std::fs::write(&base, "update_rate = 1\nlog_level = 'Error'").unwrap();
+        std::fs::write(drop_ins.join("20-b.toml"), "update_rate = 3").unwrap();
+        std::fs::write(drop_ins.join("10-a.toml"), "update_rate = 2").unwrap();
+        std::fs::write(drop_ins.join("30-c.toml.disabled"), "update_rate = 4").unwrap();
+
+        let mut layers = Layered::default();
+        layers.merge_file(&base).unwrap();
+        layers.merge_drop_ins(&drop_ins).unwrap();
+
+        assert_eq!(layers.table().get("update_rate"), Some(&Value::Integer(3)));
+        assert_eq!(
+            layers.origin("update_rate"),
+            &Source::File(drop_ins.join("20-b.toml"))
+        );
+        assert_eq!(layers.origin("log_level"), &Source::File(base));
+    }
+
+    #[test]
+    fn missing_drop_in_dir() {
+        let mut layers = Layered::default();
+        assert!(layers
+            .merge_drop_ins("/this/directory/does/not/exists.d")
+            .is_ok());
+    }
+}
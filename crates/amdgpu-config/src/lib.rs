@@ -1,7 +1,9 @@
+pub mod edit;
 #[cfg(feature = "fan")]
 pub mod fan;
 #[cfg(feature = "gui")]
 pub mod gui;
+pub mod layers;
 #[cfg(feature = "monitor")]
 pub mod monitor;
 #[cfg(feature = "voltage")]
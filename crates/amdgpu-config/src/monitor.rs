@@ -5,6 +5,10 @@ use tracing::warn;
 
 pub static DEFAULT_MONITOR_CONFIG_PATH: &str = "/etc/amdfand/monitor.toml";
 
+/// Name of config file in config directory of
+/// [amdgpu::environment::Environment]
+pub static MONITOR_CONFIG_FILE: &str = "monitor.toml";
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Config {
     /// Minimal log level
@@ -17,12 +21,15 @@ pub struct Config {
 pub enum ConfigError {
     #[error("{0}")]
     Io(#[from] std::io::Error),
+    #[error("Config file is malformed. {0}")]
+    Parse(#[from] toml::de::Error),
 }
 
 impl ConfigError {
     pub fn into_io(self) -> std::io::Error {
         match self {
             ConfigError::Io(io) => io,
+            ConfigError::Parse(e) => std::io::Error::new(std::io::ErrorKind::InvalidData, e),
         }
     }
 }
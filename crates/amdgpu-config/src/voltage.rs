@@ -1,21 +1,84 @@
 use amdgpu::utils::ensure_config;
-use amdgpu::LogLevel;
+use amdgpu::{Card, LogLevel};
+
+use crate::edit::{patch, EditError};
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
     #[error("{0}")]
     Io(#[from] std::io::Error),
+    #[error("Config file is malformed. {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("{0}")]
+    Edit(#[from] EditError),
+    #[error("Profile {profile:?} has {module} minimal clock {min} higher than maximal {max}")]
+    InvalidRange {
+        profile: String,
+        module: &'static str,
+        min: u32,
+        max: u32,
+    },
+    #[error("Profile {profile:?} has curve point {index} with frequency lower than previous one")]
+    UnsortedCurve { profile: String, index: usize },
+    #[error("Profile {profile:?} is defined more than once for {card}")]
+    DuplicatedProfile { profile: String, card: String },
+}
+
+/// Overdrive clock states, `min` is state 0 and `max` is state 1 (MHz)
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct ClockRange {
+    pub min: Option<u32>,
+    pub max: Option<u32>,
+}
+
+/// Point of `OD_VDDC_CURVE`
+#[derive(Clone, Debug, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct CurvePoint {
+    /// MHz
+    pub freq: u32,
+    /// mV
+    pub voltage: u32,
+}
+
+/// Named set of overclocking settings. Profile without `card` is used for
+/// every card which has no profile with same name
+#[derive(Clone, Debug, Default, PartialEq, serde::Deserialize, serde::Serialize)]
+pub struct Profile {
+    pub name: String,
+    #[serde(default)]
+    pub card: Option<Card>,
+    /// `OD_VDDGFX_OFFSET` in mV
+    #[serde(default)]
+    pub voltage_offset: Option<i32>,
+    /// Power limit in W
+    #[serde(default)]
+    pub power_cap: Option<u32>,
+    /// `OD_SCLK`
+    #[serde(default)]
+    pub engine: Option<ClockRange>,
+    /// `OD_MCLK`
+    #[serde(default)]
+    pub memory: Option<ClockRange>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub curve: Vec<CurvePoint>,
 }
 
 #[derive(serde::Deserialize, serde::Serialize)]
 pub struct Config {
     log_level: LogLevel,
+    /// Profile applied when none was given
+    #[serde(default)]
+    profile: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    profiles: Vec<Profile>,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             log_level: LogLevel::Error,
+            profile: None,
+            profiles: vec![],
         }
     }
 }
@@ -24,15 +87,104 @@ impl Config {
     pub fn log_level(&self) -> LogLevel {
         self.log_level
     }
+
+    pub fn default_profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    pub fn profiles(&self) -> &[Profile] {
+        &self.profiles
+    }
+
+    /// Profile with given name for card, profile for this card wins over one
+    /// for all cards
+    pub fn profile_for(&self, name: &str, card: &Card) -> Option<&Profile> {
+        let mut named = self.profiles.iter().filter(|p| p.name == name);
+        named
+            .clone()
+            .find(|p| p.card.as_ref() == Some(card))
+            .or_else(|| named.find(|p| p.card.is_none()))
+    }
+
+    /// Add profile, profile with same name and card is replaced
+    pub fn set_profile(&mut self, profile: Profile) {
+        match self
+            .profiles
+            .iter_mut()
+            .find(|p| p.name == profile.name && p.card == profile.card)
+        {
+            Some(existing) => *existing = profile,
+            None => self.profiles.push(profile),
+        }
+    }
+
+    /// Serialize config for saving. When config file exists only changed
+    /// values are written so comments and formatting of file are kept
+    pub fn to_toml_preserving(&self, path: &str) -> Result<String, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(original) => Ok(patch(&original, self)?),
+            Err(_) => Ok(toml::to_string(self).map_err(EditError::from)?),
+        }
+    }
+
+    pub fn has_profile(&self, name: &str) -> bool {
+        self.profiles.iter().any(|p| p.name == name)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        for (idx, profile) in self.profiles.iter().enumerate() {
+            for (module, range) in [("engine", &profile.engine), ("memory", &profile.memory)] {
+                if let Some(ClockRange {
+                    min: Some(min),
+                    max: Some(max),
+                }) = range
+                {
+                    if min > max {
+                        return Err(ConfigError::InvalidRange {
+                            profile: profile.name.clone(),
+                            module,
+                            min: *min,
+                            max: *max,
+                        });
+                    }
+                }
+            }
+            if let Some(index) = (1..profile.curve.len())
+                .find(|index| profile.curve[*index].freq < profile.curve[index - 1].freq)
+            {
+                return Err(ConfigError::UnsortedCurve {
+                    profile: profile.name.clone(),
+                    index,
+                });
+            }
+            if self.profiles[..idx]
+                .iter()
+                .any(|other| other.name == profile.name && other.card == profile.card)
+            {
+                return Err(ConfigError::DuplicatedProfile {
+                    profile: profile.name.clone(),
+                    card: profile
+                        .card
+                        .map(|card| card.to_string())
+                        .unwrap_or_else(|| String::from("all cards")),
+                });
+            }
+        }
+        Ok(())
+    }
 }
 
 pub fn load_config(config_path: &str) -> Result<Config, ConfigError> {
-    ensure_config::<Config, ConfigError, _>(config_path)
+    let config = ensure_config::<Config, ConfigError, _>(config_path)?;
+    config.validate()?;
+    Ok(config)
 }
 
 #[cfg(test)]
 mod serde_tests {
-    use crate::voltage::Config;
+    use amdgpu::Card;
+
+    use crate::voltage::{Config, ConfigError};
 
     #[test]
     fn serialize() {
@@ -45,4 +197,107 @@ mod serde_tests {
         let res = toml::from_str::<Config>(&toml::to_string(&Config::default()).unwrap());
         assert!(res.is_ok());
     }
+
+    #[test]
+    fn profiles() {
+        let config = toml::from_str::<Config>(
+            r#"
+log_level = 'Error'
+profile = 'undervolt'
+
+[[profiles]]
+name = 'undervolt'
+voltage_offset = -50
+power_cap = 200
+
+[profiles.engine]
+max = 2400
+
+[[profiles]]
+name = 'undervolt'
+card = 'card1'
+
+[[profiles.curve]]
+freq = 800
+voltage = 706
+
+[[profiles.curve]]
+freq = 2100
+voltage = 1100
+"#,
+        )
+        .unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.default_profile(), Some("undervolt"));
+        let all = config.profile_for("undervolt", &Card(0)).unwrap();
+        assert_eq!(all.voltage_offset, Some(-50));
+        assert_eq!(all.engine.as_ref().unwrap().max, Some(2400));
+        let card1 = config.profile_for("undervolt", &Card(1)).unwrap();
+        assert_eq!(card1.curve.len(), 2);
+        assert!(config.profile_for("stock", &Card(0)).is_none());
+
+        let written = toml::to_string(&config).unwrap();
+        let config = toml::from_str::<Config>(&written).unwrap();
+        assert_eq!(config.profiles().len(), 2);
+    }
+
+    #[test]
+    fn invalid_profiles() {
+        let config = toml::from_str::<Config>(
+            "log_level = 'Error'\n[[profiles]]\nname = 'a'\n[profiles.memory]\nmin = 1000\nmax = 900",
+        )
+        .unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::InvalidRange {
+                module: "memory",
+                ..
+            })
+        ));
+
+        let config = toml::from_str::<Config>(
+            "log_level = 'Error'\n[[profiles]]\nname = 'a'\n[[profiles]]\nname = 'a'",
+        )
+        .unwrap();
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::DuplicatedProfile { .. })
+        ));
+    }
+
+    #[test]
+    fn set_profile_keeps_comments() {
+        let dir = tempdir::TempDir::new("voltage-config").unwrap();
+        let path = dir.path().join("voltage.toml");
+        let original =
+            "# undervolt of main card\nlog_level = 'Error'\n\n[[profiles]]\nname = 'stock'\n";
+        std::fs::write(&path, original).unwrap();
+        let path = path.to_str().unwrap();
+
+        let mut config = crate::voltage::load_config(path).unwrap();
+        config.set_profile(crate::voltage::Profile {
+            name: String::from("tuned"),
+            card: Some(Card(0)),
+            voltage_offset: Some(-60),
+            ..Default::default()
+        });
+        config.set_profile(crate::voltage::Profile {
+            name: String::from("tuned"),
+            card: Some(Card(0)),
+            voltage_offset: Some(-70),
+            ..Default::default()
+        });
+        let written = config.to_toml_preserving(path).unwrap();
+        assert!(written.starts_with("# undervolt of main card\n"));
+
+        let config = toml::from_str::<Config>(&written).unwrap();
+        assert_eq!(config.profiles().len(), 2);
+        assert_eq!(
+            config
+                .profile_for("tuned", &Card(0))
+                .unwrap()
+                .voltage_offset,
+            Some(-70)
+        );
+    }
 }
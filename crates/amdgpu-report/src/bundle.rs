@@ -0,0 +1,193 @@
+//! Captured archive unpacked to temporary directory.
+
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use amdgpu::environment::Environment;
+use amdgpu::hw_mon::{HwMon, HwMonName, RootPath};
+use amdgpu::Card;
+use tempdir::TempDir;
+
+use crate::manifest::{Manifest, FORMAT_VERSION, MANIFEST_FILE};
+use crate::{ReportError, Result};
+
+/// sysfs of unpacked bundle, keeps bundle directory alive. Writes change
+/// only unpacked copy
+#[derive(Clone, Debug)]
+pub struct BundleRoot {
+    drm_dir: PathBuf,
+    _dir: Arc<TempDir>,
+}
+
+impl RootPath for BundleRoot {
+    fn root_dir(&self) -> PathBuf {
+        self.drm_dir.clone()
+    }
+
+    fn device_dir(&self, card: &Card) -> PathBuf {
+        self.root_dir().join(card.to_string()).join("device")
+    }
+
+    fn mon_dir(&self, card: &Card, name: &HwMonName) -> PathBuf {
+        self.device_dir(card).join("hwmon").join(name.as_str())
+    }
+}
+
+pub struct Bundle {
+    dir: Arc<TempDir>,
+    env: Environment,
+    manifest: Manifest,
+}
+
+impl Bundle {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = std::fs::File::open(&path).map_err(|io| ReportError::Io {
+            io,
+            path: path.as_ref().to_path_buf(),
+        })?;
+        Self::read(file)
+    }
+
+    pub fn read<R: Read>(archive: R) -> Result<Self> {
+        let dir = TempDir::new("amdgpu-bundle").map_err(|io| ReportError::Io {
+            io,
+            path: std::env::temp_dir(),
+        })?;
+        tar::Archive::new(archive)
+            .unpack(dir.path())
+            .map_err(ReportError::Archive)?;
+        let manifest = match std::fs::read_to_string(dir.path().join(MANIFEST_FILE)) {
+            Ok(content) => parse_manifest(&content)?,
+            Err(_) => return Err(ReportError::NoManifest),
+        };
+        Ok(Self {
+            env: Environment::under(dir.path()),
+            dir: Arc::new(dir),
+            manifest,
+        })
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    /// Locations inside unpacked bundle, can be passed to services and tools
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    pub fn root(&self) -> BundleRoot {
+        BundleRoot {
+            drm_dir: self.env.drm_dir(),
+            _dir: self.dir.clone(),
+        }
+    }
+
+    /// First hwmon of captured card
+    pub fn hw_mon(&self, card: Card) -> Result<HwMon<BundleRoot>> {
+        let name = card.to_string();
+        let hw_mon = self
+            .manifest
+            .cards
+            .iter()
+            .find(|info| info.card == name)
+            .and_then(|info| info.hw_mons.first())
+            .ok_or(ReportError::NoCard(name))?;
+        Ok(HwMon::new(&card, HwMonName(hw_mon.clone()), self.root()))
+    }
+}
+
+/// Read only manifest, archive is not unpacked
+pub fn read_manifest<R: Read>(archive: R) -> Result<Manifest> {
+    let mut archive = tar::Archive::new(archive);
+    let mut entries = archive.entries().map_err(ReportError::Archive)?;
+    let mut entry = match entries.next() {
+        Some(entry) => entry.map_err(ReportError::Archive)?,
+        None => return Err(ReportError::NoManifest),
+    };
+    if entry.path().map_err(ReportError::Archive)?.as_ref() != Path::new(MANIFEST_FILE) {
+        return Err(ReportError::NoManifest);
+    }
+    let mut content = String::new();
+    entry
+        .read_to_string(&mut content)
+        .map_err(ReportError::Archive)?;
+    parse_manifest(&content)
+}
+
+fn parse_manifest(content: &str) -> Result<Manifest> {
+    let manifest: Manifest = toml::from_str(content)?;
+    if manifest.format > FORMAT_VERSION {
+        return Err(ReportError::UnsupportedFormat(manifest.format));
+    }
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu::utils::hw_mons;
+    use amdgpu::Card;
+    use amdgpu_testkit::FakeGpu;
+
+    use super::{read_manifest, Bundle};
+    use crate::capture::{capture, REDACTED_VALUE};
+
+    fn fake_system() -> FakeGpu {
+        let gpu = FakeGpu::navi21()
+            .with_temp("edge", 45.0)
+            .with_fan(128, 1500)
+            .with_connector("DP", 1, true)
+            .with_card(1);
+        gpu.set_device_file("serial_number", "0x0123456789abcdef");
+        let env = gpu.env();
+        let parameters = env.sysfs_root.join("module/amdgpu/parameters");
+        std::fs::create_dir_all(&parameters).unwrap();
+        std::fs::write(parameters.join("ppfeaturemask"), "0xfff7ffff\n").unwrap();
+        std::fs::create_dir_all(env.procfs_root.join("sys/kernel")).unwrap();
+        std::fs::write(env.procfs_root.join("sys/kernel/osrelease"), "6.9.1-arch1-1\n").unwrap();
+        std::fs::write(env.fan_state_dir().join("default.pid"), "4242").unwrap();
+        gpu
+    }
+
+    #[test]
+    fn capture_and_replay() {
+        let gpu = fake_system();
+        let mut archive = vec![];
+        let manifest = capture(gpu.env(), &mut archive).unwrap();
+        assert_eq!(manifest.kernel.as_deref(), Some("6.9.1-arch1-1"));
+        assert_eq!(manifest.ppfeaturemask.as_deref(), Some("0xfff7ffff"));
+        assert_eq!(manifest.cards.len(), 1);
+        assert_eq!(manifest.cards[0].card, "card1");
+        assert_eq!(manifest.cards[0].device.as_deref(), Some("0x73bf"));
+        assert_eq!(manifest.cards[0].hw_mons, vec!["hwmon0"]);
+        assert_eq!(manifest.cards[0].connectors, vec!["card1-DP-1"]);
+        assert_eq!(
+            manifest.redacted,
+            vec!["sys/class/drm/card1/device/serial_number"]
+        );
+        assert!(manifest
+            .files
+            .contains(&"var/lib/amdfand/default.pid".to_string()));
+
+        assert_eq!(read_manifest(archive.as_slice()).unwrap(), manifest);
+
+        let bundle = Bundle::read(archive.as_slice()).unwrap();
+        let hw_mon = bundle.hw_mon(Card(1)).unwrap();
+        assert_eq!(hw_mon.hw_mon_read("temp1_input").unwrap(), "45000");
+        assert_eq!(hw_mon.hw_mon_read("pwm1").unwrap(), "128");
+        assert_eq!(
+            hw_mon.device_read("serial_number").unwrap(),
+            REDACTED_VALUE.trim()
+        );
+        assert!(bundle.hw_mon(Card(0)).is_err());
+
+        // bundle environment works with code reading real sysfs
+        let env = bundle.env();
+        let mons = hw_mons(env, true).unwrap();
+        assert_eq!(mons.len(), 1);
+        assert!(mons[0].is_amd());
+        let status = env.drm_dir().join("card1-DP-1").join("status");
+        assert_eq!(std::fs::read_to_string(status).unwrap(), "connected");
+    }
+}
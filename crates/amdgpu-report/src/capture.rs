@@ -0,0 +1,234 @@
+//! Copy files of AMD GPUs into tar archive.
+
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use amdgpu::environment::Environment;
+use amdgpu::utils::read_cards;
+use tar::{Builder, EntryType, Header};
+
+use crate::manifest::{CardInfo, Manifest, Skipped, FORMAT_VERSION, MANIFEST_FILE};
+use crate::{ReportError, Result};
+
+/// Larger files are not sysfs attributes and are skipped
+pub const MAX_FILE_SIZE: usize = 1024 * 1024;
+/// Content of redacted files
+pub static REDACTED_VALUE: &str = "<redacted>\n";
+/// Files which are never read, they are binary dumps or have side effects
+static EXCLUDED: &[&str] = &["rom", "config", "remove", "rescan", "reset"];
+/// Files which identify hardware or attached monitors
+static REDACTED: &[&str] = &["serial_number", "unique_id", "fru_id", "edid"];
+/// State directories of services with pid files
+static STATE_DIRS: &[&str] = &["amdfand", "amdgui"];
+
+enum Entry {
+    File { path: String, contents: Vec<u8> },
+    Link { path: String, target: String },
+}
+
+struct Collector {
+    entries: Vec<Entry>,
+    manifest: Manifest,
+}
+
+/// Capture all AMD GPUs found in given environment into `out`
+pub fn capture<W: Write>(env: &Environment, out: W) -> Result<Manifest> {
+    let mut collector = Collector {
+        entries: vec![],
+        manifest: Manifest {
+            format: FORMAT_VERSION,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default(),
+            ..Default::default()
+        },
+    };
+    collector.cards(env);
+    collector.system(env);
+    collector.write(out)?;
+    Ok(collector.manifest)
+}
+
+impl Collector {
+    fn cards(&mut self, env: &Environment) {
+        let drm_dir = env.drm_dir();
+        // missing driver is also worth reporting
+        let cards = match read_cards(env) {
+            Ok(cards) => cards,
+            Err(e) => return self.skip("sys/class/drm", &e.to_string()),
+        };
+        for card in cards {
+            let card = card.to_string();
+            let device_dir = drm_dir.join(&card).join("device");
+            if read_trimmed(&device_dir.join("vendor")).as_deref() != Some("0x1002") {
+                continue;
+            }
+            let archive_dir = format!("sys/class/drm/{}", card);
+            self.copy_dir(&device_dir, &format!("{}/device", archive_dir));
+
+            let mut info = CardInfo {
+                device: read_trimmed(&device_dir.join("device")),
+                card: card.clone(),
+                ..Default::default()
+            };
+            for name in dir_names(&device_dir.join("hwmon"), |name| name.starts_with("hwmon")) {
+                self.copy_dir(
+                    &device_dir.join("hwmon").join(&name),
+                    &format!("{}/device/hwmon/{}", archive_dir, name),
+                );
+                info.hw_mons.push(name);
+            }
+            // connectors are links to card directory like in sysfs
+            let prefix = format!("{}-", card);
+            for name in dir_names(&drm_dir, |name| name.starts_with(&prefix)) {
+                self.copy_dir(&drm_dir.join(&name), &format!("{}/{}", archive_dir, name));
+                self.entries.push(Entry::Link {
+                    path: format!("sys/class/drm/{}", name),
+                    target: format!("{}/{}", card, name),
+                });
+                info.connectors.push(name);
+            }
+            self.manifest.cards.push(info);
+        }
+    }
+
+    fn system(&mut self, env: &Environment) {
+        let parameters = env
+            .sysfs_root
+            .join("module")
+            .join("amdgpu")
+            .join("parameters");
+        self.copy_dir(&parameters, "sys/module/amdgpu/parameters");
+        self.manifest.ppfeaturemask = read_trimmed(&parameters.join("ppfeaturemask"));
+
+        let osrelease = env.procfs_root.join("sys").join("kernel").join("osrelease");
+        self.copy_file(&env.procfs_root.join("version"), "proc/version");
+        self.copy_file(&osrelease, "proc/sys/kernel/osrelease");
+        self.manifest.kernel = read_trimmed(&osrelease);
+
+        for name in STATE_DIRS {
+            self.copy_dir(&env.state_dir.join(name), &format!("var/lib/{}", name));
+        }
+    }
+
+    /// Copy regular files of directory, links and sub directories are
+    /// skipped
+    fn copy_dir(&mut self, dir: &Path, archive_dir: &str) {
+        let mut files: Vec<_> = match std::fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.file_type().map(|ty| ty.is_file()).unwrap_or_default())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .collect(),
+            Err(_) => return,
+        };
+        files.sort();
+        for name in files {
+            self.copy_file(&dir.join(&name), &format!("{}/{}", archive_dir, name));
+        }
+    }
+
+    fn copy_file(&mut self, path: &Path, archive_path: &str) {
+        let name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        if EXCLUDED.contains(&name) || name.starts_with("resource") {
+            return self.skip(archive_path, "excluded");
+        }
+        if !path.exists() {
+            return;
+        }
+        let contents = if REDACTED.contains(&name) {
+            self.manifest.redacted.push(archive_path.to_string());
+            REDACTED_VALUE.as_bytes().to_vec()
+        } else {
+            match std::fs::read(path) {
+                Ok(contents) if contents.len() > MAX_FILE_SIZE => {
+                    return self.skip(archive_path, "too large");
+                }
+                Ok(contents) => contents,
+                // write only attributes and disabled features (ex. overdrive)
+                Err(e) => return self.skip(archive_path, &e.to_string()),
+            }
+        };
+        self.manifest.files.push(archive_path.to_string());
+        self.entries.push(Entry::File {
+            path: archive_path.to_string(),
+            contents,
+        });
+    }
+
+    fn skip(&mut self, archive_path: &str, reason: &str) {
+        self.manifest.skipped.push(Skipped {
+            path: archive_path.to_string(),
+            reason: reason.to_string(),
+        });
+    }
+
+    fn write<W: Write>(&self, out: W) -> Result<()> {
+        let mut builder = Builder::new(out);
+        let manifest = toml::to_string(&self.manifest)?;
+        let mtime = self.manifest.created;
+        append_file(&mut builder, MANIFEST_FILE, manifest.as_bytes(), mtime)?;
+        for entry in self.entries.iter() {
+            match entry {
+                Entry::File { path, contents } => append_file(&mut builder, path, contents, mtime)?,
+                Entry::Link { path, target } => {
+                    let mut header = Header::new_gnu();
+                    header.set_entry_type(EntryType::Symlink);
+                    header.set_size(0);
+                    header.set_mode(0o777);
+                    header.set_mtime(mtime);
+                    builder
+                        .append_link(&mut header, path, target)
+                        .map_err(ReportError::Archive)?;
+                }
+            }
+        }
+        builder
+            .into_inner()
+            .and_then(|mut out| out.flush())
+            .map_err(ReportError::Archive)
+    }
+}
+
+fn append_file<W: Write>(
+    builder: &mut Builder<W>,
+    path: &str,
+    contents: &[u8],
+    mtime: u64,
+) -> Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(contents.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    builder
+        .append_data(&mut header, path, contents)
+        .map_err(ReportError::Archive)
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+/// Sorted names of entries (files, directories or links) matching filter
+fn dir_names<F: Fn(&str) -> bool>(dir: &Path, filter: F) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.file_name().to_string_lossy().into_owned())
+                .filter(|name| filter(name))
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReportError {
+    #[error("{0}")]
+    AmdGpu(#[from] amdgpu::AmdGpuError),
+    #[error("{0}")]
+    AmdUtils(#[from] amdgpu::utils::AmdGpuError),
+    #[error("Failed to access {path:?}. {io}")]
+    Io { io: std::io::Error, path: PathBuf },
+    #[error("Invalid bundle archive. {0}")]
+    Archive(std::io::Error),
+    #[error("Bundle has no manifest.toml")]
+    NoManifest,
+    #[error("Invalid bundle manifest. {0}")]
+    Manifest(#[from] toml::de::Error),
+    #[error("Failed to serialize manifest. {0}")]
+    SerializeManifest(#[from] toml::ser::Error),
+    #[error("Bundle format {0} is not supported, upgrade amdgpu-report")]
+    UnsupportedFormat(u32),
+    #[error("Card {0} is not present in bundle")]
+    NoCard(String),
+}
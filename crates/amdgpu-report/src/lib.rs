@@ -0,0 +1,20 @@
+//! Diagnostic bundle with sysfs layout of AMD GPUs.
+//!
+//! [capture::capture] copies readable files of every amdgpu device, its
+//! hwmon directories and connectors, amdgpu module parameters, kernel version
+//! and services state into tar archive. Paths inside archive are relative to
+//! system root (`sys/class/drm/card0/device/...`, `proc/version`,
+//! `var/lib/amdfand/...`) and `manifest.toml` describes content.
+//!
+//! [bundle::Bundle] unpacks archive and exposes it as
+//! [amdgpu::environment::Environment] and [amdgpu::hw_mon::RootPath], so
+//! captured bundles can be used as test fixtures.
+
+pub use error::*;
+
+pub mod bundle;
+pub mod capture;
+mod error;
+pub mod manifest;
+
+pub type Result<T> = std::result::Result<T, ReportError>;
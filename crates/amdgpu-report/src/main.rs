@@ -0,0 +1,158 @@
+use amdgpu::environment::{Environment, Overrides};
+use amdgpu_report::bundle::read_manifest;
+use amdgpu_report::capture::capture;
+use amdgpu_report::manifest::Manifest;
+use amdgpu_report::{ReportError, Result};
+use gumdrop::Options;
+use tracing::error;
+use tracing_subscriber::EnvFilter;
+
+#[derive(Debug, Options)]
+pub struct Capture {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Archive location, by default amdgpu-report-<time>.tar")]
+    output: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct Inspect {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(free, help = "Archive created by capture")]
+    bundle: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub enum Command {
+    #[options(help = "Copy GPU files into tar archive")]
+    Capture(Capture),
+    #[options(help = "Print manifest of archive")]
+    Inspect(Inspect),
+}
+
+#[derive(Options)]
+pub struct Opts {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Print version")]
+    version: bool,
+    #[options(
+        no_short,
+        help = "sysfs mount point, by default /sys (env AMDGPU_SYSFS_ROOT)"
+    )]
+    sysfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "procfs mount point, by default /proc (env AMDGPU_PROCFS_ROOT)"
+    )]
+    procfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "State directory, by default /var/lib (env AMDGPU_STATE_DIR)"
+    )]
+    state_dir: Option<String>,
+    #[options(command)]
+    command: Option<Command>,
+}
+
+impl Opts {
+    fn environment(&self) -> Environment {
+        Environment::from_env().with_overrides(Overrides {
+            sysfs_root: self.sysfs_root.clone(),
+            procfs_root: self.procfs_root.clone(),
+            state_dir: self.state_dir.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+fn run_capture(command: Capture, env: &Environment) -> Result<()> {
+    let output = command.output.unwrap_or_else(|| {
+        let secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        format!("amdgpu-report-{}.tar", secs)
+    });
+    let file = std::fs::File::create(&output).map_err(|io| ReportError::Io {
+        io,
+        path: output.clone().into(),
+    })?;
+    let manifest = capture(env, std::io::BufWriter::new(file))?;
+    print!("{}", summary(&manifest));
+    println!("Saved to {}", output);
+    Ok(())
+}
+
+fn run_inspect(command: Inspect) -> Result<()> {
+    let Some(path) = command.bundle else {
+        eprintln!("{}", Inspect::usage());
+        return Ok(());
+    };
+    let file = std::fs::File::open(&path).map_err(|io| ReportError::Io {
+        io,
+        path: path.into(),
+    })?;
+    let manifest = read_manifest(file)?;
+    print!("{}", summary(&manifest));
+    for skipped in manifest.skipped.iter() {
+        println!("  skipped {}: {}", skipped.path, skipped.reason);
+    }
+    Ok(())
+}
+
+fn summary(manifest: &Manifest) -> String {
+    let unknown = || String::from("unknown");
+    let mut buffer = format!(
+        "Format {} (amdgpu-report {})\nKernel {}\nppfeaturemask {}\n",
+        manifest.format,
+        manifest.tool_version,
+        manifest.kernel.clone().unwrap_or_else(unknown),
+        manifest.ppfeaturemask.clone().unwrap_or_else(unknown),
+    );
+    for card in manifest.cards.iter() {
+        buffer.push_str(&format!(
+            "{} device {} hwmon [{}] connectors [{}]\n",
+            card.card,
+            card.device.clone().unwrap_or_else(unknown),
+            card.hw_mons.join(", "),
+            card.connectors.join(", "),
+        ));
+    }
+    buffer.push_str(&format!(
+        "{} files, {} redacted, {} skipped\n",
+        manifest.files.len(),
+        manifest.redacted.len(),
+        manifest.skipped.len()
+    ));
+    buffer
+}
+
+fn main() {
+    if std::env::var("RUST_LOG").is_err() {
+        std::env::set_var("RUST_LOG", "WARN");
+    }
+    tracing_subscriber::fmt::fmt()
+        .with_env_filter(EnvFilter::from_default_env())
+        .init();
+
+    let opts: Opts = Opts::parse_args_default_or_exit();
+    if opts.version {
+        println!("amdgpu-report {}", env!("CARGO_PKG_VERSION"));
+        std::process::exit(0);
+    }
+    let env = opts.environment();
+    let result = match opts.command {
+        None => {
+            eprintln!("{}", Opts::usage());
+            Ok(())
+        }
+        Some(Command::Capture(command)) => run_capture(command, &env),
+        Some(Command::Inspect(command)) => run_inspect(command),
+    };
+    if let Err(e) = result {
+        error!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,51 @@
+use serde::{Deserialize, Serialize};
+
+/// Version of archive layout, bundles with newer format are rejected
+pub const FORMAT_VERSION: u32 = 1;
+/// Name of manifest, always first entry of archive
+pub static MANIFEST_FILE: &str = "manifest.toml";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct Manifest {
+    pub format: u32,
+    pub tool_version: String,
+    /// Seconds since UNIX epoch
+    pub created: u64,
+    /// `/proc/sys/kernel/osrelease`
+    pub kernel: Option<String>,
+    /// `amdgpu.ppfeaturemask` module parameter
+    pub ppfeaturemask: Option<String>,
+    /// Archive paths of captured files
+    #[serde(default)]
+    pub files: Vec<String>,
+    /// Archive paths of files which content was replaced
+    #[serde(default)]
+    pub redacted: Vec<String>,
+    // tables have to be serialized after plain values, empty list would be
+    // serialized as value
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cards: Vec<CardInfo>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<Skipped>,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct CardInfo {
+    /// ex. `card0`
+    pub card: String,
+    /// PCI device id
+    pub device: Option<String>,
+    /// ex. `hwmon2`
+    #[serde(default)]
+    pub hw_mons: Vec<String>,
+    /// ex. `card0-DP-1`
+    #[serde(default)]
+    pub connectors: Vec<String>,
+}
+
+/// File which exists but was not captured
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Skipped {
+    pub path: String,
+    pub reason: String,
+}
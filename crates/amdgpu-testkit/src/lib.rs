@@ -0,0 +1,545 @@
+//! Fake sysfs for tests of AMDGPU tools.
+//!
+//! [FakeGpu] creates temporary directory tree laid out like real system
+//! (`sys/class/drm/card0` links to PCI device with `hwmon`, `pp_*` files and
+//! connectors). [Environment] of fixture points services to this tree, so
+//! code under test can be used unchanged. Sensors can be changed between
+//! calls and every write done through [FakeRoot] is recorded.
+//!
+//! ```
+//! use amdgpu_testkit::FakeGpu;
+//!
+//! let gpu = FakeGpu::navi21().with_temp("edge", 45.0).with_fan(128, 1500);
+//! let hw_mon = gpu.hw_mon();
+//! hw_mon.hw_mon_write("pwm1", 200).unwrap();
+//! gpu.set_temp("edge", 60.0);
+//!
+//! assert_eq!(gpu.mon_file("temp1_input").as_deref(), Some("60000"));
+//! assert_eq!(gpu.writes_to("pwm1"), vec!["200".to_string()]);
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use amdgpu::environment::Environment;
+use amdgpu::hw_mon::{HwMon, HwMonName, RootPath};
+use amdgpu::{
+    utils, Card, FAN_INPUT, FAN_MAX, FAN_MIN, PULSE_WIDTH_MODULATION, PULSE_WIDTH_MODULATION_AUTO,
+    PULSE_WIDTH_MODULATION_MAX, PULSE_WIDTH_MODULATION_MIN, PULSE_WIDTH_MODULATION_MODE,
+};
+use tempdir::TempDir;
+
+pub use crate::model::Model;
+
+mod model;
+
+/// PCI address of fake GPU
+static PCI_SLOT: &str = "0000:03:00.0";
+static HW_MON: &str = "hwmon0";
+const MAX_TEMPS: u8 = 16;
+
+/// Single write performed by code under test
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Write {
+    pub path: PathBuf,
+    pub value: String,
+}
+
+type Writes = Arc<Mutex<Vec<Write>>>;
+/// `EINVAL`, error of sysfs for rejected writes
+const EINVAL: i32 = 22;
+
+/// File name and value
+type Rejected = Arc<Mutex<Vec<(String, String)>>>;
+
+/// sysfs layout of [amdgpu::hw_mon::SysFs] which records every write
+#[derive(Clone, Debug)]
+pub struct FakeRoot {
+    drm_dir: PathBuf,
+    writes: Writes,
+    rejected: Rejected,
+}
+
+impl RootPath for FakeRoot {
+    fn root_dir(&self) -> PathBuf {
+        self.drm_dir.clone()
+    }
+
+    fn device_dir(&self, card: &Card) -> PathBuf {
+        self.root_dir().join(card.to_string()).join("device")
+    }
+
+    fn mon_dir(&self, card: &Card, name: &HwMonName) -> PathBuf {
+        self.device_dir(card).join("hwmon").join(name.as_str())
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> utils::Result<()> {
+        if let Ok(mut writes) = self.writes.lock() {
+            writes.push(Write {
+                path: path.to_path_buf(),
+                value: String::from_utf8_lossy(contents).into_owned(),
+            });
+        }
+        let name = path
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let value = String::from_utf8_lossy(contents);
+        let rejected = self
+            .rejected
+            .lock()
+            .map(|rejected| {
+                rejected
+                    .iter()
+                    .any(|(file, rejected)| file == name && rejected == value.trim())
+            })
+            .unwrap_or_default();
+        if rejected {
+            return Err(utils::AmdGpuError::Write {
+                io: std::io::Error::from_raw_os_error(EINVAL),
+                path: path.to_string_lossy().into_owned(),
+            });
+        }
+        // like in sysfs reading overdrive table does not return last command
+        if name == "pp_od_clk_voltage" {
+            return Ok(());
+        }
+        utils::write(path, contents)
+    }
+}
+
+/// Single AMD GPU in temporary sysfs, removed when dropped
+///
+/// Card starts without temperature sensors and fan, they are added with
+/// builder methods.
+pub struct FakeGpu {
+    dir: TempDir,
+    env: Environment,
+    model: Model,
+    card: Card,
+    writes: Writes,
+    rejected: Rejected,
+}
+
+impl FakeGpu {
+    pub fn navi21() -> Self {
+        Self::new(Model::navi21())
+    }
+
+    pub fn polaris10() -> Self {
+        Self::new(Model::polaris10())
+    }
+
+    pub fn vega20() -> Self {
+        Self::new(Model::vega20())
+    }
+
+    pub fn new(model: Model) -> Self {
+        let dir = TempDir::new("amdgpu-testkit").expect("Failed to create fake sysfs");
+        let env = Environment::under(dir.path());
+        let gpu = Self {
+            dir,
+            env,
+            model,
+            card: Card(0),
+            writes: Writes::default(),
+            rejected: Rejected::default(),
+        };
+        for path in [
+            &gpu.env.procfs_root,
+            &gpu.env.runtime_dir,
+            &gpu.env.config_dir,
+            &gpu.env.fan_state_dir(),
+        ] {
+            create_dir(path);
+        }
+        create_dir(&gpu.mon_dir());
+        create_dir(&gpu.drm_card_dir());
+
+        let model = gpu.model.clone();
+        for (name, value) in [
+            ("vendor", "0x1002"),
+            ("device", model.device),
+            ("gpu_busy_percent", "0"),
+            ("power_dpm_force_performance_level", "auto"),
+            ("pp_dpm_sclk", model.pp_dpm_sclk),
+            ("pp_dpm_mclk", model.pp_dpm_mclk),
+            ("pp_od_clk_voltage", model.pp_od_clk_voltage),
+            ("pp_power_profile_mode", model::POWER_PROFILES),
+        ] {
+            gpu.set_device_file(name, value);
+        }
+        let cap = (model.power_cap_max * 4 / 5).to_string();
+        for (name, value) in [
+            ("name", "amdgpu"),
+            ("power1_cap", cap.as_str()),
+            ("power1_cap_max", &model.power_cap_max.to_string()),
+            ("power1_average", "30000000"),
+            ("in0_label", "vddgfx"),
+            ("in0_input", "800"),
+        ] {
+            gpu.set_mon_file(name, value);
+        }
+        gpu.link();
+        gpu
+    }
+
+    /// Move GPU to another card index, connectors are moved with it
+    pub fn with_card(mut self, index: u32) -> Self {
+        let old = self.card.to_string();
+        self.card = Card(index);
+        let new = self.card.to_string();
+        let drm = self.pci_dir().join("drm");
+        rename(&drm.join(&old), &drm.join(&new));
+        for entry in read_dir(&drm.join(&new)) {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if let Some(connector) = name.strip_prefix(&format!("{}-", old)) {
+                rename(
+                    &entry.path(),
+                    &entry
+                        .path()
+                        .with_file_name(format!("{}-{}", new, connector)),
+                );
+            }
+        }
+        self.link();
+        self
+    }
+
+    /// Add temperature sensor. `edge`, `junction` and `mem` get index and
+    /// limits used by driver, other labels are added after them without
+    /// limits
+    pub fn with_temp(self, label: &str, celsius: f64) -> Self {
+        let index = match model::known_temp(label) {
+            Some((index, crit, emergency)) => {
+                self.set_mon_file(&format!("temp{}_crit", index), crit.to_string());
+                self.set_mon_file(&format!("temp{}_crit_hyst", index), "-273150");
+                self.set_mon_file(&format!("temp{}_emergency", index), emergency.to_string());
+                index
+            }
+            None => self
+                .temp_index(label)
+                .unwrap_or_else(|| self.free_temp_index()),
+        };
+        self.set_mon_file(&format!("temp{}_label", index), label);
+        self.set_temp(label, celsius);
+        self
+    }
+
+    /// Add fan in automatic mode with tachometer
+    pub fn with_fan(self, pwm: u8, rpm: u32) -> Self {
+        let max_rpm = self.model.max_rpm.to_string();
+        for (name, value) in [
+            (PULSE_WIDTH_MODULATION, pwm.to_string()),
+            (
+                PULSE_WIDTH_MODULATION_MODE,
+                PULSE_WIDTH_MODULATION_AUTO.to_string(),
+            ),
+            (PULSE_WIDTH_MODULATION_MIN, "0".to_string()),
+            (PULSE_WIDTH_MODULATION_MAX, "255".to_string()),
+            (FAN_INPUT, rpm.to_string()),
+            (FAN_MIN, "0".to_string()),
+            (FAN_MAX, max_rpm),
+        ] {
+            self.set_mon_file(name, value);
+        }
+        self
+    }
+
+    /// Remove `fan1_*` files, like on boards without fan speed reporting
+    pub fn without_tachometer(self) -> Self {
+        for name in [FAN_INPUT, FAN_MIN, FAN_MAX] {
+            self.remove_mon_file(name);
+        }
+        self
+    }
+
+    pub fn with_usage(self, percent: u8) -> Self {
+        self.set_usage(percent);
+        self
+    }
+
+    /// Add connector, ex. `with_connector("DP", 1, true)` creates
+    /// `card0-DP-1`
+    pub fn with_connector(self, kind: &str, index: u8, connected: bool) -> Self {
+        create_dir(&self.connector_dir(kind, index));
+        self.set_connected(kind, index, connected);
+        self.link();
+        self
+    }
+
+    /// Change sensor value of temperature added with [FakeGpu::with_temp]
+    pub fn set_temp(&self, label: &str, celsius: f64) {
+        let index = self
+            .temp_index(label)
+            .unwrap_or_else(|| panic!("No temperature sensor {:?}", label));
+        let milli = (celsius * 1000.0).round() as i64;
+        self.set_mon_file(&format!("temp{}_input", index), milli.to_string());
+    }
+
+    pub fn set_rpm(&self, rpm: u32) {
+        self.set_mon_file(FAN_INPUT, rpm.to_string());
+    }
+
+    pub fn set_usage(&self, percent: u8) {
+        self.set_device_file("gpu_busy_percent", percent.to_string());
+    }
+
+    /// Plug or unplug monitor
+    pub fn set_connected(&self, kind: &str, index: u8, connected: bool) {
+        let dir = self.connector_dir(kind, index);
+        let (status, modes, dpms) = if connected {
+            ("connected", "2560x1440\n1920x1080\n1280x720\n", "On")
+        } else {
+            ("disconnected", "", "Off")
+        };
+        for (name, value) in [
+            ("status", status),
+            ("modes", modes),
+            ("dpms", dpms),
+            ("enabled", if connected { "enabled" } else { "disabled" }),
+        ] {
+            write_file(&dir.join(name), value);
+        }
+    }
+
+    pub fn set_mon_file<V: AsRef<[u8]>>(&self, name: &str, value: V) {
+        write_file(&self.mon_dir().join(name), value);
+    }
+
+    pub fn remove_mon_file(&self, name: &str) {
+        let _ = std::fs::remove_file(self.mon_dir().join(name));
+    }
+
+    pub fn set_device_file<V: AsRef<[u8]>>(&self, name: &str, value: V) {
+        write_file(&self.pci_dir().join(name), value);
+    }
+
+    /// Trimmed content of hwmon file, `None` if file does not exist
+    pub fn mon_file(&self, name: &str) -> Option<String> {
+        read_file(&self.mon_dir().join(name))
+    }
+
+    /// Trimmed content of device file, `None` if file does not exist
+    pub fn device_file(&self, name: &str) -> Option<String> {
+        read_file(&self.pci_dir().join(name))
+    }
+
+    /// Locations of fake system, sysfs and all other directories are inside
+    /// temporary directory
+    pub fn env(&self) -> &Environment {
+        &self.env
+    }
+
+    pub fn card(&self) -> Card {
+        self.card
+    }
+
+    /// Root of whole fake system
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Device directory as seen through `class/drm`
+    pub fn device_dir(&self) -> PathBuf {
+        self.root().device_dir(&self.card)
+    }
+
+    pub fn mon_dir(&self) -> PathBuf {
+        self.pci_dir().join("hwmon").join(HW_MON)
+    }
+
+    /// sysfs root sharing record of writes with this GPU
+    pub fn root(&self) -> FakeRoot {
+        FakeRoot {
+            drm_dir: self.env.drm_dir(),
+            writes: self.writes.clone(),
+            rejected: self.rejected.clone(),
+        }
+    }
+
+    pub fn hw_mon(&self) -> HwMon<FakeRoot> {
+        HwMon::new(&self.card, HwMonName(HW_MON.into()), self.root())
+    }
+
+    /// Every write done through [FakeRoot] in order
+    pub fn writes(&self) -> Vec<Write> {
+        self.writes
+            .lock()
+            .map(|writes| writes.clone())
+            .unwrap_or_default()
+    }
+
+    /// Values written to file with given name (ex. `pwm1`)
+    pub fn writes_to(&self, file: &str) -> Vec<String> {
+        self.writes()
+            .into_iter()
+            .filter(|write| write.path.file_name().and_then(|s| s.to_str()) == Some(file))
+            .map(|write| write.value)
+            .collect()
+    }
+
+    /// Writes of value to file fail with `EINVAL` like values rejected by
+    /// kernel, they are still recorded
+    pub fn reject_write(&self, file: &str, value: &str) {
+        if let Ok(mut rejected) = self.rejected.lock() {
+            rejected.push((file.to_string(), value.to_string()));
+        }
+    }
+
+    pub fn clear_writes(&self) {
+        if let Ok(mut writes) = self.writes.lock() {
+            writes.clear();
+        }
+    }
+
+    fn pci_dir(&self) -> PathBuf {
+        self.env
+            .sysfs_root
+            .join("devices")
+            .join("pci0000:00")
+            .join(PCI_SLOT)
+    }
+
+    fn drm_card_dir(&self) -> PathBuf {
+        self.pci_dir().join("drm").join(self.card.to_string())
+    }
+
+    fn connector_dir(&self, kind: &str, index: u8) -> PathBuf {
+        self.drm_card_dir()
+            .join(format!("{}-{}-{}", self.card, kind, index))
+    }
+
+    fn temp_index(&self, label: &str) -> Option<u8> {
+        (1..=MAX_TEMPS)
+            .find(|index| self.mon_file(&format!("temp{}_label", index)).as_deref() == Some(label))
+    }
+
+    fn free_temp_index(&self) -> u8 {
+        (4..=MAX_TEMPS)
+            .find(|index| self.mon_file(&format!("temp{}_label", index)).is_none())
+            .expect("Too many temperature sensors")
+    }
+
+    /// Recreate `class/drm` links like driver does, card and each connector
+    /// point into PCI device
+    fn link(&self) {
+        let drm_dir = self.env.drm_dir();
+        let _ = std::fs::remove_dir_all(&drm_dir);
+        create_dir(&drm_dir);
+        let card_dir = self.drm_card_dir();
+        symlink(&self.pci_dir(), &card_dir.join("device"));
+        symlink(&card_dir, &drm_dir.join(self.card.to_string()));
+        for entry in read_dir(&card_dir) {
+            let path = entry.path();
+            if entry.file_type().map(|ty| ty.is_dir()).unwrap_or_default() {
+                symlink(&path, &drm_dir.join(entry.file_name()));
+            }
+        }
+    }
+}
+
+fn create_dir(path: &Path) {
+    std::fs::create_dir_all(path).unwrap_or_else(|e| panic!("Create {:?} failed. {}", path, e));
+}
+
+fn write_file<V: AsRef<[u8]>>(path: &Path, value: V) {
+    std::fs::write(path, value).unwrap_or_else(|e| panic!("Write to {:?} failed. {}", path, e));
+}
+
+fn read_file(path: &Path) -> Option<String> {
+    std::fs::read_to_string(path)
+        .ok()
+        .map(|s| s.trim().to_string())
+}
+
+fn read_dir(path: &Path) -> Vec<std::fs::DirEntry> {
+    std::fs::read_dir(path)
+        .map(|dir| dir.filter_map(|entry| entry.ok()).collect())
+        .unwrap_or_default()
+}
+
+fn rename(from: &Path, to: &Path) {
+    std::fs::rename(from, to).unwrap_or_else(|e| panic!("Move {:?} failed. {}", from, e));
+}
+
+fn symlink(original: &Path, link: &Path) {
+    let _ = std::fs::remove_file(link);
+    std::os::unix::fs::symlink(original, link)
+        .unwrap_or_else(|e| panic!("Link {:?} failed. {}", link, e));
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu::environment::Environment;
+    use amdgpu::hw_mon::open_hw_mon;
+    use amdgpu::utils::{hw_mons, read_cards};
+    use amdgpu::Card;
+
+    use super::FakeGpu;
+
+    #[test]
+    fn realistic_tree() {
+        let gpu = FakeGpu::navi21()
+            .with_temp("edge", 45.0)
+            .with_temp("junction", 52.5)
+            .with_fan(128, 1500)
+            .with_connector("DP", 1, true)
+            .with_card(1);
+
+        let env: &Environment = gpu.env();
+        assert_eq!(read_cards(env).unwrap(), vec![Card(1)]);
+        let mons = hw_mons(env, true).unwrap();
+        assert_eq!(mons.len(), 1);
+        assert!(mons[0].is_amd());
+        assert!(mons[0].name_is_amd());
+
+        let hw_mon = open_hw_mon(env, Card(1)).unwrap();
+        assert_eq!(hw_mon.hw_mon_read("temp2_input").unwrap(), "52500");
+        assert_eq!(hw_mon.hw_mon_read("temp2_label").unwrap(), "junction");
+        assert_eq!(hw_mon.hw_mon_read("pwm1").unwrap(), "128");
+        assert_eq!(hw_mon.hw_mon_read("fan1_max").unwrap(), "3300");
+        assert!(hw_mon
+            .device_read("pp_od_clk_voltage")
+            .unwrap()
+            .contains("OD_VDDGFX_OFFSET:"));
+        let status = env.drm_dir().join("card1-DP-1").join("status");
+        assert_eq!(std::fs::read_to_string(status).unwrap(), "connected");
+
+        gpu.set_temp("junction", 80.0);
+        gpu.set_rpm(0);
+        assert_eq!(hw_mon.hw_mon_read("temp2_input").unwrap(), "80000");
+        assert_eq!(hw_mon.hw_mon_read("fan1_input").unwrap(), "0");
+    }
+
+    #[test]
+    fn writes_are_recorded() {
+        let gpu = FakeGpu::vega20().with_fan(0, 0).without_tachometer();
+        let hw_mon = gpu.hw_mon();
+        hw_mon.hw_mon_write("pwm1_enable", 1).unwrap();
+        hw_mon.hw_mon_write("pwm1", 90).unwrap();
+        hw_mon.device_write("pp_od_clk_voltage", "c").unwrap();
+
+        assert_eq!(gpu.writes().len(), 3);
+        assert_eq!(
+            gpu.writes()[2].path,
+            gpu.device_dir().join("pp_od_clk_voltage")
+        );
+        assert_eq!(gpu.writes_to("pwm1"), vec!["90".to_string()]);
+        assert_eq!(gpu.mon_file("pwm1_enable").as_deref(), Some("1"));
+        assert_eq!(gpu.mon_file("fan1_input"), None);
+        assert!(gpu
+            .device_file("pp_od_clk_voltage")
+            .unwrap()
+            .starts_with("OD_SCLK:"));
+
+        gpu.clear_writes();
+        assert!(gpu.writes().is_empty());
+
+        gpu.reject_write("pp_od_clk_voltage", "s 1 9000");
+        assert!(hw_mon
+            .device_write("pp_od_clk_voltage", "s 1 9000")
+            .is_err());
+        assert_eq!(gpu.writes_to("pp_od_clk_voltage"), vec!["s 1 9000"]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Files which differ between GPU generations.
+
+/// Static description of GPU used to build fake sysfs
+#[derive(Clone, Debug)]
+pub struct Model {
+    /// PCI device id (`device`)
+    pub device: &'static str,
+    pub pp_od_clk_voltage: &'static str,
+    pub pp_dpm_sclk: &'static str,
+    pub pp_dpm_mclk: &'static str,
+    /// `fan1_max`
+    pub max_rpm: u32,
+    /// `power1_cap_max` in microwatts
+    pub power_cap_max: u64,
+}
+
+impl Model {
+    /// Radeon RX 6800 / 6900 (RDNA2)
+    pub fn navi21() -> Self {
+        Self {
+            device: "0x73bf",
+            pp_od_clk_voltage: "\
+OD_SCLK:
+0: 500Mhz
+1: 2615Mhz
+OD_MCLK:
+0: 97Mhz
+1: 1000MHz
+OD_VDDGFX_OFFSET:
+0mV
+OD_RANGE:
+SCLK:     500Mhz       4000Mhz
+MCLK:     674Mhz       1075Mhz
+",
+            pp_dpm_sclk: "0: 500Mhz\n1: 0Mhz *\n2: 2615Mhz\n",
+            pp_dpm_mclk: "0: 96Mhz *\n1: 456Mhz\n2: 673Mhz\n3: 1000Mhz\n",
+            max_rpm: 3300,
+            power_cap_max: 293_000_000,
+        }
+    }
+
+    /// Radeon RX 470 / 480 / 570 / 580 (GCN 4)
+    pub fn polaris10() -> Self {
+        Self {
+            device: "0x67df",
+            pp_od_clk_voltage: "\
+OD_SCLK:
+0:        300MHz        750mV
+1:        600MHz        769mV
+2:        900MHz        881mV
+3:       1145MHz        975mV
+4:       1215MHz       1050mV
+5:       1257MHz       1100mV
+6:       1300MHz       1125mV
+7:       1340MHz       1150mV
+OD_MCLK:
+0:        300MHz        750mV
+1:       2000MHz        800mV
+OD_RANGE:
+SCLK:     300MHz       2000MHz
+MCLK:     300MHz       2250MHz
+VDDC:     750mV        1150mV
+",
+            pp_dpm_sclk: "0: 300Mhz *\n1: 600Mhz\n2: 900Mhz\n3: 1145Mhz\n4: 1215Mhz\n5: 1257Mhz\n6: 1300Mhz\n7: 1340Mhz\n",
+            pp_dpm_mclk: "0: 300Mhz *\n1: 2000Mhz\n",
+            max_rpm: 3200,
+            power_cap_max: 180_000_000,
+        }
+    }
+
+    /// Radeon VII / Instinct MI50 (Vega 20)
+    pub fn vega20() -> Self {
+        Self {
+            device: "0x66af",
+            pp_od_clk_voltage: "\
+OD_SCLK:
+0: 800Mhz
+1: 2100Mhz
+OD_MCLK:
+1: 875MHz
+OD_VDDC_CURVE:
+0: 800MHz 706mV
+1: 1450MHz 772mV
+2: 2100MHz 1143mV
+OD_RANGE:
+SCLK:     800Mhz       2150Mhz
+MCLK:     625Mhz        950Mhz
+VDDC_CURVE_SCLK[0]:     800Mhz       2150Mhz
+VDDC_CURVE_VOLT[0]:     750mV        1200mV
+VDDC_CURVE_SCLK[1]:     800Mhz       2150Mhz
+VDDC_CURVE_VOLT[1]:     750mV        1200mV
+VDDC_CURVE_SCLK[2]:     800Mhz       2150Mhz
+VDDC_CURVE_VOLT[2]:     750mV        1200mV
+",
+            pp_dpm_sclk: "0: 808Mhz *\n1: 1801Mhz\n",
+            pp_dpm_mclk: "0: 351Mhz *\n1: 800Mhz\n2: 1000Mhz\n",
+            max_rpm: 3850,
+            power_cap_max: 250_000_000,
+        }
+    }
+}
+
+/// `pp_power_profile_mode` with default profile active
+pub(crate) static POWER_PROFILES: &str = "\
+NUM        MODE_NAME     BUSY_SET_POINT  FPS  USE_RLC_BUSY  MIN_ACTIVE_LEVEL
+  0 BOOTUP_DEFAULT*:        0       0          0              0
+  1 3D_FULL_SCREEN :        0      60          1              3
+  2   POWER_SAVING :       90      30          0              0
+  3          VIDEO :       10       0          0              0
+  4             VR :        0      90          0              0
+  5        COMPUTE :        0       0          0              0
+  6         CUSTOM :        0       0          0              0
+";
+
+/// Index, critical and emergency temperature of sensors exposed by amdgpu
+/// driver
+pub(crate) fn known_temp(label: &str) -> Option<(u8, u32, u32)> {
+    match label {
+        "edge" => Some((1, 100_000, 105_000)),
+        "junction" => Some((2, 110_000, 115_000)),
+        "mem" => Some((3, 105_000, 110_000)),
+        _ => None,
+    }
+}
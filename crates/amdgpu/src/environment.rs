@@ -0,0 +1,167 @@
+//! Locations of system directories used by services.
+//!
+//! sysfs, procfs, state, runtime and config directories are taken from
+//! [Environment] instead of being hardcoded, so services can be started
+//! against fake directory tree. Locations are read from environment variables
+//! and can be overridden with command line options.
+
+use std::path::{Path, PathBuf};
+
+pub static SYSFS_ROOT_VAR: &str = "AMDGPU_SYSFS_ROOT";
+pub static PROCFS_ROOT_VAR: &str = "AMDGPU_PROCFS_ROOT";
+pub static STATE_DIR_VAR: &str = "AMDGPU_STATE_DIR";
+pub static RUNTIME_DIR_VAR: &str = "AMDGPU_RUNTIME_DIR";
+pub static CONFIG_DIR_VAR: &str = "AMDGPU_CONFIG_DIR";
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Environment {
+    /// sysfs mount point (`/sys`)
+    pub sysfs_root: PathBuf,
+    /// procfs mount point (`/proc`)
+    pub procfs_root: PathBuf,
+    /// Parent of services state directories with pid files (`/var/lib`)
+    pub state_dir: PathBuf,
+    /// Sockets of helper services (`/tmp`)
+    pub runtime_dir: PathBuf,
+    /// Config files (`/etc/amdfand`)
+    pub config_dir: PathBuf,
+}
+
+impl Default for Environment {
+    fn default() -> Self {
+        Self {
+            sysfs_root: PathBuf::from("/sys"),
+            procfs_root: PathBuf::from("/proc"),
+            state_dir: PathBuf::from("/var/lib"),
+            runtime_dir: PathBuf::from("/tmp"),
+            config_dir: PathBuf::from("/etc/amdfand"),
+        }
+    }
+}
+
+/// Locations given on command line, they take precedence over environment
+/// variables
+#[derive(Debug, Default)]
+pub struct Overrides {
+    pub sysfs_root: Option<String>,
+    pub procfs_root: Option<String>,
+    pub state_dir: Option<String>,
+    pub runtime_dir: Option<String>,
+    pub config_dir: Option<String>,
+}
+
+impl Environment {
+    /// Read locations from `AMDGPU_*` environment variables, missing ones are
+    /// system defaults
+    pub fn from_env() -> Self {
+        Self::from_vars(std::env::vars())
+    }
+
+    pub fn from_vars<I>(vars: I) -> Self
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let mut env = Self::default();
+        for (key, value) in vars {
+            if value.is_empty() {
+                continue;
+            }
+            match key.as_str() {
+                k if k == SYSFS_ROOT_VAR => env.sysfs_root = value.into(),
+                k if k == PROCFS_ROOT_VAR => env.procfs_root = value.into(),
+                k if k == STATE_DIR_VAR => env.state_dir = value.into(),
+                k if k == RUNTIME_DIR_VAR => env.runtime_dir = value.into(),
+                k if k == CONFIG_DIR_VAR => env.config_dir = value.into(),
+                _ => {}
+            }
+        }
+        env
+    }
+
+    pub fn with_overrides(mut self, overrides: Overrides) -> Self {
+        let Overrides {
+            sysfs_root,
+            procfs_root,
+            state_dir,
+            runtime_dir,
+            config_dir,
+        } = overrides;
+        if let Some(path) = sysfs_root {
+            self.sysfs_root = path.into();
+        }
+        if let Some(path) = procfs_root {
+            self.procfs_root = path.into();
+        }
+        if let Some(path) = state_dir {
+            self.state_dir = path.into();
+        }
+        if let Some(path) = runtime_dir {
+            self.runtime_dir = path.into();
+        }
+        if let Some(path) = config_dir {
+            self.config_dir = path.into();
+        }
+        self
+    }
+
+    /// All locations placed in single directory tree, ex. `<root>/sys`,
+    /// `<root>/var/lib`
+    pub fn under<P: AsRef<Path>>(root: P) -> Self {
+        let root = root.as_ref();
+        Self {
+            sysfs_root: root.join("sys"),
+            procfs_root: root.join("proc"),
+            state_dir: root.join("var").join("lib"),
+            runtime_dir: root.join("tmp"),
+            config_dir: root.join("etc").join("amdfand"),
+        }
+    }
+
+    /// Direct rendering manager devices (`/sys/class/drm`)
+    pub fn drm_dir(&self) -> PathBuf {
+        self.sysfs_root.join("class").join("drm")
+    }
+
+    /// Runtime state of amdfand processes (pid files, fan health, reload
+    /// status, control sockets)
+    pub fn fan_state_dir(&self) -> PathBuf {
+        self.state_dir.join("amdfand")
+    }
+
+    pub fn config_file(&self, name: &str) -> PathBuf {
+        self.config_dir.join(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn variables_and_overrides() {
+        let env = Environment::from_vars(vec![
+            ("AMDGPU_SYSFS_ROOT".to_string(), "/tmp/fake/sys".to_string()),
+            ("AMDGPU_STATE_DIR".to_string(), "".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ]);
+        assert_eq!(env.drm_dir(), PathBuf::from("/tmp/fake/sys/class/drm"));
+        assert_eq!(env.fan_state_dir(), PathBuf::from("/var/lib/amdfand"));
+
+        let env = env.with_overrides(Overrides {
+            sysfs_root: Some("/mnt/sys".into()),
+            runtime_dir: Some("/run/amdgpu".into()),
+            ..Default::default()
+        });
+        assert_eq!(env.sysfs_root, PathBuf::from("/mnt/sys"));
+        assert_eq!(env.runtime_dir, PathBuf::from("/run/amdgpu"));
+        assert_eq!(env.procfs_root, PathBuf::from("/proc"));
+
+        let env = Environment::under("/tmp/tree");
+        assert_eq!(
+            env.config_file("mapping.toml"),
+            PathBuf::from("/tmp/tree/etc/amdfand/mapping.toml")
+        );
+    }
+}
@@ -3,6 +3,7 @@ use std::fmt::{Debug, Display, Formatter};
 use pidlock::PidlockError;
 
 use crate::lock_file::LockFileError;
+use crate::pidfile::fan_cmd::FanCmdError;
 #[cfg(feature = "gui-helper")]
 use crate::pidfile::helper_cmd::GuiHelperError;
 use crate::pidfile::ports::PortsError;
@@ -52,6 +53,8 @@ pub enum AmdGpuError {
     GuiHelper(#[from] GuiHelperError),
     #[error("{0:?}")]
     Ports(#[from] PortsError),
+    #[error("{0}")]
+    FanCmd(#[from] FanCmdError),
     #[error("{0:?}")]
     LockFile(#[from] LockFileError),
 }
@@ -0,0 +1,93 @@
+//! Fan health published by amdfand for monitoring tools.
+//! Each controlled card has its own `/var/lib/amdfand/cardX.health` file with
+//! single RON encoded [FanHealthReport].
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Card;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub enum FanHealth {
+    /// Fan speed matches written modulation
+    #[default]
+    Ok,
+    /// Fan speed does not match written modulation, still in grace period
+    Suspect,
+    /// Fan was forced to full speed to recover from stall
+    KickStart,
+    /// Fan did not recover, control was given back to GPU firmware
+    Stalled,
+}
+
+impl std::fmt::Display for FanHealth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            FanHealth::Ok => "ok",
+            FanHealth::Suspect => "suspect",
+            FanHealth::KickStart => "kick-start",
+            FanHealth::Stalled => "stalled",
+        })
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FanHealthReport {
+    pub card: Card,
+    pub health: FanHealth,
+    /// Last written modulation
+    pub pwm: u64,
+    /// Fan speed reported by `fan1_input`
+    pub rpm: Option<u32>,
+    /// Fan speed expected for written modulation
+    pub expected_rpm: Option<u32>,
+}
+
+/// Path to health file of given card inside given directory
+pub fn health_file<P: AsRef<Path>>(dir: P, card: &Card) -> PathBuf {
+    dir.as_ref().join(format!("{}.health", card))
+}
+
+/// Save report in `dir`
+pub fn write_health<P: AsRef<Path>>(dir: P, report: &FanHealthReport) -> std::io::Result<()> {
+    let content = ron::to_string(report)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::utils::atomic_write(health_file(dir, &report.card), content, 0)
+}
+
+/// Remove report of given card, used when amdfand stops controlling it
+pub fn remove_health<P: AsRef<Path>>(dir: P, card: &Card) {
+    let _ = std::fs::remove_file(health_file(dir, card));
+}
+
+/// Load report of given card, `None` if card is not managed by amdfand or file
+/// is malformed
+pub fn read_health<P: AsRef<Path>>(dir: P, card: &Card) -> Option<FanHealthReport> {
+    let content = std::fs::read_to_string(health_file(dir, card)).ok()?;
+    ron::from_str(&content).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let dir = tempdir::TempDir::new("fan-health").unwrap();
+        let report = FanHealthReport {
+            card: Card(1),
+            health: FanHealth::Stalled,
+            pwm: 200,
+            rpm: Some(0),
+            expected_rpm: Some(2800),
+        };
+        write_health(dir.path(), &report).unwrap();
+        assert!(dir.path().join("card1.health").exists());
+        assert_eq!(read_health(dir.path(), &Card(1)), Some(report));
+        assert_eq!(read_health(dir.path(), &Card(0)), None);
+
+        remove_health(dir.path(), &Card(1));
+        assert_eq!(read_health(dir.path(), &Card(1)), None);
+    }
+}
@@ -1,8 +1,9 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use tracing::error;
 
-use crate::{utils, AmdGpuError, Card, IoFailure, ROOT_DIR};
+use crate::environment::Environment;
+use crate::{utils, AmdGpuError, Card, IoFailure};
 
 #[derive(Debug)]
 pub struct HwMonName(pub String);
@@ -19,14 +20,37 @@ pub trait RootPath {
     fn root_dir(&self) -> PathBuf;
     fn device_dir(&self, card: &Card) -> PathBuf;
     fn mon_dir(&self, card: &Card, name: &HwMonName) -> PathBuf;
+
+    /// Every write to device and hwmon files goes through root, fake roots
+    /// used in tests may record them
+    fn write(&self, path: &Path, contents: &[u8]) -> utils::Result<()> {
+        utils::write(path, contents)
+    }
 }
 
+/// Devices of direct rendering manager in sysfs given by [Environment]
 #[derive(Debug)]
-pub struct SysFs;
+pub struct SysFs {
+    drm_dir: PathBuf,
+}
+
+impl SysFs {
+    pub fn new(env: &Environment) -> Self {
+        Self {
+            drm_dir: env.drm_dir(),
+        }
+    }
+}
+
+impl Default for SysFs {
+    fn default() -> Self {
+        Self::new(&Environment::default())
+    }
+}
 
 impl RootPath for SysFs {
     fn root_dir(&self) -> PathBuf {
-        PathBuf::new().join(ROOT_DIR)
+        self.drm_dir.clone()
     }
 
     fn device_dir(&self, card: &Card) -> PathBuf {
@@ -125,27 +149,21 @@ impl<Root: RootPath> HwMon<Root> {
     }
 
     pub fn hw_mon_write(&self, name: &str, value: u64) -> utils::Result<()> {
-        utils::write(self.mon_file_path(name), format!("{}", value))?;
+        self.root
+            .write(&self.mon_file_path(name), value.to_string().as_bytes())?;
         Ok(())
     }
 
     pub fn device_write<C: AsRef<[u8]>>(&self, name: &str, value: C) -> utils::Result<()> {
-        utils::write(self.device_dir().join(name), value)?;
+        self.root
+            .write(&self.device_dir().join(name), value.as_ref())?;
         Ok(())
     }
 }
 
-#[inline]
-fn hw_mon_dirs_path(card: &Card) -> PathBuf {
-    PathBuf::new()
-        .join(ROOT_DIR)
-        .join(card.to_string())
-        .join("device")
-        .join("hwmon")
-}
-
-pub fn open_hw_mon(card: Card) -> crate::Result<HwMon> {
-    let read_path = hw_mon_dirs_path(&card);
+pub fn open_hw_mon(env: &Environment, card: Card) -> crate::Result<HwMon> {
+    let root = SysFs::new(env);
+    let read_path = root.device_dir(&card).join("hwmon");
     let entries = std::fs::read_dir(&read_path).map_err(|io| IoFailure {
         io,
         path: read_path,
@@ -164,5 +182,5 @@ pub fn open_hw_mon(card: Card) -> crate::Result<HwMon> {
         .take(1)
         .last()
         .ok_or(AmdGpuError::NoAmdHwMon)?;
-    Ok(HwMon::new(&card, name, SysFs))
+    Ok(HwMon::new(&card, name, root))
 }
@@ -1,20 +1,24 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
 pub use card::*;
 pub use error::*;
 use serde::{Deserialize, Serialize};
 pub use temp_input::*;
 
 mod card;
+pub mod environment;
 mod error;
+pub mod fan_health;
 #[cfg(feature = "gui-helper")]
 pub mod hw_mon;
 pub mod lock_file;
+pub mod pending_change;
 pub mod pidfile;
+pub mod procfs;
+pub mod sd_notify;
 mod temp_input;
 pub mod utils;
 
-pub static CONFIG_DIR: &str = "/etc/amdfand";
-
-pub static ROOT_DIR: &str = "/sys/class/drm";
 pub static HW_MON_DIR: &str = "hwmon";
 
 /// pulse width modulation fan control minimum level (0)
@@ -31,16 +35,24 @@ pub static PULSE_WIDTH_MODULATION: &str = "pwm1";
 /// control)
 pub static PULSE_WIDTH_MODULATION_MODE: &str = "pwm1_enable";
 
+/// fan speed reported by tachometer (RPM)
+pub static FAN_INPUT: &str = "fan1_input";
+
+/// minimal fan speed (RPM)
+pub static FAN_MIN: &str = "fan1_min";
+
+/// maximal fan speed (RPM)
+pub static FAN_MAX: &str = "fan1_max";
+
 // static PULSE_WIDTH_MODULATION_DISABLED: &str = "0";
 pub static PULSE_WIDTH_MODULATION_MANUAL: &str = "1";
 pub static PULSE_WIDTH_MODULATION_AUTO: &str = "2";
 
-static mut RELOAD_CONFIG: bool = false;
+static RELOAD_CONFIG: AtomicBool = AtomicBool::new(false);
+static STOP: AtomicBool = AtomicBool::new(false);
 
 extern "C" fn sig_reload(_n: i32) {
-    unsafe {
-        RELOAD_CONFIG = true;
-    };
+    request_reload();
 }
 
 /// Listen for SIGHUP signal. This signal is used to reload config
@@ -53,18 +65,45 @@ pub fn listen_unix_signal() {
     };
 }
 
+extern "C" fn sig_stop(_n: i32) {
+    STOP.store(true, Ordering::SeqCst);
+}
+
+/// Listen for SIGTERM and SIGINT signals. Service should finish current work
+/// and exit cleanly
+pub fn listen_stop_signal() {
+    use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
+    unsafe {
+        let handler: SigHandler = SigHandler::Handler(sig_stop);
+        let action = SigAction::new(handler, SaFlags::empty(), SigSet::empty());
+        sigaction(Signal::SIGTERM, &action).expect("Failed to mount action handler");
+        sigaction(Signal::SIGINT, &action).expect("Failed to mount action handler");
+    };
+}
+
+/// Check if application received SIGTERM or SIGINT
+#[inline(always)]
+pub fn is_stop_requested() -> bool {
+    STOP.load(Ordering::SeqCst)
+}
+
 /// Check if application received SIGHUP and must reload config file
 #[inline(always)]
 pub fn is_reload_required() -> bool {
-    unsafe { RELOAD_CONFIG }
+    RELOAD_CONFIG.load(Ordering::SeqCst)
+}
+
+/// Request config reload from other source than SIGHUP (ex. config file
+/// watcher)
+#[inline(always)]
+pub fn request_reload() {
+    RELOAD_CONFIG.store(true, Ordering::SeqCst);
 }
 
 /// Reset reload config flag
 #[inline(always)]
 pub fn config_reloaded() {
-    unsafe {
-        RELOAD_CONFIG = false;
-    }
+    RELOAD_CONFIG.store(false, Ordering::SeqCst);
 }
 
 pub type Result<T> = std::result::Result<T, AmdGpuError>;
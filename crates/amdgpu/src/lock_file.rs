@@ -1,11 +1,12 @@
 //! Create lock file and prevent running 2 identical services.
 //! NOTE: For 2 amdfand services you may just give 2 different names
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use nix::libc;
 use tracing::{debug, error, warn};
 
+use crate::environment::Environment;
 use crate::pidfile::Pid;
 use crate::IoFailure;
 
@@ -33,14 +34,16 @@ pub enum State {
 pub struct PidLock {
     name: String,
     pid_path: String,
+    proc_dir: PathBuf,
 }
 
 impl PidLock {
     pub fn new<P: AsRef<Path>>(
+        env: &Environment,
         sub_dir: P,
         name: String,
     ) -> std::result::Result<Self, crate::error::AmdGpuError> {
-        let pid_dir_path = std::path::Path::new("/var").join("lib").join(sub_dir);
+        let pid_dir_path = env.state_dir.join(sub_dir);
         let pid_path = {
             std::fs::create_dir_all(&pid_dir_path).map_err(|io| IoFailure {
                 io,
@@ -53,7 +56,11 @@ impl PidLock {
                 .unwrap()
         };
         debug!("Creating pid lock for path {:?}", pid_path);
-        Ok(Self { pid_path, name })
+        Ok(Self {
+            pid_path,
+            name,
+            proc_dir: env.procfs_root.clone(),
+        })
     }
 
     /// Create new lock file. File will be created if:
@@ -141,7 +148,7 @@ impl PidLock {
 
     /// Read target process name
     fn process_name(&self, pid: Pid) -> Result<String, LockFileError> {
-        match std::fs::read_to_string(format!("/proc/{}/cmdline", *pid)) {
+        match std::fs::read_to_string(self.proc_dir.join(pid.0.to_string()).join("cmdline")) {
             Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
                 Err(LockFileError::NotExists(pid))
             }
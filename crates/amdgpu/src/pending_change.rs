@@ -0,0 +1,171 @@
+//! Overclocking changes waiting for confirmation.
+//! `amdvold apply --confirm-within` writes `/var/lib/amdvold/pending.ron` with
+//! single RON encoded [PendingChange]. amdvold watchdog restores snapshot from
+//! before the change unless this file is removed by [confirm] before deadline.
+//! Watchdog first moves it to `pending.reverting` with [claim], so change can
+//! be either confirmed or reverted, never both.
+
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::environment::Environment;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PendingChange {
+    /// Applied voltage profile
+    pub profile: String,
+    /// amdvold snapshot restored when change is not confirmed
+    pub snapshot: String,
+    /// Seconds since UNIX epoch
+    pub deadline: u64,
+}
+
+impl PendingChange {
+    /// Seconds left to confirm change
+    pub fn remaining(&self, now: u64) -> u64 {
+        self.deadline.saturating_sub(now)
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        now >= self.deadline
+    }
+}
+
+/// Seconds since UNIX epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+pub fn pending_file(env: &Environment) -> PathBuf {
+    env.state_dir.join("amdvold").join("pending.ron")
+}
+
+/// Pending change claimed by watchdog which restores snapshot
+pub fn reverting_file(env: &Environment) -> PathBuf {
+    env.state_dir.join("amdvold").join("pending.reverting")
+}
+
+/// Result of [confirm]
+#[derive(Debug, PartialEq)]
+pub enum Confirmation {
+    /// Change is kept
+    Confirmed(PendingChange),
+    /// Deadline passed and watchdog already restores snapshot
+    Reverting,
+    /// Nothing was waiting for confirmation
+    Nothing,
+}
+
+pub fn write_pending(env: &Environment, change: &PendingChange) -> std::io::Result<()> {
+    let path = pending_file(env);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let content = ron::to_string(change)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::utils::atomic_write(path, content, 0)
+}
+
+/// Change waiting for confirmation, `None` if there is none or file is
+/// malformed
+pub fn read_pending(env: &Environment) -> Option<PendingChange> {
+    let content = std::fs::read_to_string(pending_file(env)).ok()?;
+    ron::from_str(&content).ok()
+}
+
+/// Keep pending change unless watchdog already claimed it
+pub fn confirm(env: &Environment) -> std::io::Result<Confirmation> {
+    let change = read_pending(env);
+    match std::fs::remove_file(pending_file(env)) {
+        Ok(()) => Ok(change.map_or(Confirmation::Nothing, Confirmation::Confirmed)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if reverting_file(env).exists() {
+                Ok(Confirmation::Reverting)
+            } else {
+                Ok(Confirmation::Nothing)
+            }
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Take pending change before reverting it, it can't be confirmed anymore.
+/// Returns `None` if it was already confirmed
+pub fn claim(env: &Environment) -> std::io::Result<Option<PendingChange>> {
+    let path = reverting_file(env);
+    match std::fs::rename(pending_file(env), &path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let content = std::fs::read_to_string(&path)?;
+    match ron::from_str(&content) {
+        Ok(change) => Ok(Some(change)),
+        // malformed change is never reverted
+        Err(_) => finish_revert(env).map(|()| None),
+    }
+}
+
+/// Give claimed change back, ex. when it's not the one watchdog waits for
+pub fn unclaim(env: &Environment) -> std::io::Result<()> {
+    std::fs::rename(reverting_file(env), pending_file(env))
+}
+
+/// Remove claimed change after it was reverted
+pub fn finish_revert(env: &Environment) -> std::io::Result<()> {
+    match std::fs::remove_file(reverting_file(env)) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn confirm_removes_change() {
+        let dir = tempdir::TempDir::new("pending-change").unwrap();
+        let env = Environment::under(dir.path());
+        let change = PendingChange {
+            profile: String::from("undervolt"),
+            snapshot: String::from("before-apply"),
+            deadline: 130,
+        };
+        assert_eq!(change.remaining(100), 30);
+        assert!(change.is_expired(130));
+
+        write_pending(&env, &change).unwrap();
+        assert_eq!(read_pending(&env), Some(change.clone()));
+        assert_eq!(confirm(&env).unwrap(), Confirmation::Confirmed(change));
+        assert_eq!(read_pending(&env), None);
+        assert_eq!(confirm(&env).unwrap(), Confirmation::Nothing);
+    }
+
+    #[test]
+    fn claimed_change_is_not_confirmed() {
+        let dir = tempdir::TempDir::new("pending-change").unwrap();
+        let env = Environment::under(dir.path());
+        let change = PendingChange {
+            profile: String::from("undervolt"),
+            snapshot: String::from("before-apply"),
+            deadline: 0,
+        };
+        write_pending(&env, &change).unwrap();
+
+        assert_eq!(claim(&env).unwrap(), Some(change.clone()));
+        assert_eq!(claim(&env).unwrap(), None);
+        assert_eq!(confirm(&env).unwrap(), Confirmation::Reverting);
+
+        unclaim(&env).unwrap();
+        assert_eq!(read_pending(&env), Some(change.clone()));
+        assert_eq!(claim(&env).unwrap(), Some(change));
+        finish_revert(&env).unwrap();
+        assert_eq!(confirm(&env).unwrap(), Confirmation::Nothing);
+    }
+}
@@ -0,0 +1,211 @@
+//! Cards controlled by running amdfand instance.
+//!
+//! Each instance writes `/var/lib/amdfand/<pid file name>.cards` on start and
+//! removes it after giving fans back to firmware on clean shutdown. When
+//! process is gone but file is still there (SIGKILL, OOM kill) fans were left
+//! in manual mode and GUI helper restores automatic mode.
+
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::pidfile::reload::clear_outcome;
+use crate::pidfile::Pid;
+use crate::{PULSE_WIDTH_MODULATION_AUTO, PULSE_WIDTH_MODULATION_MODE};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ControlledCards {
+    pub pid: Pid,
+    /// hwmon directories of controlled fans
+    pub hw_mons: Vec<PathBuf>,
+}
+
+/// Marker file of amdfand instance with given pid file name
+pub fn cards_file<P: AsRef<Path>>(dir: P, name: &str) -> PathBuf {
+    dir.as_ref().join(format!("{}.cards", name))
+}
+
+pub fn write_cards<P: AsRef<Path>>(
+    dir: P,
+    name: &str,
+    cards: &ControlledCards,
+) -> std::io::Result<()> {
+    let content = ron::to_string(cards)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::utils::atomic_write(cards_file(dir, name), content, 0)
+}
+
+pub fn read_cards<P: AsRef<Path>>(path: P) -> Option<ControlledCards> {
+    let content = std::fs::read_to_string(path).ok()?;
+    ron::from_str(&content).ok()
+}
+
+/// Clean shutdown marker, fans must be already given back to firmware
+pub fn remove_cards<P: AsRef<Path>>(dir: P, name: &str) {
+    let _ = std::fs::remove_file(cards_file(dir, name));
+}
+
+/// Switch fans to automatic mode. Errors are only logged since there is
+/// nothing else to do with them
+pub fn restore_firmware(hw_mons: &[PathBuf]) {
+    for hw_mon in hw_mons {
+        let path = hw_mon.join(PULSE_WIDTH_MODULATION_MODE);
+        if let Err(e) = std::fs::write(&path, PULSE_WIDTH_MODULATION_AUTO) {
+            error!(
+                "Failed to restore automatic fan control in {:?}. {}",
+                path, e
+            );
+        }
+    }
+}
+
+/// Process exists in given procfs and it's amdfand (pid may be reused by
+/// other process)
+pub fn is_alive<P: AsRef<Path>>(proc_dir: P, pid: Pid) -> bool {
+    let process = proc_dir.as_ref().join(pid.0.to_string());
+    if !process.is_dir() {
+        return false;
+    }
+    std::fs::read_to_string(process.join("comm"))
+        .map(|comm| comm.trim().starts_with("amdfand"))
+        .unwrap_or(true)
+}
+
+/// Give fans of dead instances back to firmware and remove their marker and
+/// reload outcome files.
+/// Returns restored instances
+pub fn restore_orphaned<P, F>(dir: P, is_alive: F) -> Vec<ControlledCards>
+where
+    P: AsRef<Path>,
+    F: Fn(Pid) -> bool,
+{
+    let entries = match std::fs::read_dir(dir.as_ref()) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("cards"))
+        .filter_map(|path| {
+            let cards = read_cards(&path)?;
+            if is_alive(cards.pid) {
+                return None;
+            }
+            // file is named after pid file, not pid, so new instance with same
+            // name may have replaced it in the meantime
+            if !is_owned_by(&path, cards.pid) {
+                return None;
+            }
+            warn!(
+                "amdfand {} died without clean shutdown, giving fans back to firmware",
+                *cards.pid
+            );
+            restore_firmware(&cards.hw_mons);
+            if is_owned_by(&path, cards.pid) {
+                let _ = std::fs::remove_file(&path);
+            }
+            clear_outcome(dir.as_ref(), cards.pid);
+            Some(cards)
+        })
+        .collect()
+}
+
+/// Marker file still exists and belongs to instance with given pid
+fn is_owned_by(path: &Path, pid: Pid) -> bool {
+    read_cards(path).is_some_and(|cards| cards.pid == pid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pidfile::reload::reload_file;
+
+    #[test]
+    fn restore_dead_instance() {
+        let dir = tempdir::TempDir::new("cards").unwrap();
+        let hw_mon = |name: &str| {
+            let path = dir.path().join(name);
+            std::fs::create_dir(&path).unwrap();
+            std::fs::write(path.join("pwm1_enable"), "1").unwrap();
+            path
+        };
+        let dead = ControlledCards {
+            pid: Pid(10),
+            hw_mons: vec![hw_mon("hwmon0")],
+        };
+        let alive = ControlledCards {
+            pid: Pid(20),
+            hw_mons: vec![hw_mon("hwmon1")],
+        };
+        write_cards(dir.path(), "card0", &dead).unwrap();
+        write_cards(dir.path(), "card1", &alive).unwrap();
+        std::fs::write(reload_file(dir.path(), Pid(10)), "Reloaded").unwrap();
+        assert_eq!(
+            read_cards(cards_file(dir.path(), "card0")),
+            Some(dead.clone())
+        );
+
+        let restored = restore_orphaned(dir.path(), |pid| pid == Pid(20));
+        assert_eq!(restored, vec![dead]);
+        let mode = |name: &str| std::fs::read_to_string(dir.path().join(name).join("pwm1_enable"));
+        assert_eq!(mode("hwmon0").unwrap(), "2");
+        assert_eq!(mode("hwmon1").unwrap(), "1");
+        assert!(!cards_file(dir.path(), "card0").exists());
+        assert!(!reload_file(dir.path(), Pid(10)).exists());
+        assert!(cards_file(dir.path(), "card1").exists());
+
+        remove_cards(dir.path(), "card1");
+        assert!(restore_orphaned(dir.path(), |_| false).is_empty());
+    }
+
+    #[test]
+    fn alive_in_procfs() {
+        let proc_dir = tempdir::TempDir::new("proc").unwrap();
+        let process = |pid: i32, comm: &str| {
+            let dir = proc_dir.path().join(pid.to_string());
+            std::fs::create_dir(&dir).unwrap();
+            std::fs::write(dir.join("comm"), comm).unwrap();
+        };
+        process(100, "amdfand\n");
+        process(200, "bash\n");
+
+        assert!(is_alive(proc_dir.path(), Pid(100)));
+        assert!(!is_alive(proc_dir.path(), Pid(200)));
+        assert!(!is_alive(proc_dir.path(), Pid(std::process::id() as i32)));
+    }
+
+    #[test]
+    fn skip_file_replaced_by_restarted_instance() {
+        let dir = tempdir::TempDir::new("cards").unwrap();
+        let hw_mon = dir.path().join("hwmon0");
+        std::fs::create_dir(&hw_mon).unwrap();
+        std::fs::write(hw_mon.join("pwm1_enable"), "1").unwrap();
+        let dead = ControlledCards {
+            pid: Pid(10),
+            hw_mons: vec![hw_mon.clone()],
+        };
+        let restarted = ControlledCards {
+            pid: Pid(30),
+            hw_mons: vec![hw_mon.clone()],
+        };
+        write_cards(dir.path(), "amdfand", &dead).unwrap();
+
+        // new instance starts while dead one is checked
+        let restored = restore_orphaned(dir.path(), |pid| {
+            if pid == Pid(10) {
+                write_cards(dir.path(), "amdfand", &restarted).unwrap();
+            }
+            pid == Pid(30)
+        });
+        assert!(restored.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(hw_mon.join("pwm1_enable")).unwrap(),
+            "1"
+        );
+        assert_eq!(
+            read_cards(cards_file(dir.path(), "amdfand")),
+            Some(restarted)
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! amdfand control socket communication toolkit
+//!
+//! Each amdfand instance serves `/var/lib/amdfand/<pid file name>.sock`.
+//! Anyone can ask for status, changing fan speed requires root.
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::fan_health::FanHealth;
+use crate::pidfile::{Pid, PidResponse};
+use crate::Card;
+
+#[derive(Debug, thiserror::Error)]
+pub enum FanCmdError {
+    #[error("amdfand socket file {0:?} not found. Is service running?")]
+    NoSockFile(PathBuf),
+    #[error("Failed to connect to amdfand socket. {0}")]
+    UnableToConnect(#[from] std::io::Error),
+    #[error("Failed to serialize amdfand command. {0}")]
+    Serialize(#[from] ron::Error),
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Command {
+    Status,
+    /// Force fan speed (percent) for given number of seconds, failsafe still
+    /// wins
+    Override {
+        speed: f64,
+        duration: u64,
+    },
+    ClearOverride,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub enum Response {
+    NoOp,
+    Status(Status),
+    OverrideSet,
+    OverrideCleared,
+    /// Command was rejected
+    Rejected(String),
+}
+
+impl PidResponse for Response {
+    fn kill_response() -> Self {
+        Self::NoOp
+    }
+}
+
+/// What decided about fan speed in last tick
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum SpeedSource {
+    /// Nothing was written yet
+    #[default]
+    None,
+    /// Temperature curve gave higher speed
+    Temperature,
+    /// Usage curve gave higher speed
+    Usage,
+    /// Speed was set with [Command::Override]
+    Override,
+    /// Critical temperature or sensor failure
+    Failsafe,
+    /// Temperature can't be read, last speed is kept
+    Hold,
+    /// Full speed pulse to recover stalled fan
+    KickStart,
+    /// Fan is controlled by GPU firmware
+    Firmware,
+    /// Other agent controls fan
+    BackOff,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SensorStatus {
+    pub name: String,
+    /// Temperature in Celsius, `None` if it can't be read or it's implausible
+    pub temp: Option<f64>,
+    pub crit: Option<f64>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct FanStatus {
+    pub card: Card,
+    pub sensors: Vec<SensorStatus>,
+    /// Temperature used by temperature curve
+    pub temp: Option<f64>,
+    pub usage: f64,
+    /// Speed from temperature curve
+    pub temp_speed: f64,
+    /// Speed from usage curve
+    pub usage_speed: f64,
+    pub source: SpeedSource,
+    /// Speed amdfand wants to have
+    pub target_speed: Option<f64>,
+    /// Modulation amdfand wants to have
+    pub target_pwm: Option<u64>,
+    /// Modulation currently set in `pwm1`
+    pub written_pwm: Option<u64>,
+    pub health: FanHealth,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OverrideStatus {
+    pub speed: f64,
+    pub remaining_secs: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Status {
+    pub pid: Pid,
+    /// Active config file
+    pub config: String,
+    pub speed_override: Option<OverrideStatus>,
+    pub fans: Vec<FanStatus>,
+}
+
+/// Socket of amdfand instance with given pid file name
+pub fn sock_file<P: AsRef<Path>>(dir: P, name: &str) -> PathBuf {
+    dir.as_ref().join(format!("{}.sock", name))
+}
+
+pub fn send_command<P: AsRef<Path>>(sock_path: P, cmd: Command) -> crate::Result<Response> {
+    let sock_path = sock_path.as_ref();
+    if !sock_path.exists() {
+        return Err(FanCmdError::NoSockFile(sock_path.to_path_buf()).into());
+    }
+
+    let mut stream = UnixStream::connect(sock_path).map_err(FanCmdError::UnableToConnect)?;
+    let s = ron::to_string(&cmd).map_err(FanCmdError::Serialize)?;
+    if stream.write_all(format!("{}\n", s).as_bytes()).is_ok() {
+        info!("Command send");
+    }
+
+    let res: Response = {
+        let mut s = String::with_capacity(512);
+        let _ = stream.read_to_string(&mut s);
+        ron::from_str(&s).map_err(FanCmdError::Serialize)?
+    };
+
+    Ok(res)
+}
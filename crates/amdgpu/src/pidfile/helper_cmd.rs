@@ -5,13 +5,15 @@ use std::os::unix::net::UnixStream;
 
 use tracing::info;
 
+use crate::environment::Environment;
+use crate::pending_change::PendingChange;
 use crate::pidfile::{Pid, PidResponse};
 
 #[derive(Debug, thiserror::Error)]
 pub enum GuiHelperError {
     #[error("GUI Helper socket file not found. Is service running?")]
     NoSockFile,
-    #[error("Failed to connect to /var/lib/amdfand/helper.sock. {0}")]
+    #[error("Failed to connect to GUI helper socket. {0}")]
     UnableToConnect(#[from] std::io::Error),
     #[error("Failed to service helper command. {0}")]
     Serialize(#[from] ron::Error),
@@ -19,9 +21,18 @@ pub enum GuiHelperError {
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
 pub enum Command {
-    ReloadConfig { pid: Pid },
+    ReloadConfig {
+        pid: Pid,
+    },
     FanServices,
-    SaveFanConfig { path: String, content: String },
+    SaveFanConfig {
+        path: String,
+        content: String,
+    },
+    /// Voltage change applied with `amdvold apply --confirm-within`
+    PendingVoltage,
+    /// Keep voltage change before amdvold watchdog reverts it
+    ConfirmVoltage,
 }
 
 #[derive(Debug, serde::Deserialize, serde::Serialize)]
@@ -30,6 +41,12 @@ pub enum Response {
     Services(Vec<Pid>),
     ConfigFileSaved,
     ConfigFileSaveFailed(String),
+    ConfigReloaded,
+    ConfigReloadFailed(String),
+    PendingVoltage(Option<PendingChange>),
+    /// Confirmed change, `None` if nothing was waiting
+    VoltageConfirmed(Option<PendingChange>),
+    VoltageConfirmFailed(String),
 }
 
 impl PidResponse for Response {
@@ -38,12 +55,12 @@ impl PidResponse for Response {
     }
 }
 
-pub fn sock_file() -> std::path::PathBuf {
-    std::path::Path::new("/tmp").join("amdgui-helper.sock")
+pub fn sock_file(env: &Environment) -> std::path::PathBuf {
+    env.runtime_dir.join("amdgui-helper.sock")
 }
 
-pub fn send_command(cmd: Command) -> crate::Result<Response> {
-    let sock_path = sock_file();
+pub fn send_command(env: &Environment, cmd: Command) -> crate::Result<Response> {
+    let sock_path = sock_file(env);
 
     if !sock_path.exists() {
         return Err(GuiHelperError::NoSockFile.into());
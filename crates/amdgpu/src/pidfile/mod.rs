@@ -9,10 +9,13 @@ use serde::de::DeserializeOwned;
 use serde::Serialize;
 use tracing::{error, info, warn};
 
+pub mod cards;
+pub mod fan_cmd;
 pub mod helper_cmd;
 pub mod ports;
+pub mod reload;
 
-#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, serde::Deserialize, serde::Serialize)]
 #[serde(transparent)]
 pub struct Pid(pub i32);
 
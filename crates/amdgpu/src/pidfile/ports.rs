@@ -7,13 +7,14 @@ use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 use tracing::info;
 
+use crate::environment::Environment;
 use crate::pidfile::PidResponse;
 
 #[derive(Debug, thiserror::Error)]
 pub enum PortsError {
     #[error("AMD GPU ports socket file not found. Is service running?")]
     NoSockFile,
-    #[error("Failed to connect to /tmp/amdgpu-ports.sock. {0}")]
+    #[error("Failed to connect to AMD GPU ports socket. {0}")]
     UnableToConnect(#[from] std::io::Error),
     #[error("Failed to ports command. {0}")]
     Serialize(#[from] ron::Error),
@@ -187,23 +188,18 @@ pub struct OutputMode {
     pub height: u16,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub enum Status {
     #[serde(rename = "c")]
     Connected,
+    #[default]
     #[serde(rename = "d")]
     Disconnected,
 }
 
-impl Default for Status {
-    fn default() -> Self {
-        Self::Disconnected
-    }
-}
-
 impl Output {
-    fn to_path(&self) -> PathBuf {
-        PathBuf::new().join("/sys/class/drm").join(format!(
+    fn to_path(&self, env: &Environment) -> PathBuf {
+        env.drm_dir().join(format!(
             "card{}-{}{}-{}",
             self.card,
             self.port_type,
@@ -215,13 +211,13 @@ impl Output {
         ))
     }
 
-    fn status_path(&self) -> PathBuf {
-        self.to_path().join("status")
+    fn status_path(&self, env: &Environment) -> PathBuf {
+        self.to_path(env).join("status")
     }
 
-    pub fn read_status(&self) -> Option<Status> {
+    pub fn read_status(&self, env: &Environment) -> Option<Status> {
         Some(
-            match std::fs::read_to_string(self.status_path()).ok()?.trim() {
+            match std::fs::read_to_string(self.status_path(env)).ok()?.trim() {
                 "connected" => Status::Connected,
                 "disconnected" => Status::Disconnected,
                 _ => return None,
@@ -247,12 +243,12 @@ impl PidResponse for Response {
     }
 }
 
-pub fn sock_file() -> PathBuf {
-    std::path::Path::new("/tmp").join("amdgpu-ports.sock")
+pub fn sock_file(env: &Environment) -> PathBuf {
+    env.runtime_dir.join("amdgpu-ports.sock")
 }
 
-pub fn send_command(cmd: Command) -> crate::Result<Response> {
-    let sock_path = sock_file();
+pub fn send_command(env: &Environment, cmd: Command) -> crate::Result<Response> {
+    let sock_path = sock_file(env);
 
     if !sock_path.exists() {
         return Err(PortsError::NoSockFile.into());
@@ -0,0 +1,90 @@
+//! Outcome of config reload triggered with SIGHUP. amdfand writes it to
+//! `/var/lib/amdfand/<pid>.reload` so GUI helper can report it back.
+
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::pidfile::Pid;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub enum ReloadOutcome {
+    /// New config is used
+    Reloaded,
+    /// New config was rejected, previous one is still used
+    Failed(String),
+}
+
+/// Path to reload outcome file of given process
+pub fn reload_file<P: AsRef<Path>>(dir: P, pid: Pid) -> PathBuf {
+    dir.as_ref().join(format!("{}.reload", *pid))
+}
+
+/// Save outcome of last reload. File is replaced at once so reader never sees
+/// partial content
+pub fn write_outcome<P: AsRef<Path>>(
+    dir: P,
+    pid: Pid,
+    outcome: &ReloadOutcome,
+) -> std::io::Result<()> {
+    let content = ron::to_string(outcome)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    crate::utils::atomic_write(reload_file(dir, pid), content, 0)
+}
+
+pub fn read_outcome<P: AsRef<Path>>(dir: P, pid: Pid) -> Option<ReloadOutcome> {
+    let content = std::fs::read_to_string(reload_file(dir, pid)).ok()?;
+    ron::from_str(&content).ok()
+}
+
+/// Remove previous outcome, must be called before sending SIGHUP
+pub fn clear_outcome<P: AsRef<Path>>(dir: P, pid: Pid) {
+    let _ = std::fs::remove_file(reload_file(dir, pid));
+}
+
+/// Wait until process reports reload outcome. `None` if it did not respond
+/// within `timeout`
+pub fn wait_for_outcome<P: AsRef<Path>>(
+    dir: P,
+    pid: Pid,
+    timeout: Duration,
+) -> Option<ReloadOutcome> {
+    let start = Instant::now();
+    loop {
+        if let Some(outcome) = read_outcome(dir.as_ref(), pid) {
+            return Some(outcome);
+        }
+        if start.elapsed() >= timeout {
+            return None;
+        }
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn outcome_round_trip() {
+        let dir = tempdir::TempDir::new("reload").unwrap();
+        let pid = Pid(42);
+        assert_eq!(
+            wait_for_outcome(dir.path(), pid, Duration::from_millis(0)),
+            None
+        );
+
+        let outcome = ReloadOutcome::Failed("Fan speed 120.0 is too high".into());
+        write_outcome(dir.path(), pid, &outcome).unwrap();
+        assert_eq!(
+            wait_for_outcome(dir.path(), pid, Duration::from_millis(0)),
+            Some(outcome)
+        );
+
+        clear_outcome(dir.path(), pid);
+        assert_eq!(read_outcome(dir.path(), pid), None);
+    }
+}
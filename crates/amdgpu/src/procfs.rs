@@ -0,0 +1,91 @@
+//! Minimal `/proc` inspection used to find who else is touching GPU files.
+
+use std::path::{Path, PathBuf};
+
+/// Process which has opened file from inspected directory
+#[derive(Clone, Debug, PartialEq)]
+pub struct FileHolder {
+    pub pid: i32,
+    /// Process name from `/proc/<pid>/comm`
+    pub name: String,
+    /// Opened file
+    pub path: PathBuf,
+}
+
+impl std::fmt::Display for FileHolder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}({}) {}", self.name, self.pid, self.path.display())
+    }
+}
+
+/// Find processes (except current one) which have any file from `dir` opened
+/// by scanning `<proc_dir>/*/fd`. Processes which can't be inspected are
+/// skipped
+pub fn file_holders<P: AsRef<Path>, D: AsRef<Path>>(proc_dir: P, dir: D) -> Vec<FileHolder> {
+    let dir = std::fs::canonicalize(dir.as_ref()).unwrap_or_else(|_| dir.as_ref().to_path_buf());
+    let own = std::process::id() as i32;
+    let entries = match std::fs::read_dir(proc_dir.as_ref()) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut holders = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let pid = entry.file_name().to_str()?.parse::<i32>().ok()?;
+            Some((pid, entry.path()))
+        })
+        .filter(|(pid, _)| *pid != own)
+        .flat_map(|(pid, process_dir)| {
+            let name = std::fs::read_to_string(process_dir.join("comm"))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_default();
+            std::fs::read_dir(process_dir.join("fd"))
+                .into_iter()
+                .flatten()
+                .filter_map(|fd| std::fs::read_link(fd.ok()?.path()).ok())
+                .filter(|path| path.starts_with(&dir))
+                .map(|path| FileHolder {
+                    pid,
+                    name: name.clone(),
+                    path,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    holders.sort_by_key(|holder| holder.pid);
+    holders
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+
+    #[test]
+    fn finds_processes_with_opened_files() {
+        let proc_dir = tempdir::TempDir::new("proc").unwrap();
+        let hwmon = tempdir::TempDir::new("hwmon").unwrap();
+        let hwmon_dir = std::fs::canonicalize(hwmon.path()).unwrap();
+
+        let fancontrol = proc_dir.path().join("1234");
+        std::fs::create_dir_all(fancontrol.join("fd")).unwrap();
+        std::fs::write(fancontrol.join("comm"), "fancontrol\n").unwrap();
+        symlink(hwmon_dir.join("pwm1"), fancontrol.join("fd").join("3")).unwrap();
+        symlink("/dev/null", fancontrol.join("fd").join("0")).unwrap();
+
+        let other = proc_dir.path().join("99");
+        std::fs::create_dir_all(other.join("fd")).unwrap();
+        symlink("/dev/null", other.join("fd").join("0")).unwrap();
+        std::fs::create_dir_all(proc_dir.path().join("self")).unwrap();
+
+        assert_eq!(
+            file_holders(proc_dir.path(), hwmon.path()),
+            vec![FileHolder {
+                pid: 1234,
+                name: "fancontrol".into(),
+                path: hwmon_dir.join("pwm1"),
+            }]
+        );
+    }
+}
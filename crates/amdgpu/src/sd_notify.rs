@@ -0,0 +1,151 @@
+//! systemd service notification protocol without libsystemd.
+//!
+//! Messages are sent as datagrams to socket from `$NOTIFY_SOCKET`. When
+//! variable is not set (service is not started by systemd or `Type` is not
+//! `notify`) every message is silently dropped.
+
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tracing::debug;
+
+pub static NOTIFY_SOCKET: &str = "NOTIFY_SOCKET";
+pub static WATCHDOG_USEC: &str = "WATCHDOG_USEC";
+pub static WATCHDOG_PID: &str = "WATCHDOG_PID";
+
+/// Connection to service manager
+#[derive(Debug, Default)]
+pub struct Notifier {
+    addr: Option<String>,
+}
+
+impl Notifier {
+    /// Notifier using `$NOTIFY_SOCKET`
+    pub fn from_env() -> Self {
+        Self {
+            addr: std::env::var(NOTIFY_SOCKET).ok().filter(|s| !s.is_empty()),
+        }
+    }
+
+    /// Notifier using given socket. Name starting with `@` is abstract socket
+    pub fn new<S: Into<String>>(addr: S) -> Self {
+        Self {
+            addr: Some(addr.into()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.addr.is_some()
+    }
+
+    /// Send raw message, ex. `READY=1\nSTATUS=Running`
+    pub fn notify(&self, state: &str) -> std::io::Result<()> {
+        let addr = match self.addr.as_deref() {
+            Some(addr) => addr,
+            None => return Ok(()),
+        };
+        debug!("sd_notify {:?}", state);
+        let socket = UnixDatagram::unbound()?;
+        socket.send_to_addr(state.as_bytes(), &socket_addr(addr)?)?;
+        Ok(())
+    }
+
+    /// Service finished startup
+    pub fn ready(&self, status: &str) -> std::io::Result<()> {
+        self.notify(&format!("READY=1\nSTATUS={}", status))
+    }
+
+    /// Service is alive, resets watchdog timer
+    pub fn watchdog(&self, status: &str) -> std::io::Result<()> {
+        self.notify(&format!("WATCHDOG=1\nSTATUS={}", status))
+    }
+
+    /// Update status without resetting watchdog timer
+    pub fn status(&self, status: &str) -> std::io::Result<()> {
+        self.notify(&format!("STATUS={}", status))
+    }
+
+    /// Service begins shutdown
+    pub fn stopping(&self) -> std::io::Result<()> {
+        self.notify("STOPPING=1")
+    }
+
+    /// Watchdog timeout requested by service manager for current process
+    pub fn watchdog_timeout(&self) -> Option<Duration> {
+        if !self.is_enabled() {
+            return None;
+        }
+        if let Ok(pid) = std::env::var(WATCHDOG_PID) {
+            if pid.parse::<u32>().ok() != Some(std::process::id()) {
+                return None;
+            }
+        }
+        std::env::var(WATCHDOG_USEC)
+            .ok()?
+            .parse::<u64>()
+            .ok()
+            .filter(|usec| *usec > 0)
+            .map(Duration::from_micros)
+    }
+}
+
+fn socket_addr(addr: &str) -> std::io::Result<SocketAddr> {
+    match addr.strip_prefix('@') {
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            SocketAddr::from_abstract_name(name.as_bytes())
+        }
+        None => SocketAddr::from_pathname(PathBuf::from(addr)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::Notifier;
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 256];
+        let len = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..len]).to_string()
+    }
+
+    #[test]
+    fn send_messages() {
+        let dir = tempdir::TempDir::new("sd-notify").unwrap();
+        let path = dir.path().join("notify.sock");
+        let socket = UnixDatagram::bind(&path).unwrap();
+        let notifier = Notifier::new(path.to_str().unwrap());
+
+        notifier.ready("card0 40.0°C 30.0%").unwrap();
+        assert_eq!(receive(&socket), "READY=1\nSTATUS=card0 40.0°C 30.0%");
+        notifier.watchdog("card0 41.0°C 31.0%").unwrap();
+        assert_eq!(receive(&socket), "WATCHDOG=1\nSTATUS=card0 41.0°C 31.0%");
+        notifier.status("card0 - -").unwrap();
+        assert_eq!(receive(&socket), "STATUS=card0 - -");
+        notifier.stopping().unwrap();
+        assert_eq!(receive(&socket), "STOPPING=1");
+    }
+
+    #[test]
+    fn abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+
+        let name = format!("amdgpu-sd-notify-{}", std::process::id());
+        let addr = SocketAddr::from_abstract_name(name.as_bytes()).unwrap();
+        let socket = UnixDatagram::bind_addr(&addr).unwrap();
+        Notifier::new(format!("@{}", name)).stopping().unwrap();
+        assert_eq!(receive(&socket), "STOPPING=1");
+    }
+
+    #[test]
+    fn disabled_without_socket() {
+        let notifier = Notifier::default();
+        assert!(!notifier.is_enabled());
+        notifier.ready("").unwrap();
+        assert_eq!(notifier.watchdog_timeout(), None);
+    }
+}
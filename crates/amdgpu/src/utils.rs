@@ -2,8 +2,9 @@ use std::io::ErrorKind;
 
 use tracing::{error, info};
 
+use crate::environment::Environment;
 use crate::hw_mon::{HwMon, RootPath};
-use crate::{hw_mon, Card, CONFIG_DIR, ROOT_DIR};
+use crate::{hw_mon, Card};
 
 pub type Result<T> = std::result::Result<T, AmdGpuError>;
 
@@ -47,6 +48,103 @@ pub fn write<P: AsRef<std::path::Path>, C: AsRef<[u8]>>(path: P, contents: C) ->
     })
 }
 
+/// Number of previous config versions kept next to config file
+pub const CONFIG_BACKUPS: usize = 5;
+
+/// Location of `n`-th previous version of file, ex. `mapping.toml.~1~`. `1`
+/// is the newest one
+pub fn backup_path<P: AsRef<std::path::Path>>(path: P, n: usize) -> std::path::PathBuf {
+    let mut name = path.as_ref().as_os_str().to_os_string();
+    name.push(format!(".~{}~", n));
+    std::path::PathBuf::from(name)
+}
+
+/// Replace file content so it's never left truncated. Content is written to
+/// temporary file which is synced and renamed over target, then directory is
+/// synced. Owner and mode of replaced file are kept. Up to `backups` previous
+/// versions are kept, writing same content does not rotate backups
+pub fn atomic_write<P: AsRef<std::path::Path>, C: AsRef<[u8]>>(
+    path: P,
+    contents: C,
+    backups: usize,
+) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let path = path.as_ref();
+    let contents = contents.as_ref();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => std::path::Path::new("."),
+    };
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(format!(".{}.tmp", std::process::id()));
+    let tmp = dir.join(tmp_name);
+
+    let mut file = std::fs::File::create(&tmp)?;
+    if let Err(e) = copy_metadata(path, &file)
+        .and_then(|_| file.write_all(contents))
+        .and_then(|_| file.sync_all())
+    {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    drop(file);
+
+    let changed = std::fs::read(path)
+        .map(|old| old != contents)
+        .unwrap_or(false);
+    let rotated = if backups > 0 && changed {
+        rotate_backups(path, backups)
+    } else {
+        Ok(())
+    };
+    if let Err(e) = rotated.and_then(|_| std::fs::rename(&tmp, path)) {
+        let _ = std::fs::remove_file(&tmp);
+        return Err(e);
+    }
+    std::fs::File::open(dir)?.sync_all()
+}
+
+/// Give temporary file owner and mode of file it replaces. Changing owner
+/// requires root, it's skipped when it fails so user can still write own
+/// files
+fn copy_metadata(path: &std::path::Path, file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = match std::fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return Ok(()),
+    };
+    let _ = std::os::unix::fs::fchown(file, Some(metadata.uid()), Some(metadata.gid()));
+    file.set_permissions(metadata.permissions())
+}
+
+/// Shift backups by one and make current file the newest backup. Current
+/// file stays in place until it's replaced
+fn rotate_backups(path: &std::path::Path, backups: usize) -> std::io::Result<()> {
+    let _ = std::fs::remove_file(backup_path(path, backups));
+    for n in (1..backups).rev() {
+        let from = backup_path(path, n);
+        if from.exists() {
+            std::fs::rename(from, backup_path(path, n + 1))?;
+        }
+    }
+    let newest = backup_path(path, 1);
+    if std::fs::hard_link(path, &newest).is_err() {
+        std::fs::copy(path, &newest)?;
+    }
+    Ok(())
+}
+
+/// Existing backups of file, newest first
+pub fn list_backups<P: AsRef<std::path::Path>>(path: P) -> Vec<(usize, std::path::PathBuf)> {
+    (1..)
+        .map(|n| (n, backup_path(&path, n)))
+        .take_while(|(_, backup)| backup.exists())
+        .collect()
+}
+
 /// linear mapping from the xrange to the yrange
 pub fn linear_map(x: f64, x1: f64, x2: f64, y1: f64, y2: f64) -> f64 {
     let m = (y2 - y1) / (x2 - x1);
@@ -54,11 +152,12 @@ pub fn linear_map(x: f64, x1: f64, x2: f64, y1: f64, y2: f64) -> f64 {
 }
 
 /// Read all available graphic cards from direct rendering manager
-pub fn read_cards() -> Result<Vec<Card>> {
-    Ok(std::fs::read_dir(ROOT_DIR)
+pub fn read_cards(env: &Environment) -> Result<Vec<Card>> {
+    let drm_dir = env.drm_dir();
+    Ok(std::fs::read_dir(&drm_dir)
         .map_err(|io| AmdGpuError::ReadDir {
             io,
-            path: ROOT_DIR.into(),
+            path: drm_dir.to_str().map(String::from).unwrap_or_default(),
         })?
         .filter_map(|entry| entry.ok())
         .filter_map(|entry| entry.file_name().as_os_str().to_str().map(String::from))
@@ -68,12 +167,12 @@ pub fn read_cards() -> Result<Vec<Card>> {
 
 /// Wrap cards in HW Mon manipulator and
 /// filter cards so only amd and listed in config cards are accessible
-pub fn hw_mons(filter: bool) -> Result<Vec<HwMon>> {
-    Ok(read_cards()?
+pub fn hw_mons(env: &Environment, filter: bool) -> Result<Vec<HwMon>> {
+    Ok(read_cards(env)?
         .into_iter()
         .flat_map(|card| {
             info!("opening hw mon for {:?}", card);
-            hw_mon::open_hw_mon(card)
+            hw_mon::open_hw_mon(env, card)
         })
         .filter(|hw_mon| {
             !filter || {
@@ -96,18 +195,22 @@ pub fn ensure_config<Config, Error, P>(config_path: P) -> std::result::Result<Co
 where
     Config: serde::Serialize + serde::de::DeserializeOwned + Default + Sized,
     P: AsRef<std::path::Path>,
-    Error: From<std::io::Error>,
+    Error: From<std::io::Error> + From<toml::de::Error>,
 {
     match std::fs::read_to_string(&config_path) {
-        Ok(s) => Ok(toml::from_str::<Config>(s.as_str()).unwrap()),
+        Ok(s) => Ok(toml::from_str::<Config>(s.as_str())?),
         Err(e) if e.kind() == ErrorKind::NotFound => {
             let config = Config::default();
-            std::fs::write(config_path, toml::to_string(&config).unwrap())?;
+            atomic_write(
+                config_path,
+                toml::to_string(&config).unwrap(),
+                CONFIG_BACKUPS,
+            )?;
             Ok(config)
         }
         Err(e) => {
             error!("{:?}", e);
-            panic!();
+            Err(e.into())
         }
     }
 }
@@ -129,12 +232,111 @@ pub fn load_temp_inputs<Root: RootPath>(hw_mon: &HwMon<Root>) -> Vec<String> {
 }
 
 /// Create config directory if does not exists
-pub fn ensure_config_dir() -> Result<()> {
-    if std::fs::read(CONFIG_DIR).map_err(|e| e.kind() == ErrorKind::NotFound) == Err(true) {
-        std::fs::create_dir_all(CONFIG_DIR).map_err(|e| AmdGpuError::CreatePath {
+pub fn ensure_config_dir(env: &Environment) -> Result<()> {
+    let config_dir = &env.config_dir;
+    if std::fs::read(config_dir).map_err(|e| e.kind() == ErrorKind::NotFound) == Err(true) {
+        std::fs::create_dir_all(config_dir).map_err(|e| AmdGpuError::CreatePath {
             io: e,
-            path: CONFIG_DIR.into(),
+            path: config_dir.to_str().map(String::from).unwrap_or_default(),
         })?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn atomic_write_rotates_backups() {
+        let dir = tempdir::TempDir::new("atomic").unwrap();
+        let path = dir.path().join("mapping.toml");
+        let read = |path: &std::path::Path| std::fs::read_to_string(path).unwrap();
+
+        for version in 1..=4 {
+            atomic_write(&path, format!("version = {}", version), 2).unwrap();
+        }
+        // unchanged content does not push out backups
+        atomic_write(&path, "version = 4", 2).unwrap();
+
+        assert_eq!(read(&path), "version = 4");
+        assert_eq!(read(&backup_path(&path, 1)), "version = 3");
+        assert_eq!(read(&backup_path(&path, 2)), "version = 2");
+        assert!(!backup_path(&path, 3).exists());
+        assert_eq!(
+            list_backups(&path),
+            vec![(1, backup_path(&path, 1)), (2, backup_path(&path, 2))]
+        );
+        assert_eq!(
+            backup_path(&path, 1).file_name().unwrap(),
+            "mapping.toml.~1~"
+        );
+        // no temporary files are left behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn atomic_write_cleans_up_after_failed_rotation() {
+        let dir = tempdir::TempDir::new("atomic").unwrap();
+        let path = dir.path().join("mapping.toml");
+        atomic_write(&path, "version = 1", 2).unwrap();
+        atomic_write(&path, "version = 2", 2).unwrap();
+        // backup can't be shifted over directory
+        std::fs::create_dir(backup_path(&path, 2)).unwrap();
+        std::fs::write(backup_path(&path, 2).join("keep"), "").unwrap();
+
+        assert!(atomic_write(&path, "version = 3", 2).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "version = 2");
+        let tmp_files = std::fs::read_dir(dir.path())
+            .unwrap()
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().ends_with(".tmp"))
+            .count();
+        assert_eq!(tmp_files, 0);
+    }
+
+    #[test]
+    fn atomic_write_keeps_mode() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempdir::TempDir::new("atomic").unwrap();
+        let path = dir.path().join("mapping.toml");
+        std::fs::write(&path, "version = 1").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        let before = std::fs::metadata(&path).unwrap();
+
+        atomic_write(&path, "version = 2", 0).unwrap();
+        let after = std::fs::metadata(&path).unwrap();
+        assert_eq!(after.permissions().mode() & 0o777, 0o640);
+        assert_eq!((after.uid(), after.gid()), (before.uid(), before.gid()));
+        assert_ne!(after.ino(), before.ino());
+    }
+
+    #[test]
+    fn hw_mons_from_environment() {
+        let root = tempdir::TempDir::new("env").unwrap();
+        let env = Environment::under(root.path());
+        let card = |name: &str, vendor: &str| {
+            let device = env.drm_dir().join(name).join("device");
+            std::fs::create_dir_all(device.join("hwmon").join("hwmon3")).unwrap();
+            std::fs::write(device.join("vendor"), vendor).unwrap();
+            std::fs::write(device.join("hwmon").join("hwmon3").join("name"), "amdgpu").unwrap();
+        };
+        card("card0", "0x1002\n");
+        card("card1", "0x10de\n");
+        std::fs::create_dir_all(env.drm_dir().join("card0-DP-1")).unwrap();
+
+        let mut cards = read_cards(&env).unwrap();
+        cards.sort_by_key(|card| card.0);
+        assert_eq!(cards, vec![Card(0), Card(1)]);
+
+        let amd = hw_mons(&env, true).unwrap();
+        assert_eq!(amd.len(), 1);
+        assert_eq!(
+            amd[0].mon_dir(),
+            env.drm_dir().join("card0/device/hwmon/hwmon3")
+        );
+        assert!(amd[0].name_is_amd());
+        assert_eq!(hw_mons(&env, false).unwrap().len(), 2);
+    }
+}
@@ -5,8 +5,10 @@
 //! It is responsible for:
 //! * Loading all amdfand processes. In order to do this process needs to be
 //!   killed with signal 0 to check if it still is alive
-//! * Reload amdfand process with signal SIGHUP
+//! * Reload amdfand process with signal SIGHUP and report if new config was
+//!   accepted
 //! * Save changed config file
+//! * Give fans back to GPU firmware when amdfand dies without clean shutdown
 //!
 //! It is using `/tmp/amdgui-helper.sock` file and `ron` serialization for
 //! communication. After each operation connection is terminated so each command
@@ -20,10 +22,18 @@ use std::ffi::OsStr;
 use std::fs::Permissions;
 use std::os::unix::fs::PermissionsExt;
 use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
+use amdgpu::environment::{Environment, Overrides};
+use amdgpu::pending_change::{confirm, read_pending, Confirmation};
+use amdgpu::pidfile::cards::{is_alive, restore_orphaned};
 use amdgpu::pidfile::helper_cmd::{Command, Response};
+use amdgpu::pidfile::reload::{clear_outcome, wait_for_outcome, ReloadOutcome};
 use amdgpu::pidfile::{handle_connection, Pid};
+use amdgpu::utils::{atomic_write, CONFIG_BACKUPS};
 use amdgpu::IoFailure;
+use gumdrop::Options;
 use tracing::{error, info, warn};
 
 #[derive(Debug, thiserror::Error)]
@@ -36,16 +46,54 @@ pub enum Error {
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[derive(Options)]
+pub struct Opts {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(
+        no_short,
+        help = "procfs mount point, by default /proc (env AMDGPU_PROCFS_ROOT)"
+    )]
+    procfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "Services state directory, by default /var/lib (env AMDGPU_STATE_DIR)"
+    )]
+    state_dir: Option<String>,
+    #[options(
+        no_short,
+        help = "Socket directory, by default /tmp (env AMDGPU_RUNTIME_DIR)"
+    )]
+    runtime_dir: Option<String>,
+}
+
+impl Opts {
+    fn environment(&self) -> Environment {
+        Environment::from_env().with_overrides(Overrides {
+            procfs_root: self.procfs_root.clone(),
+            state_dir: self.state_dir.clone(),
+            runtime_dir: self.runtime_dir.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+/// How long reloaded process may take to report new config status
+const RELOAD_TIMEOUT: Duration = Duration::from_secs(10);
+/// How often amdfand processes are checked
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(2);
+
 fn main() -> Result<()> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "DEBUG");
     }
     tracing_subscriber::fmt::init();
+    let env = Opts::parse_args_default_or_exit().environment();
 
-    let mut lock = amdgpu::lock_file::PidLock::new("amdgui", String::from("helper"))?;
+    let mut lock = amdgpu::lock_file::PidLock::new(&env, "amdgui", String::from("helper"))?;
     lock.acquire()?;
 
-    let sock_path = amdgpu::pidfile::helper_cmd::sock_file();
+    let sock_path = amdgpu::pidfile::helper_cmd::sock_file(&env);
     let listener = {
         let _ = std::fs::remove_file(&sock_path);
 
@@ -58,8 +106,16 @@ fn main() -> Result<()> {
         error!("Failed to change gui helper socket file mode. {:?}", e);
     }
 
+    {
+        let env = env.clone();
+        std::thread::spawn(move || supervise(&env));
+    }
+
+    let env = &env;
     while let Ok((stream, _addr)) = listener.accept() {
-        handle_connection::<_, Command, Response>(stream, handle_command);
+        handle_connection::<_, Command, Response>(stream, move |service, cmd| {
+            handle_command(service, cmd, env)
+        });
     }
 
     lock.release()?;
@@ -68,40 +124,89 @@ fn main() -> Result<()> {
 
 pub type Service = amdgpu::pidfile::Service<Response>;
 
-fn handle_command(service: Service, cmd: Command) {
+/// Restore firmware fan control of amdfand instances which died without clean
+/// shutdown
+fn supervise(env: &Environment) {
+    let state_dir = env.fan_state_dir();
+    loop {
+        for cards in restore_orphaned(&state_dir, |pid| is_alive(&env.procfs_root, pid)) {
+            info!(
+                "Restored automatic fan control after amdfand {} died: {:?}",
+                *cards.pid, cards.hw_mons
+            );
+        }
+        std::thread::sleep(SUPERVISE_INTERVAL);
+    }
+}
+
+fn handle_command(service: Service, cmd: Command, env: &Environment) {
     match cmd {
         Command::ReloadConfig { pid } => {
             info!("Reloading config file for pid {:?}", pid);
-            handle_reload_config(service, pid);
+            handle_reload_config(service, env.fan_state_dir(), pid);
         }
-        Command::FanServices => handle_fan_services(service),
+        Command::FanServices => handle_fan_services(service, &env.fan_state_dir()),
         Command::SaveFanConfig { path, content } => handle_save_fan_config(service, path, content),
+        Command::PendingVoltage => {
+            let mut service = service;
+            service.write_response(Response::PendingVoltage(read_pending(env)));
+        }
+        Command::ConfirmVoltage => handle_confirm_voltage(service, env),
+    }
+}
+
+fn handle_confirm_voltage(mut service: Service, env: &Environment) {
+    match confirm(env) {
+        Ok(Confirmation::Confirmed(change)) => {
+            info!("Confirmed voltage change {:?}", change);
+            service.write_response(Response::VoltageConfirmed(Some(change)));
+        }
+        Ok(Confirmation::Reverting) => service.write_response(Response::VoltageConfirmFailed(
+            String::from("Change was not confirmed in time and is already reverting"),
+        )),
+        Ok(Confirmation::Nothing) => service.write_response(Response::VoltageConfirmed(None)),
+        Err(e) => service.write_response(Response::VoltageConfirmFailed(e.to_string())),
     }
 }
 
 fn handle_save_fan_config(mut service: Service, path: String, content: String) {
-    match std::fs::write(path, content) {
+    match atomic_write(path, content, CONFIG_BACKUPS) {
         Err(e) => service.write_response(Response::ConfigFileSaveFailed(format!("{:?}", e))),
         Ok(..) => service.write_response(Response::ConfigFileSaved),
     }
 }
 
-fn handle_fan_services(mut service: Service) {
+fn handle_fan_services(mut service: Service, state_dir: &Path) {
     info!("Loading fan services");
-    let services = read_fan_services();
+    let services = read_fan_services(state_dir);
     info!("Loaded fan services pid {:?}", services);
     service.write_response(Response::Services(services));
 }
 
-fn handle_reload_config(service: Service, pid: Pid) {
-    unsafe {
-        nix::libc::kill(pid.0, nix::sys::signal::Signal::SIGHUP as i32);
-    }
-    service.kill();
+fn handle_reload_config(service: Service, state_dir: PathBuf, pid: Pid) {
+    // amdfand checks reload flag once per tick so waiting must not block other
+    // connections
+    std::thread::spawn(move || {
+        let mut service = service;
+        clear_outcome(&state_dir, pid);
+        unsafe {
+            nix::libc::kill(pid.0, nix::sys::signal::Signal::SIGHUP as i32);
+        }
+        let response = match wait_for_outcome(&state_dir, pid, RELOAD_TIMEOUT) {
+            Some(ReloadOutcome::Reloaded) => Response::ConfigReloaded,
+            Some(ReloadOutcome::Failed(msg)) => Response::ConfigReloadFailed(msg),
+            None => {
+                warn!("Process {:?} did not report config reload outcome", pid);
+                Response::NoOp
+            }
+        };
+        service.write_response(response);
+        service.close();
+    });
 }
 
-fn read_fan_services() -> Vec<Pid> {
-    if let Ok(entry) = std::fs::read_dir("/var/lib/amdfand") {
+fn read_fan_services(state_dir: &Path) -> Vec<Pid> {
+    if let Ok(entry) = std::fs::read_dir(state_dir) {
         entry
             .filter(|e| {
                 e.as_ref()
@@ -125,7 +230,7 @@ fn read_fan_services() -> Vec<Pid> {
             .map(Pid)
             .collect()
     } else {
-        warn!("Directory /var/lib/amdfand not found");
+        warn!("Directory {:?} not found", state_dir);
         vec![]
     }
 }
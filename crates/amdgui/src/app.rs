@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
+use amdgpu::environment::Environment;
 use amdgpu::pidfile::ports::{Output, OutputType};
 use amdgpu::pidfile::Pid;
 use eframe::App;
@@ -66,8 +67,9 @@ impl From<Vec<Pid>> for FanServices {
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Default)]
 pub enum Page {
+    #[default]
     TempConfig,
     UsageConfig,
     Monitoring,
@@ -75,12 +77,6 @@ pub enum Page {
     Settings,
 }
 
-impl Default for Page {
-    fn default() -> Self {
-        Self::TempConfig
-    }
-}
-
 pub type FanConfig = Arc<Mutex<amdgpu_config::fan::Config>>;
 
 #[cfg(not(debug_assertions))]
@@ -159,16 +155,18 @@ impl App for AmdGui {
 }
 
 impl AmdGui {
-    pub fn new_with_config(config: FanConfig) -> Self {
+    /// `config` is merged with drop-in files and used for monitoring,
+    /// `base_config` is edited and saved
+    pub fn new_with_config(config: FanConfig, base_config: FanConfig) -> Self {
         Self {
             page: Default::default(),
             pid_files: SocketState::NotAvailable,
             outputs: SocketState::NotAvailable,
-            cooling_performance: CoolingPerformance::new(100, config.clone()),
-            temp_config: EditTempConfig::new(config.clone()),
-            usage_config: EditUsageConfig::new(config.clone()),
+            cooling_performance: CoolingPerformance::new(100, config),
+            temp_config: EditTempConfig::new(base_config.clone()),
+            usage_config: EditUsageConfig::new(base_config.clone()),
             outputs_settings: OutputsSettings::default(),
-            config: StatefulConfig::new(config),
+            config: StatefulConfig::new(base_config),
             reload_pid_list_delay: 0,
         }
     }
@@ -223,7 +221,7 @@ impl AmdGui {
         {
             use amdgpu::pidfile::helper_cmd::{send_command, Command, Response};
 
-            match send_command(Command::FanServices) {
+            match send_command(&Environment::from_env(), Command::FanServices) {
                 Ok(Response::Services(services))
                     if self
                         .pid_files
@@ -249,7 +247,7 @@ impl AmdGui {
         {
             use amdgpu::pidfile::ports::{send_command, Command, Response};
 
-            match send_command(Command::Ports) {
+            match send_command(&Environment::from_env(), Command::Ports) {
                 Ok(Response::NoOp) => {}
                 Ok(Response::Ports(outputs)) => {
                     let mut names = outputs.iter().fold(
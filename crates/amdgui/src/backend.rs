@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use egui::panel::TopBottomSide;
-use egui::{Align, Layout, PointerButton};
+use egui::{Align, Layout, PointerButton, UiBuilder};
 use parking_lot::Mutex;
 
 use crate::app::Page;
@@ -9,10 +9,10 @@ use crate::AmdGui;
 
 pub fn create_ui(amd_gui: Arc<Mutex<AmdGui>>, ctx: &egui::Context) {
     egui::containers::TopBottomPanel::new(TopBottomSide::Top, "menu").show(ctx, |ui| {
-        let mut child = ui.child_ui(
-            ui.available_rect_before_wrap(),
-            Layout::left_to_right(Align::default()),
-            None,
+        let mut child = ui.new_child(
+            UiBuilder::new()
+                .max_rect(ui.available_rect_before_wrap())
+                .layout(Layout::left_to_right(Align::default())),
         );
 
         if child
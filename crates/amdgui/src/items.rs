@@ -113,12 +113,12 @@ impl LineStyle {
     }
 }
 
-impl ToString for LineStyle {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for LineStyle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            LineStyle::Solid => "Solid".into(),
-            LineStyle::Dotted { spacing } => format!("Dotted{}Px", spacing),
-            LineStyle::Dashed { length } => format!("Dashed{}Px", length),
+            LineStyle::Solid => f.write_str("Solid"),
+            LineStyle::Dotted { spacing } => write!(f, "Dotted{}Px", spacing),
+            LineStyle::Dashed { length } => write!(f, "Dashed{}Px", length),
         }
     }
 }
@@ -117,7 +117,11 @@ impl PlotItem for Points {
 
         let default_stroke = Stroke::new(stroke_size, *color);
         let mut stem_stroke = default_stroke;
-        let stroke = (!filled).then_some(default_stroke).unwrap_or(Stroke::NONE);
+        let stroke = if *filled {
+            Stroke::NONE
+        } else {
+            default_stroke
+        };
         let fill = filled.then(|| *color).unwrap_or_default();
 
         if *highlight {
@@ -1,6 +1,8 @@
 use std::path::PathBuf;
 use std::sync::Arc;
 
+use amdgpu::environment::Environment;
+use amdgpu_config::fan::FAN_CONFIG_FILE;
 pub use app::AmdGui;
 use parking_lot::Mutex;
 use tokio::sync::mpsc::UnboundedReceiver;
@@ -12,7 +14,9 @@ pub mod backend;
 pub mod items;
 pub mod transform;
 pub mod widgets;
-pub use {egui, image, parking_lot};
+pub use egui;
+pub use image;
+pub use parking_lot;
 
 #[derive(Debug, gumdrop::Options)]
 struct Opts {
@@ -25,7 +29,7 @@ where
 {
     let config_path = gumdrop::parse_args_default_or_exit::<Opts>()
         .config
-        .unwrap_or_else(|| PathBuf::new().join(amdgpu_config::fan::DEFAULT_FAN_CONFIG_PATH));
+        .unwrap_or_else(|| Environment::from_env().config_file(FAN_CONFIG_FILE));
     let config_path = config_path.to_str().expect("Config path must exists");
 
     if std::env::var("RUST_LOG").is_err() {
@@ -34,6 +38,11 @@ where
     let config = Arc::new(Mutex::new(
         amdgpu_config::fan::load_config(config_path).expect("No FAN config"),
     ));
+    // editors save to base file, drop-in files and env overrides are only
+    // displayed
+    let base_config = Arc::new(Mutex::new(
+        amdgpu_config::fan::load_base_config(config_path).expect("No FAN config"),
+    ));
     tracing_subscriber::fmt::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .with_max_level({
@@ -49,7 +58,7 @@ where
             level
         })
         .init();
-    let amd_gui = Arc::new(Mutex::new(AmdGui::new_with_config(config)));
+    let amd_gui = Arc::new(Mutex::new(AmdGui::new_with_config(config, base_config)));
 
     let receiver = schedule_tick(amd_gui.clone());
 
@@ -1,5 +1,6 @@
 use std::collections::vec_deque::VecDeque;
 
+use amdgpu::environment::Environment;
 use amdmond_lib::AmdMon;
 use egui::{Color32, Ui};
 
@@ -14,7 +15,7 @@ pub struct CoolingPerformance {
 impl CoolingPerformance {
     #[allow(clippy::explicit_auto_deref)]
     pub fn new(capacity: usize, fan_config: FanConfig) -> Self {
-        let amd_mon = amdgpu::utils::hw_mons(true)
+        let amd_mon = amdgpu::utils::hw_mons(&Environment::from_env(), true)
             .map_err(|e| {
                 tracing::error!("Failed to open hw mon: {e}");
                 e
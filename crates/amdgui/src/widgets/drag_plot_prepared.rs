@@ -1,6 +1,6 @@
 use egui::{
     emath, pos2, remap_clamp, vec2, Align2, Color32, Layout, NumExt, Pos2, Response, Rgba, Shape,
-    Stroke, TextStyle, Ui,
+    Stroke, TextStyle, Ui, UiBuilder,
 };
 
 use crate::items::{Line, PlotItem, Value};
@@ -28,7 +28,11 @@ impl DragPlotPrepared {
 
         let transform = &self.transform;
 
-        let mut plot_ui = ui.child_ui(*transform.frame(), Layout::default(), None);
+        let mut plot_ui = ui.new_child(
+            UiBuilder::new()
+                .max_rect(*transform.frame())
+                .layout(Layout::default()),
+        );
         plot_ui.set_clip_rect(*transform.frame());
 
         for item in &self.items {
@@ -1,6 +1,7 @@
+use amdgpu::environment::Environment;
 use amdgpu::pidfile::helper_cmd::{send_command, Command, Response};
 use amdgpu_config::fan::UsagePoint;
-use egui::{emath, pos2, Align, Color32, Layout, PointerButton, Ui};
+use egui::{emath, pos2, Align, Color32, Layout, PointerButton, Ui, UiBuilder};
 
 use crate::app::{ChangeState, FanConfig, FanServices, StatefulConfig};
 use crate::widgets;
@@ -36,18 +37,18 @@ impl EditUsageConfig {
     pub fn draw(&mut self, ui: &mut Ui, pid_files: &mut FanServices, state: &mut StatefulConfig) {
         let available = ui.available_rect_before_wrap();
         ui.horizontal_top(|ui| {
-            ui.child_ui(
-                emath::Rect {
-                    min: available.min,
-                    max: pos2(available.width() / 2.0, available.height()),
-                },
-                Layout::left_to_right(Align::default()),
-                None,
+            ui.new_child(
+                UiBuilder::new()
+                    .max_rect(emath::Rect {
+                        min: available.min,
+                        max: pos2(available.width() / 2.0, available.height()),
+                    })
+                    .layout(Layout::left_to_right(Align::default())),
             )
             .vertical(|ui| {
                 egui::ScrollArea::vertical()
                     .enable_scrolling(true)
-                    .id_source("usage-config")
+                    .id_salt("usage-config")
                     .show(ui, |ui| {
                         ui.add({
                             let curve = {
@@ -117,13 +118,13 @@ impl EditUsageConfig {
                     });
             });
 
-            ui.child_ui(
-                emath::Rect {
-                    min: pos2(available.width() / 2.0 + 20.0, available.min.y),
-                    max: available.max,
-                },
-                Layout::left_to_right(Align::default()),
-                None,
+            ui.new_child(
+                UiBuilder::new()
+                    .max_rect(emath::Rect {
+                        min: pos2(available.width() / 2.0 + 20.0, available.min.y),
+                        max: available.max,
+                    })
+                    .layout(Layout::left_to_right(Align::default())),
             )
             .vertical(|ui| {
                 ui.add(UsageConfigFile::new(self.config.clone()));
@@ -158,7 +159,7 @@ impl EditUsageConfig {
         let config = config.lock();
 
         let c: &amdgpu_config::fan::Config = &*config;
-        let content = match toml::to_string(c) {
+        let content = match c.to_toml_preserving() {
             Err(e) => {
                 tracing::error!("Config file serialization failed. {:?}", e);
                 return;
@@ -169,7 +170,7 @@ impl EditUsageConfig {
             path: String::from(config.path()),
             content,
         };
-        match send_command(command) {
+        match send_command(&Environment::from_env(), command) {
             Ok(Response::ConfigFileSaveFailed(msg)) => {
                 state.state = ChangeState::Failure(msg);
             }
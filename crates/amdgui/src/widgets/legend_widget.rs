@@ -2,7 +2,7 @@ use std::collections::BTreeMap;
 
 use egui::epaint::ahash::AHashSet;
 use egui::epaint::Shadow;
-use egui::{Align, Color32, Direction, Frame, Layout, Rect, Response, Ui, Widget};
+use egui::{Align, Color32, Direction, Frame, Layout, Rect, Response, Ui, UiBuilder, Widget};
 
 // use egui::style::Margin;
 // use epaint::margin::Margin;
@@ -88,7 +88,7 @@ impl Widget for &mut LegendWidget {
         let layout = Layout::from_main_dir_and_cross_align(main_dir, cross_align);
         let legend_pad = 4.0;
         let legend_rect = rect.shrink(legend_pad);
-        let mut legend_ui = ui.child_ui(legend_rect, layout, None);
+        let mut legend_ui = ui.new_child(UiBuilder::new().max_rect(legend_rect).layout(layout));
         legend_ui
             .scope(|ui| {
                 // ui.style_mut().body_text_style = config.text_style;
@@ -1,4 +1,5 @@
-use amdgpu::pidfile::helper_cmd::Command;
+use amdgpu::environment::Environment;
+use amdgpu::pidfile::helper_cmd::{Command, Response as HelperResponse};
 use egui::{PointerButton, Response, Sense, Ui};
 
 use crate::app::{ChangeState, FanServices};
@@ -18,9 +19,14 @@ impl<'l> egui::Widget for ReloadSection<'l> {
                     if ui.button("Reload").clicked_by(PointerButton::Primary) {
                         service.reload = ChangeState::Reloading;
 
-                        match amdgpu::pidfile::helper_cmd::send_command(Command::ReloadConfig {
-                            pid: service.pid,
-                        }) {
+                        match amdgpu::pidfile::helper_cmd::send_command(
+                            &Environment::from_env(),
+                            Command::ReloadConfig { pid: service.pid },
+                        ) {
+                            Ok(HelperResponse::ConfigReloadFailed(msg)) => {
+                                tracing::error!("Service rejected new config. {}", msg);
+                                service.reload = ChangeState::Failure(msg);
+                            }
                             Ok(response) => {
                                 service.reload = ChangeState::Success;
                                 tracing::info!("{:?}", response)
@@ -1,9 +1,12 @@
 pub mod errors;
 
+use amdgpu::environment::Environment;
+use amdgpu::fan_health::{read_health, FanHealth};
 use amdgpu::hw_mon::HwMon;
 use amdgpu::utils::load_temp_inputs;
 use amdgpu::{
-    TempInput, PULSE_WIDTH_MODULATION, PULSE_WIDTH_MODULATION_MAX, PULSE_WIDTH_MODULATION_MIN,
+    TempInput, FAN_INPUT, PULSE_WIDTH_MODULATION, PULSE_WIDTH_MODULATION_MAX,
+    PULSE_WIDTH_MODULATION_MIN,
 };
 use amdgpu_config::fan;
 
@@ -109,6 +112,17 @@ impl AmdMon {
         Ok(value)
     }
 
+    /// Fan speed reported by tachometer
+    pub fn rpm(&self) -> Option<u32> {
+        self.hw_mon_read(FAN_INPUT).ok()?.parse().ok()
+    }
+
+    /// Fan health published by amdfand, `None` if card is not managed by
+    /// amdfand
+    pub fn fan_health(&self, env: &Environment) -> Option<FanHealth> {
+        read_health(env.fan_state_dir(), self.card()).map(|report| report.health)
+    }
+
     pub fn pwm_min(&mut self) -> u32 {
         if self.pwm_min.is_none() {
             self.pwm_min = Some(self.value_or(PULSE_WIDTH_MODULATION_MIN, 0));
@@ -141,3 +155,34 @@ impl AmdMon {
         Ok(value)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use amdgpu::hw_mon::open_hw_mon;
+    use amdgpu_config::fan;
+    use amdgpu_testkit::FakeGpu;
+
+    use super::AmdMon;
+
+    #[test]
+    fn sensors_follow_card() {
+        let gpu = FakeGpu::navi21()
+            .with_temp("edge", 45.0)
+            .with_temp("junction", 52.0)
+            .with_fan(128, 1500);
+        let hw_mon = open_hw_mon(gpu.env(), gpu.card()).unwrap();
+        let mut mon = AmdMon::wrap(hw_mon, &fan::Config::default());
+
+        // default config reads edge sensor
+        assert_eq!(mon.max_gpu_temp().unwrap(), 45.0);
+        assert_eq!(mon.pwm().unwrap(), 128);
+        assert_eq!(mon.rpm(), Some(1500));
+        assert_eq!(mon.pwm_max(), 255);
+        assert_eq!(mon.fan_health(gpu.env()), None);
+
+        gpu.set_temp("edge", 70.5);
+        gpu.set_rpm(2100);
+        assert_eq!(mon.max_gpu_temp().unwrap(), 70.5);
+        assert_eq!(mon.rpm(), Some(2100));
+    }
+}
@@ -1,8 +1,9 @@
 use std::fs::File;
 
+use amdgpu::environment::Environment;
 use amdgpu::utils::hw_mons;
 use amdgpu_config::fan;
-use amdgpu_config::fan::DEFAULT_FAN_CONFIG_PATH;
+use amdgpu_config::fan::FAN_CONFIG_FILE;
 use amdgpu_config::monitor::Config;
 use amdmond_lib::errors::AmdMonError;
 use amdmond_lib::AmdMon;
@@ -31,8 +32,8 @@ struct Stat {
     usage_speed: f64,
 }
 
-pub fn run(command: LogFile, config: Config) -> amdmond_lib::Result<()> {
-    let fan_config = fan::load_config(DEFAULT_FAN_CONFIG_PATH)?;
+pub fn run(command: LogFile, env: &Environment, config: Config) -> amdmond_lib::Result<()> {
+    let fan_config = fan::load_config(&env.config_file(FAN_CONFIG_FILE).to_string_lossy())?;
 
     let duration =
         std::time::Duration::from_millis(command.interval.unwrap_or_else(|| config.interval()));
@@ -42,6 +43,7 @@ pub fn run(command: LogFile, config: Config) -> amdmond_lib::Result<()> {
     let stat_file = std::fs::OpenOptions::new()
         .create(true)
         .append(false)
+        .truncate(false)
         .write(true)
         .open(command.stat_file.as_str())
         .map_err(|io| AmdMonError::Io {
@@ -56,11 +58,11 @@ pub fn run(command: LogFile, config: Config) -> amdmond_lib::Result<()> {
         .from_writer(stat_file);
 
     let mon = {
-        let mons = hw_mons(true)?;
+        let mons = hw_mons(env, true)?;
         if mons.is_empty() {
             return Err(AmdMonError::NoHwMon);
         }
-        AmdMon::wrap(hw_mons(true)?.remove(0), &fan_config)
+        AmdMon::wrap(hw_mons(env, true)?.remove(0), &fan_config)
     };
 
     loop {
@@ -93,7 +95,7 @@ fn write_temperature(
             temperatures.remove(1).1.unwrap_or_default()
         } else {
             temperatures
-                .get(0)
+                .first()
                 .and_then(|(_, v)| v.as_ref().ok().copied())
                 .unwrap_or_default()
         }
@@ -2,8 +2,9 @@ mod command;
 mod log_file;
 mod watch;
 
+use amdgpu::environment::{Environment, Overrides};
 use amdgpu::utils::ensure_config_dir;
-use amdgpu_config::monitor::{load_config, Config, DEFAULT_MONITOR_CONFIG_PATH};
+use amdgpu_config::monitor::{load_config, Config, MONITOR_CONFIG_FILE};
 use gumdrop::Options;
 use tracing::level_filters::LevelFilter;
 use tracing_subscriber::EnvFilter;
@@ -21,11 +22,37 @@ pub struct Opts {
     pub version: bool,
     #[options(help = "Config location")]
     pub config: Option<String>,
+    #[options(
+        no_short,
+        help = "sysfs mount point, by default /sys (env AMDGPU_SYSFS_ROOT)"
+    )]
+    pub sysfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "Services state directory, by default /var/lib (env AMDGPU_STATE_DIR)"
+    )]
+    pub state_dir: Option<String>,
+    #[options(
+        no_short,
+        help = "Config directory, by default /etc/amdfand (env AMDGPU_CONFIG_DIR)"
+    )]
+    pub config_dir: Option<String>,
     #[options(command)]
     pub command: Option<Command>,
 }
 
-fn run(config: Config) -> amdmond_lib::Result<()> {
+impl Opts {
+    fn environment(&self) -> Environment {
+        Environment::from_env().with_overrides(Overrides {
+            sysfs_root: self.sysfs_root.clone(),
+            state_dir: self.state_dir.clone(),
+            config_dir: self.config_dir.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+fn run(env: Environment, config: Config) -> amdmond_lib::Result<()> {
     let opts: Opts = Opts::parse_args_default_or_exit();
 
     if opts.version {
@@ -33,8 +60,8 @@ fn run(config: Config) -> amdmond_lib::Result<()> {
         std::process::exit(0);
     }
     match opts.command {
-        Some(Command::Watch(w)) => watch::run(w, config),
-        Some(Command::LogFile(l)) => log_file::run(l, config),
+        Some(Command::Watch(w)) => watch::run(w, &env, config),
+        Some(Command::LogFile(l)) => log_file::run(l, &env, config),
         _ => {
             println!("{}", <Opts as gumdrop::Options>::usage());
             Ok(())
@@ -42,15 +69,19 @@ fn run(config: Config) -> amdmond_lib::Result<()> {
     }
 }
 
-fn setup() -> amdmond_lib::Result<(String, Config)> {
+fn setup() -> amdmond_lib::Result<(Environment, Config)> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "DEBUG");
     }
-    ensure_config_dir()?;
+    let opts = Opts::parse_args_default_or_exit();
+    let env = opts.environment();
+    ensure_config_dir(&env)?;
 
-    let config_path = Opts::parse_args_default_or_exit()
-        .config
-        .unwrap_or_else(|| DEFAULT_MONITOR_CONFIG_PATH.to_string());
+    let config_path = opts.config.unwrap_or_else(|| {
+        env.config_file(MONITOR_CONFIG_FILE)
+            .to_string_lossy()
+            .into_owned()
+    });
     let config = load_config(&config_path)
         .map_err(|io| amdmond_lib::errors::AmdMonError::Io {
             io: io.into_io(),
@@ -64,18 +95,18 @@ fn setup() -> amdmond_lib::Result<(String, Config)> {
         .with_max_level(config.log_level().as_str().parse::<LevelFilter>().unwrap())
         .init();
 
-    Ok((config_path, config))
+    Ok((env, config))
 }
 
 fn main() -> amdmond_lib::Result<()> {
-    let (_config_path, config) = match setup() {
+    let (env, config) = match setup() {
         Ok(config) => config,
         Err(e) => {
             eprintln!("{}", e);
             std::process::exit(1);
         }
     };
-    match run(config) {
+    match run(env, config) {
         Ok(()) => Ok(()),
         Err(e) => {
             eprintln!("{}", e);
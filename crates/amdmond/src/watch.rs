@@ -1,7 +1,8 @@
 use std::str::FromStr;
 
+use amdgpu::environment::Environment;
 use amdgpu::utils::{hw_mons, linear_map};
-use amdgpu_config::fan::DEFAULT_FAN_CONFIG_PATH;
+use amdgpu_config::fan::FAN_CONFIG_FILE;
 use amdgpu_config::{fan, monitor};
 use amdmond_lib::errors::AmdMonError;
 use amdmond_lib::AmdMon;
@@ -43,26 +44,26 @@ impl Default for Watch {
 }
 
 /// Start print cards temperature and fan speed
-pub fn run(monitor: Watch, _config: monitor::Config) -> amdmond_lib::Result<()> {
-    let fan_config = fan::load_config(DEFAULT_FAN_CONFIG_PATH)?;
+pub fn run(monitor: Watch, env: &Environment, _config: monitor::Config) -> amdmond_lib::Result<()> {
+    let fan_config = fan::load_config(&env.config_file(FAN_CONFIG_FILE).to_string_lossy())?;
     match monitor.format {
-        MonitorFormat::Short => short(fan_config),
-        MonitorFormat::Verbose => verbose(fan_config),
+        MonitorFormat::Short => short(env, fan_config),
+        MonitorFormat::Verbose => verbose(env, fan_config),
     }
 }
 
-pub fn verbose(config: fan::Config) -> amdmond_lib::Result<()> {
-    let mut hw_mons = AmdMon::wrap_all(hw_mons(true)?, &config);
+pub fn verbose(env: &Environment, config: fan::Config) -> amdmond_lib::Result<()> {
+    let mut hw_mons = AmdMon::wrap_all(hw_mons(env, true)?, &config);
 
     loop {
         print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
         for hw_mon in hw_mons.iter_mut() {
             println!("Card {:3}", hw_mon.card().to_string().replace("card", ""));
-            println!("  MIN |  MAX |  PWM   |   %");
+            println!("  MIN |  MAX |  PWM   |   % |   RPM | Fan");
             let min = hw_mon.pwm_min();
             let max = hw_mon.pwm_max();
             println!(
-                " {:>4} | {:>4} | {:>6} | {:>3}",
+                " {:>4} | {:>4} | {:>6} | {:>3} | {:>5} | {}",
                 min,
                 max,
                 hw_mon
@@ -76,6 +77,8 @@ pub fn verbose(config: fan::Config) -> amdmond_lib::Result<()> {
                     100f64,
                 ))
                 .round(),
+                rpm(hw_mon),
+                health(env, hw_mon),
             );
 
             println!();
@@ -94,19 +97,19 @@ pub fn verbose(config: fan::Config) -> amdmond_lib::Result<()> {
     }
 }
 
-pub fn short(config: fan::Config) -> amdmond_lib::Result<()> {
-    let mut hw_mons = AmdMon::wrap_all(hw_mons(true)?, &config);
+pub fn short(env: &Environment, config: fan::Config) -> amdmond_lib::Result<()> {
+    let mut hw_mons = AmdMon::wrap_all(hw_mons(env, true)?, &config);
     loop {
         print!("{esc}[2J{esc}[1;1H", esc = 27 as char);
         for hw_mon in hw_mons.iter_mut() {
             println!(
-                "Card {:3} | Temp     |  MIN |  MAX |  PWM |   %",
+                "Card {:3} | Temp     |  MIN |  MAX |  PWM |   % |   RPM | Fan",
                 hw_mon.card().to_string().replace("card", "")
             );
             let min = hw_mon.pwm_min();
             let max = hw_mon.pwm_max();
             println!(
-                "         | {:>5.2}    | {:>4} | {:>4} | {:>4} | {:>3}",
+                "         | {:>5.2}    | {:>4} | {:>4} | {:>4} | {:>3} | {:>5} | {}",
                 hw_mon.max_gpu_temp().unwrap_or_default(),
                 min,
                 max,
@@ -119,8 +122,23 @@ pub fn short(config: fan::Config) -> amdmond_lib::Result<()> {
                     100f64,
                 )
                 .round(),
+                rpm(hw_mon),
+                health(env, hw_mon),
             );
         }
         std::thread::sleep(std::time::Duration::from_secs(4));
     }
 }
+
+fn rpm(hw_mon: &AmdMon) -> String {
+    hw_mon
+        .rpm()
+        .map_or_else(|| String::from("-"), |rpm| rpm.to_string())
+}
+
+/// Fan health published by amdfand
+fn health(env: &Environment, hw_mon: &AmdMon) -> String {
+    hw_mon
+        .fan_health(env)
+        .map_or_else(|| String::from("-"), |health| health.to_string())
+}
@@ -4,14 +4,42 @@ use std::os::unix::net::{UnixListener, UnixStream};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
+use amdgpu::environment::{Environment, Overrides};
 use amdgpu::pidfile::ports::*;
 use amdgpu::IoFailure;
+use gumdrop::Options;
 use tracing_subscriber::EnvFilter;
 
 #[cfg(feature = "static")]
 extern crate eyra;
 
-fn parse_output(entry: DirEntry) -> Option<Output> {
+#[derive(Options)]
+pub struct Opts {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(
+        no_short,
+        help = "sysfs mount point, by default /sys (env AMDGPU_SYSFS_ROOT)"
+    )]
+    sysfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "Socket directory, by default /tmp (env AMDGPU_RUNTIME_DIR)"
+    )]
+    runtime_dir: Option<String>,
+}
+
+impl Opts {
+    fn environment(&self) -> Environment {
+        Environment::from_env().with_overrides(Overrides {
+            sysfs_root: self.sysfs_root.clone(),
+            runtime_dir: self.runtime_dir.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+fn parse_output(env: &Environment, entry: DirEntry) -> Option<Output> {
     let ty = entry.file_type().ok()?;
     if ty.is_dir() {
         return None;
@@ -62,24 +90,28 @@ fn parse_output(entry: DirEntry) -> Option<Output> {
         output.port_name = Some(it.collect::<Vec<_>>().join("-"));
     }
 
-    output.status = output.read_status()?;
+    output.status = output.read_status(env)?;
 
     Some(output)
 }
 
-async fn read_outputs(state: Arc<Mutex<Vec<Output>>>) {
+fn outputs(env: &Environment) -> Vec<Output> {
+    std::fs::read_dir(env.drm_dir())
+        .unwrap()
+        .filter_map(|r| r.ok())
+        .filter(|e| {
+            e.path()
+                .to_str()
+                .map(|s| s.contains("card"))
+                .unwrap_or_default()
+        })
+        .filter_map(|entry| parse_output(env, entry))
+        .collect()
+}
+
+async fn read_outputs(env: &Environment, state: Arc<Mutex<Vec<Output>>>) {
     loop {
-        let outputs = std::fs::read_dir("/sys/class/drm")
-            .unwrap()
-            .filter_map(|r| r.ok())
-            .filter(|e| {
-                e.path()
-                    .to_str()
-                    .map(|s| s.contains("card"))
-                    .unwrap_or_default()
-            })
-            .filter_map(parse_output)
-            .collect::<Vec<_>>();
+        let outputs = outputs(env);
         if let Ok(mut lock) = state.lock() {
             *lock = outputs;
         }
@@ -90,8 +122,8 @@ async fn read_outputs(state: Arc<Mutex<Vec<Output>>>) {
 
 pub type Service = amdgpu::pidfile::Service<Response>;
 
-async fn service(state: Arc<Mutex<Vec<Output>>>) {
-    let sock_path = sock_file();
+async fn service(env: &Environment, state: Arc<Mutex<Vec<Output>>>) {
+    let sock_path = sock_file(env);
     let listener = {
         let _ = std::fs::remove_file(&sock_path);
 
@@ -144,6 +176,7 @@ fn main() {
     tracing_subscriber::fmt::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .init();
+    let env = Opts::parse_args_default_or_exit().environment();
 
     let executor = tokio::runtime::Builder::new_current_thread()
         .enable_all()
@@ -153,9 +186,42 @@ fn main() {
     let state = Arc::new(Mutex::new(Vec::new()));
 
     executor.block_on(async {
-        let sync = read_outputs(state.clone());
-        let handle = service(state);
+        let sync = read_outputs(&env, state.clone());
+        let handle = service(&env, state);
 
         tokio::join!(sync, handle);
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use amdgpu::pidfile::ports::{OutputType, Status};
+    use amdgpu_testkit::FakeGpu;
+
+    use super::outputs;
+
+    #[test]
+    fn connectors_are_listed() {
+        let gpu = FakeGpu::navi21()
+            .with_connector("DP", 1, true)
+            .with_connector("HDMI-A", 1, false);
+
+        let mut outputs = outputs(gpu.env());
+        outputs.sort_by(|a, b| a.port_type.cmp(&b.port_type));
+        assert_eq!(outputs.len(), 2);
+
+        let dp = &outputs[0];
+        assert_eq!(dp.card, "0");
+        assert_eq!(dp.ty, OutputType::parse_str("DP"));
+        assert_eq!(dp.port_number, 1);
+        assert!(matches!(dp.status, Status::Connected));
+        assert!(dp.display_power_managment);
+        assert_eq!(dp.modes.len(), 3);
+
+        let hdmi = &outputs[1];
+        assert_eq!(hdmi.port_type, "HDMI");
+        assert_eq!(hdmi.port_name.as_deref(), Some("A"));
+        assert!(matches!(hdmi.status, Status::Disconnected));
+        assert!(hdmi.modes.is_empty());
+    }
+}
@@ -0,0 +1,248 @@
+//! Apply overclocking profile from voltage config.
+
+use amdgpu::environment::Environment;
+use amdgpu::hw_mon::RootPath;
+use amdgpu::utils::hw_mons;
+use amdgpu_config::voltage::{ClockRange, Profile};
+
+use crate::clock_state::{Frequency, Voltage};
+use crate::command::{HardwareModule, VoltageManipulator};
+use crate::confirm::{guard, revert, Timeout};
+use crate::transaction::Transaction;
+use crate::{Config, VoltageError};
+
+#[derive(Debug, gumdrop::Options)]
+pub struct Apply {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Profile name, by default profile from config")]
+    profile: Option<String>,
+    #[options(help = "Print changes without writing them")]
+    dry_run: bool,
+    #[options(
+        no_short,
+        help = "Revert profile unless `amdvold confirm` is called in given time, ex. 30s"
+    )]
+    confirm_within: Option<Timeout>,
+}
+
+impl<Root: RootPath> VoltageManipulator<Root> {
+    /// Stage clock ranges, curve and voltage offset of profile
+    pub fn profile_transaction(&self, profile: &Profile) -> Transaction<'_, Root> {
+        let mut transaction = self.transaction();
+        let ranges = [
+            (&profile.engine, HardwareModule::Engine),
+            (&profile.memory, HardwareModule::Memory),
+        ];
+        for (range, module) in ranges {
+            let Some(ClockRange { min, max }) = range else {
+                continue;
+            };
+            for (index, freq) in [(0, min), (1, max)] {
+                if let Some(freq) = freq {
+                    transaction.write_state(
+                        index,
+                        Frequency::from_mhz(*freq),
+                        None,
+                        module.clone(),
+                    );
+                }
+            }
+        }
+        for (index, point) in profile.curve.iter().enumerate() {
+            transaction.write_state(
+                index as u16,
+                Frequency::from_mhz(point.freq),
+                Some(Voltage::from_millivolts(point.voltage)),
+                HardwareModule::Curve,
+            );
+        }
+        if let Some(offset) = profile.voltage_offset {
+            transaction.write_voltage_offset(offset);
+        }
+        transaction
+    }
+
+    /// Commit clock ranges, curve and voltage offset and set power limit
+    pub fn apply_profile(&self, profile: &Profile) -> crate::Result<()> {
+        let transaction = self.profile_transaction(profile);
+        if !transaction.is_empty() {
+            transaction.commit()?;
+        }
+        if let Some(watts) = profile.power_cap {
+            self.hw_mon_write("power1_cap", watts as u64 * 1_000_000)?;
+        }
+        Ok(())
+    }
+
+    fn print_profile(&self, profile: &Profile) -> crate::Result<()> {
+        let transaction = self.profile_transaction(profile);
+        if !transaction.is_empty() {
+            for diff in transaction.preview()? {
+                println!("{} {}", self.card(), diff);
+            }
+        }
+        if let Some(watts) = profile.power_cap {
+            println!("{} Power cap: {}W", self.card(), watts);
+        }
+        Ok(())
+    }
+}
+
+pub fn run(command: Apply, env: &Environment, config: &Config) -> crate::Result<()> {
+    let name = match command
+        .profile
+        .or_else(|| config.default_profile().map(String::from))
+    {
+        Some(name) => name,
+        // service runs apply on every boot, also without configured profile
+        None => {
+            tracing::info!(
+                "No profile was given and config has no default profile, nothing to apply"
+            );
+            return Ok(());
+        }
+    };
+    if !config.has_profile(&name) {
+        return Err(VoltageError::UnknownProfile(name));
+    }
+    let mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, config);
+    if mons.is_empty() {
+        return Err(VoltageError::NoAmdGpu);
+    }
+    if command.dry_run {
+        for mon in mons {
+            if let Some(profile) = config.profile_for(&name, mon.card()) {
+                mon.print_profile(profile)?;
+            }
+        }
+        return Ok(());
+    }
+    let pending = match command.confirm_within {
+        Some(timeout) => Some(guard(env, config, &name, timeout)?),
+        None => None,
+    };
+    let applied = mons.iter().try_for_each(|mon| {
+        match config.profile_for(&name, mon.card()) {
+            Some(profile) => {
+                mon.apply_profile(profile)?;
+                tracing::info!("Applied profile {} to {}", name, mon.card());
+            }
+            None => tracing::info!("Profile {} has no settings for {}", name, mon.card()),
+        }
+        Ok(())
+    });
+    match (applied, pending) {
+        (Err(e), Some(pending)) => {
+            revert(env, config, &pending)?;
+            Err(e)
+        }
+        (Err(e), None) => Err(e),
+        (Ok(()), Some(pending)) => {
+            println!(
+                "Run `amdvold confirm` within {}s, otherwise previous settings will be restored",
+                pending.remaining(amdgpu::pending_change::now())
+            );
+            Ok(())
+        }
+        (Ok(()), None) => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_config::voltage::{ClockRange, CurvePoint, Profile};
+    use amdgpu_testkit::FakeGpu;
+    use gumdrop::Options;
+
+    use super::{run, Apply};
+    use crate::command::VoltageManipulator;
+    use crate::{Config, VoltageError};
+
+    #[test]
+    fn profile_writes_numeric_commands() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let profile = Profile {
+            name: String::from("undervolt"),
+            power_cap: Some(180),
+            engine: Some(ClockRange {
+                min: Some(800),
+                max: Some(2000),
+            }),
+            memory: Some(ClockRange {
+                min: None,
+                max: Some(900),
+            }),
+            curve: vec![
+                CurvePoint {
+                    freq: 800,
+                    voltage: 750,
+                },
+                CurvePoint {
+                    freq: 2000,
+                    voltage: 1050,
+                },
+            ],
+            ..Default::default()
+        };
+        manipulator.apply_profile(&profile).unwrap();
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec![
+                "s 0 800",
+                "s 1 2000",
+                "m 1 900",
+                "vc 0 800 750",
+                "vc 1 2000 1050",
+                "c"
+            ]
+        );
+        assert_eq!(gpu.writes_to("power1_cap"), vec!["180000000"]);
+    }
+
+    #[test]
+    fn nothing_to_apply_without_profile() {
+        let gpu = FakeGpu::vega20();
+        let command = Apply::parse_args_default::<&str>(&[]).unwrap();
+        run(command, gpu.env(), &Config::default()).unwrap();
+        assert!(gpu.writes().is_empty());
+
+        let command = Apply::parse_args_default(&["--profile", "missing"]).unwrap();
+        assert!(matches!(
+            run(command, gpu.env(), &Config::default()),
+            Err(VoltageError::UnknownProfile(name)) if name == "missing"
+        ));
+    }
+
+    #[test]
+    fn invalid_profile_is_not_written() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let profile = Profile {
+            name: String::from("overclock"),
+            power_cap: Some(180),
+            memory: Some(ClockRange {
+                min: None,
+                max: Some(1200),
+            }),
+            ..Default::default()
+        };
+        assert!(manipulator.apply_profile(&profile).is_err());
+        assert!(gpu.writes().is_empty());
+    }
+
+    #[test]
+    fn voltage_offset() {
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let profile = Profile {
+            name: String::from("undervolt"),
+            voltage_offset: Some(-50),
+            ..Default::default()
+        };
+        manipulator.apply_profile(&profile).unwrap();
+        assert_eq!(gpu.writes_to("pp_od_clk_voltage"), vec!["vo -50", "c"]);
+        assert!(gpu.writes_to("power1_cap").is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use amdgpu::environment::Environment;
 use amdgpu::utils::hw_mons;
 
 use crate::command::VoltageManipulator;
@@ -8,8 +9,8 @@ pub struct ApplyChanges {
     help: bool,
 }
 
-pub fn run(_command: ApplyChanges, config: &Config) -> crate::Result<()> {
-    let mut mons = VoltageManipulator::wrap_all(hw_mons(false)?, config);
+pub fn run(_command: ApplyChanges, env: &Environment, config: &Config) -> crate::Result<()> {
+    let mut mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, config);
     if mons.is_empty() {
         return Err(VoltageError::NoAmdGpu);
     }
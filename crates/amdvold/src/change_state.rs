@@ -1,6 +1,8 @@
+use amdgpu::environment::Environment;
+use amdgpu::hw_mon::RootPath;
 use amdgpu::utils::hw_mons;
 
-use crate::clock_state::{Frequency, Voltage};
+use crate::clock_state::{ClockState, Frequency, OdFormat, OdRange, ValueRange, Voltage};
 use crate::command::{HardwareModule, VoltageManipulator};
 use crate::{Config, VoltageError};
 
@@ -12,8 +14,135 @@ pub enum ChangeStateError {
     Freq,
     #[error("No voltage was given")]
     Voltage,
-    #[error("No AMD GPU module was given (either memory or engine)")]
+    #[error("No AMD GPU module was given (either memory, engine or curve)")]
     Module,
+    #[error("{name} {value}{unit} is outside of allowed range {min}{unit} - {max}{unit}")]
+    OutOfRange {
+        name: String,
+        value: u32,
+        min: u32,
+        max: u32,
+        unit: &'static str,
+    },
+    #[error("Curve point {index} does not exist, allowed points are 0 - {last}")]
+    NoCurvePoint { index: u16, last: u16 },
+    #[error("Cards with {0} table do not accept voltage for engine and memory states")]
+    UnexpectedVoltage(OdFormat),
+    #[error("Cards with {0} table have no voltage curve")]
+    NoCurve(OdFormat),
+    #[error("No voltage offset was given")]
+    Offset,
+    #[error("Voltage offset {value}mV is outside of allowed range {min}mV - {max}mV")]
+    OffsetOutOfRange { value: i32, min: i32, max: i32 },
+    #[error("Card does not support voltage offset, only RDNA2 and newer cards have it")]
+    UnsupportedOffset,
+    #[error("{name} {index} frequency {freq}MHz is lower than {name} {previous} frequency {previous_freq}MHz")]
+    Unordered {
+        name: &'static str,
+        index: u16,
+        freq: u32,
+        previous: u16,
+        previous_freq: u32,
+    },
+}
+
+/// Check if voltage is required by format of `pp_od_clk_voltage`
+pub fn check_format(
+    format: Option<OdFormat>,
+    module: &HardwareModule,
+    voltage: Option<&Voltage>,
+) -> Result<(), ChangeStateError> {
+    match (format, module, voltage) {
+        (
+            Some(format @ (OdFormat::VoltageOffset | OdFormat::ClockRange)),
+            HardwareModule::Curve,
+            _,
+        ) => Err(ChangeStateError::NoCurve(format)),
+        (_, HardwareModule::Curve, None) | (Some(OdFormat::StateVoltage), _, None) => {
+            Err(ChangeStateError::Voltage)
+        }
+        (
+            Some(format @ (OdFormat::VddcCurve | OdFormat::VoltageOffset | OdFormat::ClockRange)),
+            HardwareModule::Engine | HardwareModule::Memory,
+            Some(_),
+        ) => Err(ChangeStateError::UnexpectedVoltage(format)),
+        _ => Ok(()),
+    }
+}
+
+/// Check voltage offset against `OD_RANGE`
+pub fn check_offset(state: &ClockState, offset: i32) -> Result<(), ChangeStateError> {
+    if state.format() != Some(OdFormat::VoltageOffset) {
+        return Err(ChangeStateError::UnsupportedOffset);
+    }
+    match state.od_range.voltage_offset {
+        Some(range) if !range.contains(offset) => Err(ChangeStateError::OffsetOutOfRange {
+            value: offset,
+            min: range.min,
+            max: range.max,
+        }),
+        _ => Ok(()),
+    }
+}
+
+fn check(
+    range: Option<&ValueRange>,
+    name: String,
+    value: u32,
+    unit: &'static str,
+) -> Result<(), ChangeStateError> {
+    match range {
+        Some(range) if !range.contains(value) => Err(ChangeStateError::OutOfRange {
+            name,
+            value,
+            min: range.min,
+            max: range.max,
+            unit,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Check new state against `OD_RANGE`, limits missing from range are not
+/// checked
+pub fn validate(
+    range: &OdRange,
+    index: u16,
+    module: &HardwareModule,
+    freq: &Frequency,
+    voltage: Option<&Voltage>,
+) -> Result<(), ChangeStateError> {
+    let (name, freq_range, voltage_range) = match module {
+        HardwareModule::Engine => ("Engine", range.engine.as_ref(), range.voltage.as_ref()),
+        HardwareModule::Memory => ("Memory", range.memory.as_ref(), range.voltage.as_ref()),
+        HardwareModule::Curve => {
+            if let Some(last) = range.curve_freq.keys().last() {
+                if !range.curve_freq.contains_key(&index) {
+                    return Err(ChangeStateError::NoCurvePoint { index, last: *last });
+                }
+            }
+            (
+                "Curve point",
+                range.curve_freq.get(&index),
+                range.curve_voltage.get(&index),
+            )
+        }
+    };
+    check(
+        freq_range,
+        format!("{} {} frequency", name, index),
+        freq.mhz(),
+        "MHz",
+    )?;
+    if let Some(voltage) = voltage {
+        check(
+            voltage_range,
+            format!("{} {} voltage", name, index),
+            voltage.millivolts(),
+            "mV",
+        )?;
+    }
+    Ok(())
 }
 
 #[derive(Debug, gumdrop::Options)]
@@ -22,22 +151,34 @@ pub struct ChangeState {
     help: bool,
     #[options(help = "Profile number", free)]
     index: u16,
-    #[options(help = "Either memory or engine", free)]
+    #[options(help = "Either memory, engine or curve", free)]
     module: Option<HardwareModule>,
     #[options(help = "New GPU module frequency", free)]
     frequency: Option<Frequency>,
-    #[options(help = "New GPU module voltage", free)]
+    #[options(
+        help = "New GPU module voltage, not used by RDNA engine and memory states",
+        free
+    )]
     voltage: Option<Voltage>,
     #[options(help = "Apply changes immediately after change")]
     apply_immediately: bool,
+    #[options(help = "Print changes without writing them")]
+    dry_run: bool,
 }
 
-pub fn run(command: ChangeState, config: &Config) -> crate::Result<()> {
-    let mut mons = VoltageManipulator::wrap_all(hw_mons(false)?, config);
+pub fn run(command: ChangeState, env: &Environment, config: &Config) -> crate::Result<()> {
+    let mut mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, config);
     if mons.is_empty() {
         return Err(VoltageError::NoAmdGpu);
     }
     let mon = mons.remove(0);
+    change_state(&mon, command)
+}
+
+fn change_state<Root: RootPath>(
+    mon: &VoltageManipulator<Root>,
+    command: ChangeState,
+) -> crate::Result<()> {
     let ChangeState {
         help: _,
         index,
@@ -45,16 +186,142 @@ pub fn run(command: ChangeState, config: &Config) -> crate::Result<()> {
         frequency,
         voltage,
         apply_immediately,
+        dry_run,
     } = command;
-    mon.write_state(
-        index,
-        frequency.ok_or(ChangeStateError::Freq)?,
-        voltage.ok_or(ChangeStateError::Voltage)?,
-        module.ok_or(ChangeStateError::Module)?,
-    )?;
-    if apply_immediately {
-        mon.write_apply()?;
+    let frequency = frequency.ok_or(ChangeStateError::Freq)?;
+    let module = module.ok_or(ChangeStateError::Module)?;
+    let mut transaction = mon.transaction();
+    transaction.write_state(index, frequency, voltage, module);
+    if dry_run {
+        for diff in transaction.preview()? {
+            println!("{} {}", mon.card(), diff);
+        }
+        Ok(())
+    } else if apply_immediately {
+        transaction.commit()
+    } else {
+        transaction.stage()
     }
+}
 
-    Ok(())
+#[cfg(test)]
+mod tests {
+    use amdgpu_testkit::FakeGpu;
+
+    use super::{change_state, validate, ChangeState, ChangeStateError};
+    use crate::clock_state::state_tests::VALID_STRING;
+    use crate::clock_state::ClockState;
+    use crate::command::{HardwareModule, VoltageManipulator};
+    use crate::{Config, VoltageError};
+
+    #[test]
+    fn validate_against_range() {
+        let range = VALID_STRING.trim().parse::<ClockState>().unwrap().od_range;
+        let freq = |s: &str| s.parse().unwrap();
+        let volt = |s: &str| s.parse().unwrap();
+
+        assert!(validate(&range, 1, &HardwareModule::Engine, &freq("2150MHz"), None).is_ok());
+        let err = validate(&range, 1, &HardwareModule::Engine, &freq("2200MHz"), None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Engine 1 frequency 2200MHz is outside of allowed range 800MHz - 2150MHz"
+        );
+        assert!(matches!(
+            validate(&range, 1, &HardwareModule::Memory, &freq("1000MHz"), None),
+            Err(ChangeStateError::OutOfRange {
+                min: 625,
+                max: 950,
+                ..
+            })
+        ));
+        let err = validate(
+            &range,
+            2,
+            &HardwareModule::Curve,
+            &freq("2100MHz"),
+            Some(&volt("1250mV")),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Curve point 2 voltage 1250mV is outside of allowed range 750mV - 1200mV"
+        );
+        assert!(matches!(
+            validate(&range, 3, &HardwareModule::Curve, &freq("2100MHz"), None),
+            Err(ChangeStateError::NoCurvePoint { index: 3, last: 2 })
+        ));
+    }
+
+    #[test]
+    fn invalid_state_is_not_written() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let command = |freq: &str| ChangeState {
+            help: false,
+            index: 1,
+            module: Some(HardwareModule::Curve),
+            frequency: Some(freq.parse().unwrap()),
+            voltage: Some("1000mV".parse().unwrap()),
+            apply_immediately: true,
+            dry_run: false,
+        };
+
+        assert!(matches!(
+            change_state(&manipulator, command("3000MHz")),
+            Err(VoltageError::ChangeStateError(
+                ChangeStateError::OutOfRange { .. }
+            ))
+        ));
+        assert!(gpu.writes().is_empty());
+
+        let dry_run = ChangeState {
+            dry_run: true,
+            ..command("2000MHz")
+        };
+        change_state(&manipulator, dry_run).unwrap();
+        assert!(gpu.writes().is_empty());
+
+        change_state(&manipulator, command("2000MHz")).unwrap();
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec!["vc 1 2000 1000", "c"]
+        );
+    }
+
+    #[test]
+    fn voltage_depends_on_format() {
+        let command = |module, voltage: Option<&str>| ChangeState {
+            help: false,
+            index: 1,
+            module: Some(module),
+            frequency: Some("1000MHz".parse().unwrap()),
+            voltage: voltage.map(|v| v.parse().unwrap()),
+            apply_immediately: false,
+            dry_run: false,
+        };
+
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        assert!(matches!(
+            change_state(&manipulator, command(HardwareModule::Engine, Some("900mV"))),
+            Err(VoltageError::ChangeStateError(
+                ChangeStateError::UnexpectedVoltage(_)
+            ))
+        ));
+        assert!(matches!(
+            change_state(&manipulator, command(HardwareModule::Curve, Some("900mV"))),
+            Err(VoltageError::ChangeStateError(ChangeStateError::NoCurve(_)))
+        ));
+        change_state(&manipulator, command(HardwareModule::Memory, None)).unwrap();
+        assert_eq!(gpu.writes_to("pp_od_clk_voltage"), vec!["m 1 1000"]);
+
+        let gpu = FakeGpu::polaris10();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        assert!(matches!(
+            change_state(&manipulator, command(HardwareModule::Engine, None)),
+            Err(VoltageError::ChangeStateError(ChangeStateError::Voltage))
+        ));
+        change_state(&manipulator, command(HardwareModule::Memory, Some("900mV"))).unwrap();
+        assert_eq!(gpu.writes_to("pp_od_clk_voltage"), vec!["m 1 1000 900"]);
+    }
 }
@@ -1,19 +1,41 @@
+use std::collections::BTreeMap;
 use std::iter::Peekable;
 use std::str::Chars;
 
 const ENGINE_CLOCK_LABEL: &str = "OD_SCLK:";
 const MEMORY_CLOCK_LABEL: &str = "OD_MCLK:";
 const CURVE_POINTS_LABEL: &str = "OD_VDDC_CURVE:";
+const VOLTAGE_OFFSET_LABEL: &str = "OD_VDDGFX_OFFSET:";
+const RANGE_LABEL: &str = "OD_RANGE:";
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Frequency {
     pub value: u32,
     pub unit: String,
 }
 
-impl ToString for Frequency {
-    fn to_string(&self) -> String {
-        format!("{}{}", self.value, self.unit)
+impl Frequency {
+    pub fn from_mhz(value: u32) -> Self {
+        Self {
+            value,
+            unit: String::from("MHz"),
+        }
+    }
+
+    /// Value in MHz, kernel accepts only plain numbers in this unit
+    pub fn mhz(&self) -> u32 {
+        match self.unit.to_lowercase().as_str() {
+            "ghz" => self.value * 1000,
+            "khz" => self.value / 1000,
+            "hz" => self.value / 1_000_000,
+            _ => self.value,
+        }
+    }
+}
+
+impl std::fmt::Display for Frequency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&format!("{}{}", self.value, self.unit))
     }
 }
 
@@ -50,15 +72,33 @@ impl std::str::FromStr for Frequency {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Voltage {
     pub value: u32,
     pub unit: String,
 }
 
-impl ToString for Voltage {
-    fn to_string(&self) -> String {
-        format!("{}{}", self.value, self.unit)
+impl Voltage {
+    pub fn from_millivolts(value: u32) -> Self {
+        Self {
+            value,
+            unit: String::from("mV"),
+        }
+    }
+
+    /// Value in mV, kernel accepts only plain numbers in this unit
+    pub fn millivolts(&self) -> u32 {
+        match self.unit.as_str() {
+            "V" => self.value * 1000,
+            "uV" | "µV" => self.value / 1000,
+            _ => self.value,
+        }
+    }
+}
+
+impl std::fmt::Display for Voltage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(&format!("{}{}", self.value, self.unit))
     }
 }
 
@@ -95,6 +135,43 @@ impl std::str::FromStr for Voltage {
     }
 }
 
+/// Layout of `pp_od_clk_voltage`, it differs between GPU generations
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OdFormat {
+    /// Polaris and Vega10, every engine and memory state has own voltage
+    /// (`s 1 1450 800`)
+    StateVoltage,
+    /// Vega20 and Navi1x (RDNA), min and max clocks (`s 1 2000`) and
+    /// `OD_VDDC_CURVE` points (`vc 2 2000 1100`)
+    VddcCurve,
+    /// Navi2x and Navi3x (RDNA2, RDNA3), min and max clocks (`s 1 2500`) and
+    /// `OD_VDDGFX_OFFSET` (`vo -50`)
+    VoltageOffset,
+    /// Only min and max clocks (`s 1 2500`), without any voltage control,
+    /// ex. RDNA2 on kernels which do not expose `OD_VDDGFX_OFFSET`
+    ClockRange,
+}
+
+impl std::fmt::Display for OdFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.pad(match self {
+            OdFormat::StateVoltage => "state voltage",
+            OdFormat::VddcCurve => "voltage curve",
+            OdFormat::VoltageOffset => "voltage offset",
+            OdFormat::ClockRange => "clock range",
+        })
+    }
+}
+
+/// Single line of `OD_SCLK` or `OD_MCLK`
+#[derive(Debug, Eq, PartialEq)]
+pub struct OdLevel {
+    pub index: u16,
+    pub freq: Frequency,
+    /// Only in [OdFormat::StateVoltage]
+    pub voltage: Option<Voltage>,
+}
+
 #[derive(Debug, Eq, PartialEq)]
 pub struct CurvePoint {
     pub freq: Frequency,
@@ -111,6 +188,105 @@ pub enum ClockStateError {
     NotFrequency(String),
     #[error("Voltage section for engine clock is not valid. Line {0:?} is malformed")]
     InvalidEngineClockSection(String),
+    #[error("Range section is not valid. Line {0:?} is malformed")]
+    InvalidRange(String),
+}
+
+/// Allowed values in MHz or mV
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ValueRange<T = u32> {
+    pub min: T,
+    pub max: T,
+}
+
+impl<T: PartialOrd> ValueRange<T> {
+    pub fn contains(&self, value: T) -> bool {
+        self.min <= value && value <= self.max
+    }
+}
+
+/// Parse signed voltage like `-50mV`, RDNA3 prints range with `mv`
+pub fn parse_offset(s: &str) -> std::result::Result<i32, ClockStateError> {
+    let s = s.trim();
+    s.strip_suffix("mV")
+        .or_else(|| s.strip_suffix("mv"))
+        .unwrap_or(s)
+        .parse()
+        .map_err(|_| ClockStateError::NotVoltage(s.to_string()))
+}
+
+/// Limits from `OD_RANGE` section
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct OdRange {
+    /// `SCLK`
+    pub engine: Option<ValueRange>,
+    /// `MCLK`
+    pub memory: Option<ValueRange>,
+    /// `VDDC`, voltage of engine and memory states on older cards
+    pub voltage: Option<ValueRange>,
+    /// `VDDC_CURVE_SCLK[n]` by point index
+    pub curve_freq: BTreeMap<u16, ValueRange>,
+    /// `VDDC_CURVE_VOLT[n]` by point index
+    pub curve_voltage: BTreeMap<u16, ValueRange>,
+    /// `VDDGFX_OFFSET` in mV
+    pub voltage_offset: Option<ValueRange<i32>>,
+}
+
+impl OdRange {
+    /// Parse single `LABEL: min max` line, unknown labels are ignored
+    fn parse_line(&mut self, line: &str) -> std::result::Result<(), ClockStateError> {
+        let invalid = || ClockStateError::InvalidRange(line.to_string());
+        let (label, values) = line.split_once(':').ok_or_else(invalid)?;
+        let mut parts = values.split_whitespace();
+        let (name, index) = match label.split_once('[') {
+            Some((name, index)) => (
+                name,
+                Some(
+                    index
+                        .strip_suffix(']')
+                        .and_then(|index| index.parse::<u16>().ok())
+                        .ok_or_else(invalid)?,
+                ),
+            ),
+            None => (label, None),
+        };
+        let mut freq_range = || -> std::result::Result<ValueRange, ClockStateError> {
+            let min = parts.next().ok_or_else(invalid)?.parse::<Frequency>()?;
+            let max = parts.next().ok_or_else(invalid)?.parse::<Frequency>()?;
+            Ok(ValueRange {
+                min: min.mhz(),
+                max: max.mhz(),
+            })
+        };
+        match (name, index) {
+            ("SCLK", None) => self.engine = Some(freq_range()?),
+            ("MCLK", None) => self.memory = Some(freq_range()?),
+            ("VDDGFX_OFFSET", None) => {
+                let min = parse_offset(parts.next().ok_or_else(invalid)?)?;
+                let max = parse_offset(parts.next().ok_or_else(invalid)?)?;
+                self.voltage_offset = Some(ValueRange { min, max });
+            }
+            ("VDDC_CURVE_SCLK", Some(index)) => {
+                self.curve_freq.insert(index, freq_range()?);
+            }
+            ("VDDC", None) | ("VDDC_CURVE_VOLT", Some(_)) => {
+                let min = parts.next().ok_or_else(invalid)?.parse::<Voltage>()?;
+                let max = parts.next().ok_or_else(invalid)?.parse::<Voltage>()?;
+                let range = ValueRange {
+                    min: min.millivolts(),
+                    max: max.millivolts(),
+                };
+                match index {
+                    Some(index) => {
+                        self.curve_voltage.insert(index, range);
+                    }
+                    None => self.voltage = Some(range),
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Eq, PartialEq)]
@@ -120,6 +296,35 @@ pub struct ClockState {
     pub engine_label_highest: Option<Frequency>,
     pub memory_label_lowest: Option<Frequency>,
     pub memory_label_highest: Option<Frequency>,
+    /// All `OD_SCLK` lines
+    pub engine_levels: Vec<OdLevel>,
+    /// All `OD_MCLK` lines, Navi1x has only level 1
+    pub memory_levels: Vec<OdLevel>,
+    /// `OD_VDDGFX_OFFSET` in mV
+    pub voltage_offset: Option<i32>,
+    pub od_range: OdRange,
+}
+
+impl ClockState {
+    /// Detect layout, `None` if there is no overdrive table
+    pub fn format(&self) -> Option<OdFormat> {
+        if self.voltage_offset.is_some() || self.od_range.voltage_offset.is_some() {
+            Some(OdFormat::VoltageOffset)
+        } else if !self.curve_labels.is_empty() || !self.od_range.curve_freq.is_empty() {
+            Some(OdFormat::VddcCurve)
+        } else if self
+            .engine_levels
+            .iter()
+            .chain(self.memory_levels.iter())
+            .any(|level| level.voltage.is_some())
+        {
+            Some(OdFormat::StateVoltage)
+        } else if !self.engine_levels.is_empty() {
+            Some(OdFormat::ClockRange)
+        } else {
+            None
+        }
+    }
 }
 
 impl Default for ClockState {
@@ -130,6 +335,10 @@ impl Default for ClockState {
             engine_label_highest: None,
             memory_label_lowest: None,
             memory_label_highest: None,
+            engine_levels: vec![],
+            memory_levels: vec![],
+            voltage_offset: None,
+            od_range: OdRange::default(),
         }
     }
 }
@@ -144,6 +353,8 @@ impl std::str::FromStr for ClockState {
             ParseEngineClock,
             ParseMemoryClock,
             ParseCurve,
+            ParseVoltageOffset,
+            ParseRange,
         }
         let mut state = State::Unknown;
         for line in s.lines() {
@@ -154,7 +365,9 @@ impl std::str::FromStr for ClockState {
 
             let line = line[start..].trim();
             match state {
-                _ if line == "OD_RANGE:" => break,
+                _ if line == RANGE_LABEL => {
+                    state = State::ParseRange;
+                }
                 _ if line == ENGINE_CLOCK_LABEL => {
                     state = State::ParseEngineClock;
                 }
@@ -164,12 +377,20 @@ impl std::str::FromStr for ClockState {
                 _ if line == CURVE_POINTS_LABEL => {
                     state = State::ParseCurve;
                 }
+                _ if line == VOLTAGE_OFFSET_LABEL => {
+                    state = State::ParseVoltageOffset;
+                }
+                // sections not handled here, ex. `OD_FAN_CURVE:`
+                _ if line.starts_with("OD_") && line.ends_with(':') => {
+                    state = State::Unknown;
+                }
                 State::ParseEngineClock => {
                     if clock_state.engine_label_lowest.is_none() {
                         clock_state.engine_label_lowest = Some(parse_freq_line(line)?);
                     } else {
                         clock_state.engine_label_highest = Some(parse_freq_line(line)?);
                     }
+                    clock_state.engine_levels.push(parse_level_line(line)?);
                 }
                 State::ParseMemoryClock => {
                     if clock_state.memory_label_lowest.is_none() {
@@ -177,6 +398,10 @@ impl std::str::FromStr for ClockState {
                     } else {
                         clock_state.memory_label_highest = Some(parse_freq_line(line)?);
                     }
+                    clock_state.memory_levels.push(parse_level_line(line)?);
+                }
+                State::ParseVoltageOffset => {
+                    clock_state.voltage_offset = Some(parse_offset(line)?);
                 }
                 State::ParseCurve => {
                     let (freq, volt) = parse_freq_voltage_line(line)?;
@@ -185,6 +410,7 @@ impl std::str::FromStr for ClockState {
                         voltage: volt,
                     });
                 }
+                State::ParseRange => clock_state.od_range.parse_line(line)?,
                 _ => {}
             }
         }
@@ -195,7 +421,7 @@ impl std::str::FromStr for ClockState {
 fn consume_mode_number<'line>(
     line: &'line str,
     chars: &mut Peekable<Chars<'line>>,
-) -> std::result::Result<(), ClockStateError> {
+) -> std::result::Result<u16, ClockStateError> {
     let mut buffer = String::with_capacity(4);
     while chars.peek().filter(|c| c.is_numeric()).is_some() {
         buffer.push(chars.next().unwrap());
@@ -207,7 +433,7 @@ fn consume_mode_number<'line>(
         .next()
         .filter(|c| *c == ':')
         .ok_or_else(|| ClockStateError::InvalidEngineClockSection(line.to_string()))?;
-    Ok(())
+    Ok(buffer.parse()?)
 }
 
 fn consume_freq(chars: &mut Peekable<Chars>) -> std::result::Result<Frequency, ClockStateError> {
@@ -238,6 +464,22 @@ fn parse_freq_line(line: &str) -> std::result::Result<Frequency, ClockStateError
     consume_freq(&mut chars)
 }
 
+fn parse_level_line(line: &str) -> std::result::Result<OdLevel, ClockStateError> {
+    let mut chars = line.chars().peekable();
+    let index = consume_mode_number(line, &mut chars)?;
+    let freq = consume_freq(&mut chars)?;
+    consume_white(&mut chars);
+    let voltage = match chars.peek() {
+        Some(_) => Some(consume_voltage(&mut chars)?),
+        None => None,
+    };
+    Ok(OdLevel {
+        index,
+        freq,
+        voltage,
+    })
+}
+
 fn parse_freq_voltage_line(
     line: &str,
 ) -> std::result::Result<(Frequency, Voltage), ClockStateError> {
@@ -352,12 +594,14 @@ mod parse_frequency {
 }
 
 #[cfg(test)]
-mod state_tests {
-    use crate::clock_state::{ClockState, CurvePoint, Frequency, Voltage};
+pub(crate) mod state_tests {
+    use std::collections::BTreeMap;
 
-    #[test]
-    fn valid_string() {
-        let s = r#"
+    use crate::clock_state::{
+        ClockState, CurvePoint, Frequency, OdFormat, OdLevel, OdRange, ValueRange, Voltage,
+    };
+
+    pub(crate) static VALID_STRING: &str = r#"
 OD_SCLK:
 0: 800Mhz
 1: 2100Mhz
@@ -377,7 +621,10 @@ VDDC_CURVE_VOLT[1]:     750mV        1200mV
 VDDC_CURVE_SCLK[2]:     800Mhz       2150Mhz
 VDDC_CURVE_VOLT[2]:     750mV        1200mV
         "#;
-        let res = s.trim().parse::<ClockState>();
+
+    #[test]
+    fn valid_string() {
+        let res = VALID_STRING.trim().parse::<ClockState>();
         assert_eq!(
             res,
             Ok(ClockState {
@@ -426,7 +673,254 @@ VDDC_CURVE_VOLT[2]:     750mV        1200mV
                     unit: "MHz".to_string(),
                 }),
                 memory_label_highest: None,
+                engine_levels: vec![
+                    OdLevel {
+                        index: 0,
+                        freq: Frequency {
+                            value: 800,
+                            unit: "Mhz".to_string(),
+                        },
+                        voltage: None,
+                    },
+                    OdLevel {
+                        index: 1,
+                        freq: Frequency {
+                            value: 2100,
+                            unit: "Mhz".to_string(),
+                        },
+                        voltage: None,
+                    },
+                ],
+                memory_levels: vec![OdLevel {
+                    index: 1,
+                    freq: Frequency {
+                        value: 875,
+                        unit: "MHz".to_string(),
+                    },
+                    voltage: None,
+                }],
+                voltage_offset: None,
+                od_range: OdRange {
+                    engine: Some(ValueRange {
+                        min: 800,
+                        max: 2150
+                    }),
+                    memory: Some(ValueRange { min: 625, max: 950 }),
+                    voltage: None,
+                    curve_freq: (0..3)
+                        .map(|idx| (
+                            idx,
+                            ValueRange {
+                                min: 800,
+                                max: 2150
+                            }
+                        ))
+                        .collect::<BTreeMap<_, _>>(),
+                    curve_voltage: (0..3)
+                        .map(|idx| (
+                            idx,
+                            ValueRange {
+                                min: 750,
+                                max: 1200
+                            }
+                        ))
+                        .collect::<BTreeMap<_, _>>(),
+                    voltage_offset: None,
+                },
             })
         );
     }
+
+    #[test]
+    fn vega20_format() {
+        let state = VALID_STRING.parse::<ClockState>().unwrap();
+        assert_eq!(state.format(), Some(OdFormat::VddcCurve));
+    }
+
+    #[test]
+    fn polaris_format() {
+        let s = r#"OD_SCLK:
+0:        300MHz        750mV
+1:        600MHz        769mV
+2:        900MHz        887mV
+3:       1145MHz       1100mV
+4:       1215MHz       1150mV
+5:       1257MHz       1150mV
+6:       1300MHz       1150mV
+7:       1340MHz       1150mV
+OD_MCLK:
+0:        300MHz        750mV
+1:       2000MHz        800mV
+OD_RANGE:
+SCLK:     300MHz       2000MHz
+MCLK:     300MHz       2250MHz
+VDDC:     750mV        1150mV
+"#;
+        let state = s.parse::<ClockState>().unwrap();
+        assert_eq!(state.format(), Some(OdFormat::StateVoltage));
+        assert_eq!(state.engine_levels.len(), 8);
+        assert_eq!(
+            state.engine_levels[3],
+            OdLevel {
+                index: 3,
+                freq: Frequency {
+                    value: 1145,
+                    unit: "MHz".to_string()
+                },
+                voltage: Some(Voltage {
+                    value: 1100,
+                    unit: "mV".to_string()
+                }),
+            }
+        );
+        assert_eq!(
+            state.memory_levels[1].voltage.as_ref().map(|v| v.value),
+            Some(800)
+        );
+    }
+
+    #[test]
+    fn navi10_format() {
+        let s = r#"OD_SCLK:
+0: 300Mhz
+1: 2000Mhz
+OD_MCLK:
+1: 875MHz
+OD_VDDC_CURVE:
+0: 700MHz 711mV
+1: 1350MHz 785mV
+2: 2000MHz 1106mV
+OD_RANGE:
+SCLK:     300Mhz       2150Mhz
+MCLK:     625Mhz        950Mhz
+VDDC_CURVE_SCLK[0]:     300Mhz       2150Mhz
+VDDC_CURVE_VOLT[0]:     750mV        1200mV
+VDDC_CURVE_SCLK[1]:     300Mhz       2150Mhz
+VDDC_CURVE_VOLT[1]:     750mV        1200mV
+VDDC_CURVE_SCLK[2]:     300Mhz       2150Mhz
+VDDC_CURVE_VOLT[2]:     750mV        1200mV
+"#;
+        let state = s.parse::<ClockState>().unwrap();
+        assert_eq!(state.format(), Some(OdFormat::VddcCurve));
+        assert_eq!(state.memory_levels[0].index, 1);
+        assert_eq!(state.curve_labels.len(), 3);
+        assert_eq!(state.voltage_offset, None);
+    }
+
+    #[test]
+    fn navi21_format() {
+        let s = r#"OD_SCLK:
+0: 500Mhz
+1: 2615Mhz
+OD_MCLK:
+0: 97Mhz
+1: 1000MHz
+OD_VDDGFX_OFFSET:
+-25mV
+OD_RANGE:
+SCLK:     500Mhz       4000Mhz
+MCLK:     674Mhz       1075Mhz
+"#;
+        let state = s.parse::<ClockState>().unwrap();
+        assert_eq!(state.format(), Some(OdFormat::VoltageOffset));
+        assert_eq!(state.voltage_offset, Some(-25));
+        assert_eq!(state.engine_levels[1].freq.mhz(), 2615);
+        assert_eq!(state.memory_levels[0].freq.mhz(), 97);
+        assert_eq!(
+            state.od_range.memory,
+            Some(ValueRange {
+                min: 674,
+                max: 1075
+            })
+        );
+        assert_eq!(state.od_range.voltage_offset, None);
+    }
+
+    #[test]
+    fn clock_range_format() {
+        let s = r#"OD_SCLK:
+0: 500Mhz
+1: 2615Mhz
+OD_MCLK:
+0: 97Mhz
+1: 1000MHz
+OD_RANGE:
+SCLK:     500Mhz       4000Mhz
+MCLK:     674Mhz       1075Mhz
+"#;
+        let state = s.parse::<ClockState>().unwrap();
+        assert_eq!(state.format(), Some(OdFormat::ClockRange));
+        assert_eq!(state.voltage_offset, None);
+        assert_eq!(state.od_range.voltage_offset, None);
+        assert_eq!(state.engine_levels[1].freq.mhz(), 2615);
+    }
+
+    #[test]
+    fn navi31_format() {
+        let s = r#"OD_SCLK:
+0: 500Mhz
+1: 3150Mhz
+OD_MCLK:
+0: 97Mhz
+1: 1250MHz
+OD_VDDGFX_OFFSET:
+0mV
+OD_RANGE:
+SCLK:     500Mhz       5000Mhz
+MCLK:     1250Mhz      1500Mhz
+VDDGFX_OFFSET:    -450mv          0mv
+"#;
+        let state = s.parse::<ClockState>().unwrap();
+        assert_eq!(state.format(), Some(OdFormat::VoltageOffset));
+        assert_eq!(state.voltage_offset, Some(0));
+        assert_eq!(
+            state.od_range.voltage_offset,
+            Some(ValueRange { min: -450, max: 0 })
+        );
+        assert_eq!(
+            state.od_range.engine,
+            Some(ValueRange {
+                min: 500,
+                max: 5000
+            })
+        );
+    }
+
+    #[test]
+    fn range_of_states_with_voltage() {
+        let s = "OD_SCLK:\n0: 300MHz 750mV\n1: 2000MHz 800mV\nOD_RANGE:\nSCLK: 300MHz 2000MHz\nVDDC: 750mV 1150mV\n";
+        let state = s.parse::<ClockState>().unwrap();
+        assert_eq!(
+            state.od_range.voltage,
+            Some(ValueRange {
+                min: 750,
+                max: 1150
+            })
+        );
+        assert!(state.od_range.memory.is_none());
+    }
+
+    #[test]
+    fn unknown_sections_are_skipped() {
+        let s = "OD_SCLK:\n0: 500Mhz\n1: 2615Mhz\nOD_FAN_CURVE:\n0: 25C 15%\nOD_RANGE:\nSCLK: 500Mhz 4000Mhz\nFAN_CURVE(hotspot temp): 25C 100C\n";
+        let state = s.parse::<ClockState>().unwrap();
+        assert_eq!(
+            state.od_range.engine,
+            Some(ValueRange {
+                min: 500,
+                max: 4000
+            })
+        );
+        assert!(state.memory_label_lowest.is_none());
+    }
+
+    #[test]
+    fn malformed_range() {
+        assert_eq!(
+            "OD_RANGE:\nSCLK: 300MHz\n".parse::<ClockState>(),
+            Err(crate::clock_state::ClockStateError::InvalidRange(
+                "SCLK: 300MHz".to_string()
+            ))
+        );
+    }
 }
@@ -1,16 +1,34 @@
-use amdgpu::hw_mon::HwMon;
+use amdgpu::hw_mon::{HwMon, RootPath, SysFs};
 
+use crate::apply::Apply;
 use crate::apply_changes::ApplyChanges;
 use crate::change_state::ChangeState;
 use crate::clock_state::{ClockState, Frequency, Voltage};
+use crate::confirm::{Confirm, Watchdog};
 use crate::print_states::PrintStates;
+use crate::reset::Reset;
 use crate::setup_info::SetupInfo;
+use crate::snapshot::SnapshotCommand;
+use crate::tune::Tune;
+use crate::voltage_offset::VoltageOffset;
 use crate::{Config, VoltageError};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum HardwareModule {
     Engine,
     Memory,
+    /// `OD_VDDC_CURVE` point
+    Curve,
+}
+
+impl HardwareModule {
+    fn command(&self) -> &'static str {
+        match self {
+            HardwareModule::Engine => "s",
+            HardwareModule::Memory => "m",
+            HardwareModule::Curve => "vc",
+        }
+    }
 }
 
 impl std::str::FromStr for HardwareModule {
@@ -20,6 +38,7 @@ impl std::str::FromStr for HardwareModule {
         match s.to_lowercase().as_str() {
             "memory" => Ok(HardwareModule::Memory),
             "engine" => Ok(HardwareModule::Engine),
+            "curve" => Ok(HardwareModule::Curve),
             _ => Err(VoltageError::UnknownHardwareModule(s.to_string())),
         }
     }
@@ -31,32 +50,46 @@ pub enum VoltageCommand {
     PrintStates(PrintStates),
     ChangeState(ChangeState),
     ApplyChanges(ApplyChanges),
+    #[options(help = "Apply overclocking profile from config")]
+    Apply(Apply),
+    #[options(help = "Change GPU voltage offset (RDNA2 and newer)")]
+    VoltageOffset(VoltageOffset),
+    #[options(help = "Reset clocks and voltages to defaults")]
+    Reset(Reset),
+    #[options(help = "Keep profile applied with --confirm-within")]
+    Confirm(Confirm),
+    #[options(help = "Revert unconfirmed profile after deadline, started by apply")]
+    Watchdog(Watchdog),
+    #[options(help = "Save or restore all writable settings")]
+    Snapshot(SnapshotCommand),
+    #[options(help = "Search lowest stable voltage")]
+    Tune(Tune),
 }
 
-pub struct VoltageManipulator {
-    hw_mon: HwMon,
+pub struct VoltageManipulator<Root: RootPath = SysFs> {
+    hw_mon: HwMon<Root>,
 }
 
-impl std::ops::Deref for VoltageManipulator {
-    type Target = HwMon;
+impl<Root: RootPath> std::ops::Deref for VoltageManipulator<Root> {
+    type Target = HwMon<Root>;
 
     fn deref(&self) -> &Self::Target {
         &self.hw_mon
     }
 }
 
-impl std::ops::DerefMut for VoltageManipulator {
+impl<Root: RootPath> std::ops::DerefMut for VoltageManipulator<Root> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         &mut self.hw_mon
     }
 }
 
-impl VoltageManipulator {
-    pub fn wrap(hw_mon: HwMon, _config: &Config) -> Self {
+impl<Root: RootPath> VoltageManipulator<Root> {
+    pub fn wrap(hw_mon: HwMon<Root>, _config: &Config) -> Self {
         Self { hw_mon }
     }
 
-    pub fn wrap_all(mons: Vec<HwMon>, config: &Config) -> Vec<Self> {
+    pub fn wrap_all(mons: Vec<HwMon<Root>>, config: &Config) -> Vec<Self> {
         mons.into_iter()
             .map(|mon| Self::wrap(mon, config))
             .collect()
@@ -67,26 +100,32 @@ impl VoltageManipulator {
         Ok(())
     }
 
+    /// Restore default overdrive table, it's applied after `c`
+    pub fn write_reset(&self) -> crate::Result<()> {
+        self.device_write("pp_od_clk_voltage", "r")?;
+        Ok(())
+    }
+
+    /// Write single overdrive state, voltage is optional since RDNA cards
+    /// accept only frequency for `s` and `m`
     pub fn write_state(
         &self,
         state_index: u16,
         freq: Frequency,
-        voltage: Voltage,
+        voltage: Option<Voltage>,
         module: HardwareModule,
     ) -> crate::Result<()> {
-        self.device_write(
-            "pp_od_clk_voltage",
-            format!(
-                "{module} {state_index} {freq} {voltage}",
-                state_index = state_index,
-                freq = freq.to_string(),
-                voltage = voltage.to_string(),
-                module = match module {
-                    HardwareModule::Engine => "s",
-                    HardwareModule::Memory => "m",
-                },
-            ),
-        )?;
+        let mut command = format!("{} {} {}", module.command(), state_index, freq.mhz());
+        if let Some(voltage) = voltage {
+            command.push_str(&format!(" {}", voltage.millivolts()));
+        }
+        self.device_write("pp_od_clk_voltage", command)?;
+        Ok(())
+    }
+
+    /// Write `OD_VDDGFX_OFFSET` in mV
+    pub fn write_voltage_offset(&self, offset: i32) -> crate::Result<()> {
+        self.device_write("pp_od_clk_voltage", format!("vo {}", offset))?;
         Ok(())
     }
 
@@ -95,3 +134,34 @@ impl VoltageManipulator {
         Ok(state)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_testkit::FakeGpu;
+
+    use super::{HardwareModule, VoltageManipulator};
+    use crate::Config;
+
+    #[test]
+    fn change_state_writes_commands() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+
+        let states = manipulator.clock_states().unwrap();
+        assert_eq!(states.curve_labels.len(), 3);
+
+        manipulator
+            .write_state(
+                1,
+                "1500MHz".parse().unwrap(),
+                Some("800mV".parse().unwrap()),
+                HardwareModule::Engine,
+            )
+            .unwrap();
+        manipulator.write_apply().unwrap();
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec!["s 1 1500 800", "c"]
+        );
+    }
+}
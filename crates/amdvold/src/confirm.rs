@@ -0,0 +1,302 @@
+//! Apply guarded by confirmation, changes which are not confirmed in time are
+//! reverted by separate watchdog process.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use amdgpu::environment::{
+    Environment, CONFIG_DIR_VAR, PROCFS_ROOT_VAR, RUNTIME_DIR_VAR, STATE_DIR_VAR, SYSFS_ROOT_VAR,
+};
+use amdgpu::pending_change::{
+    claim, confirm, finish_revert, now, read_pending, unclaim, write_pending, Confirmation,
+    PendingChange,
+};
+
+use crate::snapshot::Snapshot;
+use crate::{snapshot, Config};
+
+/// Snapshot taken before guarded apply, reserved name so user snapshots are
+/// not overwritten
+pub static CONFIRM_SNAPSHOT: &str = ".before-apply";
+/// How often watchdog checks pending change
+const WATCHDOG_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfirmError {
+    #[error("Invalid timeout {0:?}, expected seconds or minutes like 30s or 2m")]
+    InvalidTimeout(String),
+    #[error("Profile {profile:?} is waiting for confirmation for next {remaining}s")]
+    Pending { profile: String, remaining: u64 },
+    #[error("Change was not confirmed in time and is already reverting")]
+    Reverting,
+    #[error("Failed to access pending change. {0}")]
+    Io(#[from] std::io::Error),
+}
+
+/// Time to confirm change, ex. `30s`, `2m` or `45`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Timeout(pub Duration);
+
+impl FromStr for Timeout {
+    type Err = ConfirmError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ConfirmError::InvalidTimeout(s.to_string());
+        let (value, multiplier) = match s.trim() {
+            s if s.ends_with('s') => (&s[..s.len() - 1], 1),
+            s if s.ends_with('m') => (&s[..s.len() - 1], 60),
+            s => (s, 1),
+        };
+        let value = value.parse::<u64>().map_err(|_| invalid())?;
+        if value == 0 {
+            return Err(invalid());
+        }
+        Ok(Self(Duration::from_secs(value * multiplier)))
+    }
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct Confirm {
+    #[options(help = "Help message")]
+    help: bool,
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct Watchdog {
+    #[options(help = "Help message")]
+    help: bool,
+}
+
+/// Save snapshot, write pending change and start watchdog
+pub fn guard(
+    env: &Environment,
+    config: &Config,
+    profile: &str,
+    timeout: Timeout,
+) -> crate::Result<PendingChange> {
+    let now = now();
+    if let Some(pending) = read_pending(env).filter(|pending| !pending.is_expired(now)) {
+        return Err(ConfirmError::Pending {
+            remaining: pending.remaining(now),
+            profile: pending.profile,
+        }
+        .into());
+    }
+    snapshot::save_as(env, config, CONFIRM_SNAPSHOT)?;
+    let pending = PendingChange {
+        profile: profile.to_string(),
+        snapshot: CONFIRM_SNAPSHOT.to_string(),
+        deadline: now + timeout.0.as_secs(),
+    };
+    write_pending(env, &pending).map_err(ConfirmError::from)?;
+    spawn_watchdog(env)?;
+    Ok(pending)
+}
+
+fn spawn_watchdog(env: &Environment) -> crate::Result<()> {
+    use std::os::unix::process::CommandExt;
+
+    let child = std::process::Command::new(std::env::current_exe()?)
+        .arg("watchdog")
+        .env(SYSFS_ROOT_VAR, &env.sysfs_root)
+        .env(PROCFS_ROOT_VAR, &env.procfs_root)
+        .env(STATE_DIR_VAR, &env.state_dir)
+        .env(RUNTIME_DIR_VAR, &env.runtime_dir)
+        .env(CONFIG_DIR_VAR, &env.config_dir)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        // closing terminal or ctrl-c must not stop watchdog
+        .process_group(0)
+        .spawn()?;
+    tracing::info!("Started watchdog {}", child.id());
+    Ok(())
+}
+
+/// Claim pending change and restore its snapshot. Nothing is restored when
+/// change was confirmed in the meantime, returns if snapshot was restored
+pub fn revert(env: &Environment, config: &Config, pending: &PendingChange) -> crate::Result<bool> {
+    let snapshot = snapshot::load(env, &pending.snapshot)?;
+    match claim(env).map_err(ConfirmError::from)? {
+        Some(claimed) if claimed == *pending => {}
+        Some(_) => {
+            unclaim(env).map_err(ConfirmError::from)?;
+            return Ok(false);
+        }
+        None => return Ok(false),
+    }
+    restore_claimed(env, config, &snapshot)?;
+    Ok(true)
+}
+
+/// Restore snapshot of claimed change and release it, also when restoring
+/// fails
+fn restore_claimed(env: &Environment, config: &Config, snapshot: &Snapshot) -> crate::Result<()> {
+    let restored = snapshot::restore_all(env, config, snapshot, false);
+    finish_revert(env).map_err(ConfirmError::from)?;
+    restored.map(drop)
+}
+
+/// Wait until pending change is confirmed or revert it after deadline
+pub fn watch(env: &Environment, config: &Config) -> crate::Result<()> {
+    loop {
+        let Some(pending) = read_pending(env) else {
+            tracing::info!("Change was confirmed");
+            return Ok(());
+        };
+        if pending.is_expired(now()) {
+            tracing::warn!(
+                "Profile {} was not confirmed, restoring snapshot {}",
+                pending.profile,
+                pending.snapshot
+            );
+            if !revert(env, config, &pending)? {
+                tracing::info!("Change was confirmed");
+            }
+            return Ok(());
+        }
+        std::thread::sleep(WATCHDOG_INTERVAL);
+    }
+}
+
+pub fn run_confirm(_command: Confirm, env: &Environment) -> crate::Result<()> {
+    match confirm(env).map_err(ConfirmError::from)? {
+        Confirmation::Confirmed(change) => println!("Confirmed profile {}", change.profile),
+        Confirmation::Reverting => return Err(ConfirmError::Reverting.into()),
+        Confirmation::Nothing => println!("Nothing to confirm"),
+    }
+    Ok(())
+}
+
+pub fn run_watchdog(_command: Watchdog, env: &Environment, config: &Config) -> crate::Result<()> {
+    watch(env, config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use amdgpu::pending_change::{
+        claim, confirm, read_pending, reverting_file, write_pending, Confirmation, PendingChange,
+    };
+    use amdgpu_testkit::FakeGpu;
+
+    use super::{restore_claimed, revert, watch, Timeout, CONFIRM_SNAPSHOT};
+    use crate::{snapshot, Config};
+
+    #[test]
+    fn parse_timeout() {
+        assert_eq!(
+            "30s".parse::<Timeout>().unwrap(),
+            Timeout(Duration::from_secs(30))
+        );
+        assert_eq!(
+            "2m".parse::<Timeout>().unwrap(),
+            Timeout(Duration::from_secs(120))
+        );
+        assert_eq!(
+            "45".parse::<Timeout>().unwrap(),
+            Timeout(Duration::from_secs(45))
+        );
+        assert!("0s".parse::<Timeout>().is_err());
+        assert!("soon".parse::<Timeout>().is_err());
+    }
+
+    #[test]
+    fn unconfirmed_change_is_reverted() {
+        let gpu = FakeGpu::navi21();
+        let config = Config::default();
+        snapshot::save_as(gpu.env(), &config, CONFIRM_SNAPSHOT).unwrap();
+        write_pending(
+            gpu.env(),
+            &PendingChange {
+                profile: String::from("undervolt"),
+                snapshot: CONFIRM_SNAPSHOT.to_string(),
+                deadline: 0,
+            },
+        )
+        .unwrap();
+        gpu.set_device_file("power_dpm_force_performance_level", "high");
+
+        watch(gpu.env(), &config).unwrap();
+        assert_eq!(read_pending(gpu.env()), None);
+        assert_eq!(
+            gpu.device_file("power_dpm_force_performance_level")
+                .as_deref(),
+            Some("auto")
+        );
+    }
+
+    #[test]
+    fn late_confirm_is_not_reverted() {
+        let gpu = FakeGpu::navi21();
+        let config = Config::default();
+        snapshot::save_as(gpu.env(), &config, CONFIRM_SNAPSHOT).unwrap();
+        let pending = PendingChange {
+            profile: String::from("undervolt"),
+            snapshot: CONFIRM_SNAPSHOT.to_string(),
+            deadline: 0,
+        };
+        gpu.set_device_file("power_dpm_force_performance_level", "high");
+
+        // confirmed after watchdog noticed expired deadline
+        assert!(!revert(gpu.env(), &config, &pending).unwrap());
+        assert_eq!(
+            gpu.device_file("power_dpm_force_performance_level")
+                .as_deref(),
+            Some("high")
+        );
+    }
+
+    #[test]
+    fn confirm_during_revert_is_rejected() {
+        let gpu = FakeGpu::navi21();
+        let config = Config::default();
+        snapshot::save_as(gpu.env(), &config, CONFIRM_SNAPSHOT).unwrap();
+        let pending = PendingChange {
+            profile: String::from("undervolt"),
+            snapshot: CONFIRM_SNAPSHOT.to_string(),
+            deadline: 0,
+        };
+        write_pending(gpu.env(), &pending).unwrap();
+        gpu.set_device_file("power_dpm_force_performance_level", "high");
+
+        // watchdog claimed change and did not restore snapshot yet
+        assert_eq!(claim(gpu.env()).unwrap(), Some(pending));
+        assert_eq!(confirm(gpu.env()).unwrap(), Confirmation::Reverting);
+        let snapshot = snapshot::load(gpu.env(), CONFIRM_SNAPSHOT).unwrap();
+        restore_claimed(gpu.env(), &config, &snapshot).unwrap();
+
+        assert_eq!(
+            gpu.device_file("power_dpm_force_performance_level")
+                .as_deref(),
+            Some("auto")
+        );
+        assert!(!reverting_file(gpu.env()).exists());
+        assert_eq!(confirm(gpu.env()).unwrap(), Confirmation::Nothing);
+    }
+
+    #[test]
+    fn guard_snapshot_is_reserved() {
+        let gpu = FakeGpu::navi21();
+        let config = Config::default();
+        assert!(snapshot::save(gpu.env(), &config, CONFIRM_SNAPSHOT).is_err());
+        snapshot::save(gpu.env(), &config, "before-apply").unwrap();
+        snapshot::save_as(gpu.env(), &config, CONFIRM_SNAPSHOT).unwrap();
+        assert!(snapshot::snapshot_dir(gpu.env())
+            .join("before-apply.toml")
+            .exists());
+        assert!(snapshot::load(gpu.env(), CONFIRM_SNAPSHOT).is_ok());
+    }
+
+    #[test]
+    fn confirmed_change_is_kept() {
+        let gpu = FakeGpu::navi21();
+        gpu.set_device_file("power_dpm_force_performance_level", "high");
+        watch(gpu.env(), &Config::default()).unwrap();
+        assert_eq!(
+            gpu.device_file("power_dpm_force_performance_level")
+                .as_deref(),
+            Some("high")
+        );
+    }
+}
@@ -3,11 +3,16 @@ use amdgpu_config::voltage::ConfigError;
 
 use crate::change_state::ChangeStateError;
 use crate::clock_state::ClockStateError;
+use crate::confirm::ConfirmError;
+use crate::snapshot::SnapshotError;
+use crate::tune::TuneError;
 
 #[derive(Debug, thiserror::Error)]
 pub enum VoltageError {
     #[error("No AMD GPU card was found")]
     NoAmdGpu,
+    #[error("Profile {0:?} is not defined in config")]
+    UnknownProfile(String),
     #[error("Unknown hardware module {0:?}")]
     UnknownHardwareModule(String),
     #[error("{0}")]
@@ -21,5 +26,11 @@ pub enum VoltageError {
     #[error("{0:}")]
     ChangeStateError(#[from] ChangeStateError),
     #[error("{0:}")]
+    Snapshot(#[from] SnapshotError),
+    #[error("{0:}")]
+    Confirm(#[from] ConfirmError),
+    #[error("{0:}")]
+    Tune(#[from] TuneError),
+    #[error("{0:}")]
     AmdUtils(#[from] utils::AmdGpuError),
 }
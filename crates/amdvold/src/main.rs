@@ -1,3 +1,4 @@
+use amdgpu::environment::{Environment, Overrides};
 use amdgpu::utils::ensure_config_dir;
 use amdgpu_config::voltage::{load_config, Config};
 use gumdrop::Options;
@@ -8,15 +9,23 @@ use tracing_subscriber::EnvFilter;
 use crate::command::VoltageCommand;
 use crate::error::VoltageError;
 
+mod apply;
 mod apply_changes;
 mod change_state;
 mod clock_state;
 mod command;
+mod confirm;
 mod error;
 mod print_states;
+mod reset;
 mod setup_info;
+mod snapshot;
+mod transaction;
+mod tune;
+mod voltage_offset;
 
-pub static DEFAULT_CONFIG_PATH: &str = "/etc/amdfand/voltage.toml";
+/// Name of config file in config directory
+pub static CONFIG_FILE: &str = "voltage.toml";
 
 pub type Result<T> = std::result::Result<T, VoltageError>;
 
@@ -28,11 +37,37 @@ pub struct Opts {
     version: bool,
     #[options(help = "Config location")]
     config: Option<String>,
+    #[options(
+        no_short,
+        help = "sysfs mount point, by default /sys (env AMDGPU_SYSFS_ROOT)"
+    )]
+    sysfs_root: Option<String>,
+    #[options(
+        no_short,
+        help = "Config directory, by default /etc/amdfand (env AMDGPU_CONFIG_DIR)"
+    )]
+    config_dir: Option<String>,
+    #[options(
+        no_short,
+        help = "State directory with snapshots, by default /var/lib (env AMDGPU_STATE_DIR)"
+    )]
+    state_dir: Option<String>,
     #[options(command)]
     command: Option<command::VoltageCommand>,
 }
 
-fn run(config: Config) -> Result<()> {
+impl Opts {
+    fn environment(&self) -> Environment {
+        Environment::from_env().with_overrides(Overrides {
+            sysfs_root: self.sysfs_root.clone(),
+            config_dir: self.config_dir.clone(),
+            state_dir: self.state_dir.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+fn run(env: &Environment, config_path: &str, config: Config) -> Result<()> {
     let opts: Opts = Opts::parse_args_default_or_exit();
 
     if opts.version {
@@ -45,39 +80,48 @@ fn run(config: Config) -> Result<()> {
             Opts::usage();
             Ok(())
         }
-        Some(VoltageCommand::PrintStates(command)) => print_states::run(command, config),
+        Some(VoltageCommand::PrintStates(command)) => print_states::run(command, env, config),
         Some(VoltageCommand::SetupInfo(command)) => setup_info::run(command, &config),
-        Some(VoltageCommand::ChangeState(command)) => change_state::run(command, &config),
-        Some(VoltageCommand::ApplyChanges(command)) => apply_changes::run(command, &config),
+        Some(VoltageCommand::ChangeState(command)) => change_state::run(command, env, &config),
+        Some(VoltageCommand::ApplyChanges(command)) => apply_changes::run(command, env, &config),
+        Some(VoltageCommand::Apply(command)) => apply::run(command, env, &config),
+        Some(VoltageCommand::VoltageOffset(command)) => voltage_offset::run(command, env, &config),
+        Some(VoltageCommand::Reset(command)) => reset::run(command, env, &config),
+        Some(VoltageCommand::Confirm(command)) => confirm::run_confirm(command, env),
+        Some(VoltageCommand::Watchdog(command)) => confirm::run_watchdog(command, env, &config),
+        Some(VoltageCommand::Snapshot(command)) => snapshot::run(command, env, &config),
+        Some(VoltageCommand::Tune(command)) => tune::run(command, env, config, config_path),
     }
 }
 
-fn setup() -> Result<(String, Config)> {
+fn setup() -> Result<(Environment, String, Config)> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "DEBUG");
     }
-    ensure_config_dir()?;
+    let opts = Opts::parse_args_default_or_exit();
+    let env = opts.environment();
+    ensure_config_dir(&env)?;
 
-    let config_path = Opts::parse_args_default_or_exit()
+    let config_path = opts
         .config
-        .unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string());
+        .unwrap_or_else(|| env.config_file(CONFIG_FILE).to_string_lossy().into_owned());
     let config = load_config(&config_path)?;
     tracing_subscriber::fmt::fmt()
         .with_env_filter(EnvFilter::from_default_env())
         .with_max_level(config.log_level().as_str().parse::<LevelFilter>().unwrap())
         .init();
-    Ok((config_path, config))
+    Ok((env, config_path, config))
 }
 
 fn main() -> Result<()> {
-    let (config_path, config) = match setup() {
+    let (env, config_path, config) = match setup() {
         Ok(config) => config,
         Err(e) => {
             error!("{}", e);
             std::process::exit(1);
         }
     };
-    match run(config) {
+    match run(&env, &config_path, config) {
         Ok(()) => Ok(()),
         Err(e) => {
             let _config = load_config(&config_path).expect(
@@ -1,3 +1,4 @@
+use amdgpu::environment::Environment;
 use amdgpu::utils::hw_mons;
 
 use crate::command::VoltageManipulator;
@@ -8,35 +9,39 @@ pub struct PrintStates {
     help: bool,
 }
 
-pub fn run(_command: PrintStates, config: Config) -> crate::Result<()> {
-    let mons = VoltageManipulator::wrap_all(hw_mons(false)?, &config);
+pub fn run(_command: PrintStates, env: &Environment, config: Config) -> crate::Result<()> {
+    let mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, &config);
     for mon in mons {
         let states = mon.clock_states()?;
+        if let Some(format) = states.format() {
+            println!("Overdrive format: {}", format);
+            println!();
+        }
         println!("Engine clock frequencies:");
         if let Some(freq) = states.engine_label_lowest {
-            println!("  LOWEST {}", freq.to_string());
+            println!("  LOWEST {}", freq);
         }
         if let Some(freq) = states.engine_label_highest {
-            println!("  HIGHEST {}", freq.to_string());
+            println!("  HIGHEST {}", freq);
         }
         println!();
         println!("Memory clock frequencies:");
         if let Some(freq) = states.memory_label_lowest {
-            println!("  LOWEST {}", freq.to_string());
+            println!("  LOWEST {}", freq);
         }
         if let Some(freq) = states.memory_label_highest {
-            println!("  HIGHEST {}", freq.to_string());
+            println!("  HIGHEST {}", freq);
         }
         println!();
         println!("Curves:");
         for curve in states.curve_labels.iter() {
-            println!(
-                "  {:>10} {:>10}",
-                curve.freq.to_string(),
-                curve.voltage.to_string()
-            );
+            println!("  {:>10} {:>10}", curve.freq, curve.voltage);
         }
         println!();
+        if let Some(offset) = states.voltage_offset {
+            println!("Voltage offset: {}mV", offset);
+            println!();
+        }
     }
     Ok(())
 }
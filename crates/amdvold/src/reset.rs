@@ -0,0 +1,28 @@
+use amdgpu::environment::Environment;
+use amdgpu::utils::hw_mons;
+
+use crate::command::VoltageManipulator;
+use crate::{Config, VoltageError};
+
+#[derive(Debug, gumdrop::Options)]
+pub struct Reset {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Only restore default table without committing it")]
+    no_apply: bool,
+}
+
+pub fn run(command: Reset, env: &Environment, config: &Config) -> crate::Result<()> {
+    let mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, config);
+    if mons.is_empty() {
+        return Err(VoltageError::NoAmdGpu);
+    }
+    for mon in mons {
+        mon.write_reset()?;
+        if !command.no_apply {
+            mon.write_apply()?;
+        }
+        println!("Reset {}", mon.card());
+    }
+    Ok(())
+}
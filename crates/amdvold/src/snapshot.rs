@@ -0,0 +1,576 @@
+//! Save all writable settings of cards and apply them back later.
+
+use std::path::PathBuf;
+
+use amdgpu::environment::Environment;
+use amdgpu::hw_mon::RootPath;
+use amdgpu::pending_change::now;
+use amdgpu::utils::{atomic_write, hw_mons};
+use amdgpu::{PULSE_WIDTH_MODULATION, PULSE_WIDTH_MODULATION_MODE};
+use gumdrop::Options;
+use serde::{Deserialize, Serialize};
+
+use crate::clock_state::{ClockState, OdFormat, OdLevel};
+use crate::command::VoltageManipulator;
+use crate::{Config, VoltageError};
+
+static PERFORMANCE_LEVEL: &str = "power_dpm_force_performance_level";
+static POWER_PROFILE: &str = "pp_power_profile_mode";
+static OD_CLK_VOLTAGE: &str = "pp_od_clk_voltage";
+static DPM_SCLK: &str = "pp_dpm_sclk";
+static DPM_MCLK: &str = "pp_dpm_mclk";
+static POWER_CAP: &str = "power1_cap";
+/// Performance level required by DPM masks, power profiles and some
+/// overdrive tables
+static MANUAL_LEVEL: &str = "manual";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SnapshotError {
+    #[error("Snapshot name {0:?} is not valid, use name without path separators and leading dot")]
+    InvalidName(String),
+    #[error("Snapshot {0:?} does not exist")]
+    NotFound(PathBuf),
+    #[error("Failed to access snapshot {path:?}. {io}")]
+    Io { io: std::io::Error, path: PathBuf },
+    #[error("Snapshot file is not valid. {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Failed to serialize snapshot. {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Snapshot of {card} was taken for device {expected}, found {found}")]
+    DeviceMismatch {
+        card: String,
+        expected: String,
+        found: String,
+    },
+}
+
+#[derive(Debug, Options)]
+pub struct SnapshotCommand {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(command)]
+    command: Option<SnapshotSubCommand>,
+}
+
+#[derive(Debug, Options)]
+pub enum SnapshotSubCommand {
+    #[options(help = "Save current settings of all cards")]
+    Save(Save),
+    #[options(help = "Apply saved settings")]
+    Restore(Restore),
+    #[options(help = "List saved snapshots")]
+    List(List),
+}
+
+#[derive(Debug, Options)]
+pub struct Save {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(free, help = "Snapshot name")]
+    name: Option<String>,
+}
+
+#[derive(Debug, Options)]
+pub struct Restore {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(free, help = "Snapshot name")]
+    name: Option<String>,
+    #[options(help = "Print writes without changing anything")]
+    dry_run: bool,
+}
+
+#[derive(Debug, Options)]
+pub struct List {
+    #[options(help = "Help message")]
+    help: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct Snapshot {
+    /// Seconds since UNIX epoch
+    pub created: u64,
+    #[serde(default)]
+    pub cards: Vec<CardSnapshot>,
+}
+
+/// Settings of single card, missing value is not supported by card
+#[derive(Debug, Default, PartialEq, Deserialize, Serialize)]
+pub struct CardSnapshot {
+    /// ex. `card0`
+    pub card: String,
+    /// PCI device id, snapshot is not restored on different GPU
+    pub device: Option<String>,
+    /// `power_dpm_force_performance_level`
+    pub performance_level: Option<String>,
+    /// Active profile of `pp_power_profile_mode`
+    pub power_profile: Option<u32>,
+    /// `power1_cap` in microwatts
+    pub power_cap: Option<u64>,
+    /// `pwm1_enable`
+    pub pwm_enable: Option<u64>,
+    /// `pwm1`
+    pub pwm: Option<u64>,
+    /// Active levels of `pp_dpm_sclk`
+    #[serde(default)]
+    pub dpm_sclk: Vec<u32>,
+    /// Active levels of `pp_dpm_mclk`
+    #[serde(default)]
+    pub dpm_mclk: Vec<u32>,
+    /// Commands recreating `pp_od_clk_voltage` table, ex. `s 1 2100`
+    #[serde(default)]
+    pub od_clk_voltage: Vec<String>,
+}
+
+/// Single write done by restore
+#[derive(Debug, PartialEq)]
+pub enum SettingWrite {
+    Device { file: &'static str, value: String },
+    HwMon { file: &'static str, value: u64 },
+}
+
+impl std::fmt::Display for SettingWrite {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SettingWrite::Device { file, value } => write!(f, "{} <- {}", file, value),
+            SettingWrite::HwMon { file, value } => write!(f, "hwmon/{} <- {}", file, value),
+        }
+    }
+}
+
+impl<Root: RootPath> VoltageManipulator<Root> {
+    pub fn snapshot(&self) -> CardSnapshot {
+        let device = |name: &str| self.device_read(name).ok();
+        let hw_mon = |name: &str| {
+            self.hw_mon_read(name)
+                .ok()
+                .and_then(|s| s.parse::<u64>().ok())
+        };
+        CardSnapshot {
+            card: self.card().to_string(),
+            device: device("device"),
+            performance_level: device(PERFORMANCE_LEVEL),
+            power_profile: device(POWER_PROFILE).and_then(|s| active_profile(&s)),
+            power_cap: hw_mon(POWER_CAP),
+            pwm_enable: hw_mon(PULSE_WIDTH_MODULATION_MODE),
+            pwm: hw_mon(PULSE_WIDTH_MODULATION),
+            dpm_sclk: device(DPM_SCLK)
+                .map(|s| active_levels(&s))
+                .unwrap_or_default(),
+            dpm_mclk: device(DPM_MCLK)
+                .map(|s| active_levels(&s))
+                .unwrap_or_default(),
+            od_clk_voltage: device(OD_CLK_VOLTAGE)
+                .and_then(|s| s.parse::<ClockState>().ok())
+                .map(|state| od_commands(&state))
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Write settings in order accepted by driver. Returns performed writes,
+    /// with `dry_run` nothing is written
+    pub fn restore(
+        &self,
+        snapshot: &CardSnapshot,
+        dry_run: bool,
+    ) -> crate::Result<Vec<SettingWrite>> {
+        if let (Some(expected), Ok(found)) = (&snapshot.device, self.device_read("device")) {
+            if *expected != found {
+                return Err(SnapshotError::DeviceMismatch {
+                    card: snapshot.card.clone(),
+                    expected: expected.clone(),
+                    found,
+                }
+                .into());
+            }
+        }
+        let writes = restore_plan(snapshot);
+        if !dry_run {
+            for write in writes.iter() {
+                match write {
+                    SettingWrite::Device { file, value } => self.device_write(file, value)?,
+                    SettingWrite::HwMon { file, value } => self.hw_mon_write(file, *value)?,
+                }
+            }
+        }
+        Ok(writes)
+    }
+}
+
+/// Manual level goes first, overdrive table is committed before DPM masks and
+/// power profile, saved level, power cap and fan are last
+fn restore_plan(snapshot: &CardSnapshot) -> Vec<SettingWrite> {
+    let device = |file: &'static str, value: String| SettingWrite::Device { file, value };
+    let mut writes = vec![];
+    let level = snapshot.performance_level.as_deref();
+    let manual = level == Some(MANUAL_LEVEL);
+    let needs_manual = !snapshot.od_clk_voltage.is_empty()
+        || snapshot.power_profile.is_some()
+        || (manual && !(snapshot.dpm_sclk.is_empty() && snapshot.dpm_mclk.is_empty()));
+    if needs_manual && level.is_some() {
+        writes.push(device(PERFORMANCE_LEVEL, MANUAL_LEVEL.to_string()));
+    }
+    if !snapshot.od_clk_voltage.is_empty() {
+        for command in snapshot.od_clk_voltage.iter() {
+            writes.push(device(OD_CLK_VOLTAGE, command.clone()));
+        }
+        writes.push(device(OD_CLK_VOLTAGE, "c".to_string()));
+    }
+    // masks are used by driver only in manual level
+    if manual {
+        for (file, levels) in [
+            (DPM_SCLK, &snapshot.dpm_sclk),
+            (DPM_MCLK, &snapshot.dpm_mclk),
+        ] {
+            if !levels.is_empty() {
+                let levels: Vec<String> = levels.iter().map(u32::to_string).collect();
+                writes.push(device(file, levels.join(" ")));
+            }
+        }
+    }
+    if let Some(profile) = snapshot.power_profile {
+        writes.push(device(POWER_PROFILE, profile.to_string()));
+    }
+    if let Some(level) = level.filter(|_| !(manual && needs_manual)) {
+        writes.push(device(PERFORMANCE_LEVEL, level.to_string()));
+    }
+    if let Some(value) = snapshot.power_cap {
+        writes.push(SettingWrite::HwMon {
+            file: POWER_CAP,
+            value,
+        });
+    }
+    if let Some(mode) = snapshot.pwm_enable {
+        writes.push(SettingWrite::HwMon {
+            file: PULSE_WIDTH_MODULATION_MODE,
+            value: mode,
+        });
+        // modulation is kept by driver only in manual mode
+        if let Some(pwm) = snapshot.pwm.filter(|_| mode == 1) {
+            writes.push(SettingWrite::HwMon {
+                file: PULSE_WIDTH_MODULATION,
+                value: pwm,
+            });
+        }
+    }
+    writes
+}
+
+/// Commands which write current `pp_od_clk_voltage` table back. Only
+/// sections of detected format are written, driver accepts only numbers
+pub fn od_commands(state: &ClockState) -> Vec<String> {
+    let format = match state.format() {
+        Some(format) => format,
+        None => return vec![],
+    };
+    let level = |prefix: &str, level: &OdLevel| match level.voltage.as_ref() {
+        Some(voltage) if format == OdFormat::StateVoltage => format!(
+            "{} {} {} {}",
+            prefix,
+            level.index,
+            level.freq.mhz(),
+            voltage.millivolts()
+        ),
+        _ => format!("{} {} {}", prefix, level.index, level.freq.mhz()),
+    };
+    let mut commands: Vec<String> = state
+        .engine_levels
+        .iter()
+        .map(|l| level("s", l))
+        .chain(state.memory_levels.iter().map(|l| level("m", l)))
+        .collect();
+    match format {
+        OdFormat::VddcCurve => {
+            commands.extend(state.curve_labels.iter().enumerate().map(|(index, point)| {
+                format!(
+                    "vc {} {} {}",
+                    index,
+                    point.freq.mhz(),
+                    point.voltage.millivolts()
+                )
+            }))
+        }
+        OdFormat::VoltageOffset => {
+            if let Some(offset) = state.voltage_offset {
+                commands.push(format!("vo {}", offset));
+            }
+        }
+        OdFormat::StateVoltage | OdFormat::ClockRange => {}
+    }
+    commands
+}
+
+/// Levels marked with `*` in `pp_dpm_*` file
+fn active_levels(content: &str) -> Vec<u32> {
+    content
+        .lines()
+        .filter(|line| line.trim_end().ends_with('*'))
+        .filter_map(|line| line.split_once(':'))
+        .filter_map(|(level, _)| level.trim().parse().ok())
+        .collect()
+}
+
+/// Profile marked with `*` in `pp_power_profile_mode`
+fn active_profile(content: &str) -> Option<u32> {
+    content
+        .lines()
+        .filter(|line| line.contains('*'))
+        .find_map(|line| line.split_whitespace().next()?.parse().ok())
+}
+
+pub fn snapshot_dir(env: &Environment) -> PathBuf {
+    env.state_dir.join("amdvold").join("snapshots")
+}
+
+/// Snapshots with leading dot are taken by amdvold itself, ex. before
+/// `apply --confirm-within`. They can't be saved by user and are not listed
+fn is_reserved(name: &str) -> bool {
+    name.starts_with('.')
+}
+
+fn snapshot_path(env: &Environment, name: &str) -> Result<PathBuf, SnapshotError> {
+    if name.trim_start_matches('.').is_empty() || name.contains('/') {
+        return Err(SnapshotError::InvalidName(name.to_string()));
+    }
+    Ok(snapshot_dir(env).join(format!("{}.toml", name)))
+}
+
+pub fn save(env: &Environment, config: &Config, name: &str) -> crate::Result<PathBuf> {
+    if is_reserved(name) {
+        return Err(SnapshotError::InvalidName(name.to_string()).into());
+    }
+    save_as(env, config, name)
+}
+
+/// Save snapshot also under reserved name
+pub(crate) fn save_as(env: &Environment, config: &Config, name: &str) -> crate::Result<PathBuf> {
+    let path = snapshot_path(env, name)?;
+    let mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, config);
+    if mons.is_empty() {
+        return Err(VoltageError::NoAmdGpu);
+    }
+    let snapshot = Snapshot {
+        created: now(),
+        cards: mons.iter().map(VoltageManipulator::snapshot).collect(),
+    };
+    let content = toml::to_string(&snapshot).map_err(SnapshotError::from)?;
+    let io_err = |io| SnapshotError::Io {
+        io,
+        path: path.clone(),
+    };
+    std::fs::create_dir_all(snapshot_dir(env)).map_err(io_err)?;
+    atomic_write(&path, content, 0).map_err(io_err)?;
+    Ok(path)
+}
+
+pub fn load(env: &Environment, name: &str) -> crate::Result<Snapshot> {
+    let path = snapshot_path(env, name)?;
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(SnapshotError::NotFound(path).into())
+        }
+        Err(io) => return Err(SnapshotError::Io { io, path }.into()),
+    };
+    Ok(toml::from_str(&content).map_err(SnapshotError::from)?)
+}
+
+/// Restore settings of all cards from snapshot which are present, returns
+/// writes with card names
+pub fn restore_all(
+    env: &Environment,
+    config: &Config,
+    snapshot: &Snapshot,
+    dry_run: bool,
+) -> crate::Result<Vec<(String, SettingWrite)>> {
+    let mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, config);
+    let mut writes = vec![];
+    for card in snapshot.cards.iter() {
+        let Some(mon) = mons.iter().find(|mon| mon.card().to_string() == card.card) else {
+            tracing::warn!("{} from snapshot is not present, skipping", card.card);
+            continue;
+        };
+        for write in mon.restore(card, dry_run)? {
+            writes.push((card.card.clone(), write));
+        }
+    }
+    Ok(writes)
+}
+
+pub fn run(command: SnapshotCommand, env: &Environment, config: &Config) -> crate::Result<()> {
+    match command.command {
+        Some(SnapshotSubCommand::Save(Save {
+            name: Some(name), ..
+        })) => {
+            let path = save(env, config, &name)?;
+            println!("Saved snapshot {} to {}", name, path.display());
+        }
+        Some(SnapshotSubCommand::Restore(Restore {
+            name: Some(name),
+            dry_run,
+            ..
+        })) => {
+            let snapshot = load(env, &name)?;
+            for (card, write) in restore_all(env, config, &snapshot, dry_run)? {
+                println!("{} {}", card, write);
+            }
+            if !dry_run {
+                println!("Restored snapshot {}", name);
+            }
+        }
+        Some(SnapshotSubCommand::List(_)) => {
+            let mut names: Vec<String> = std::fs::read_dir(snapshot_dir(env))
+                .map(|dir| {
+                    dir.filter_map(|entry| entry.ok())
+                        .filter_map(|entry| {
+                            entry
+                                .file_name()
+                                .to_str()
+                                .and_then(|name| name.strip_suffix(".toml"))
+                                .filter(|name| !is_reserved(name))
+                                .map(String::from)
+                        })
+                        .collect()
+                })
+                .unwrap_or_default();
+            names.sort();
+            for name in names {
+                println!("{}", name);
+            }
+        }
+        Some(SnapshotSubCommand::Save(_)) => println!("{}", Save::usage()),
+        Some(SnapshotSubCommand::Restore(_)) => println!("{}", Restore::usage()),
+        None => println!("{}", SnapshotCommand::usage()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_testkit::FakeGpu;
+
+    use super::{od_commands, CardSnapshot, SettingWrite};
+    use crate::command::VoltageManipulator;
+    use crate::Config;
+
+    #[test]
+    fn overdrive_tables_to_commands() {
+        let gpu = FakeGpu::polaris10();
+        let commands = od_commands(
+            &gpu.device_file("pp_od_clk_voltage")
+                .unwrap()
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(commands.len(), 10);
+        assert_eq!(commands[0], "s 0 300 750");
+        assert_eq!(commands[9], "m 1 2000 800");
+
+        let gpu = FakeGpu::vega20();
+        let commands = od_commands(
+            &gpu.device_file("pp_od_clk_voltage")
+                .unwrap()
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            commands,
+            vec![
+                "s 0 800",
+                "s 1 2100",
+                "m 1 875",
+                "vc 0 800 706",
+                "vc 1 1450 772",
+                "vc 2 2100 1143"
+            ]
+        );
+
+        let gpu = FakeGpu::navi21();
+        let commands = od_commands(
+            &gpu.device_file("pp_od_clk_voltage")
+                .unwrap()
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(commands.last().map(String::as_str), Some("vo 0"));
+
+        // offset is written only when it was parsed
+        let table = gpu
+            .device_file("pp_od_clk_voltage")
+            .unwrap()
+            .replace("0mV", "auto");
+        let commands = table
+            .parse()
+            .map(|state| od_commands(&state))
+            .unwrap_or_default();
+        assert!(commands.iter().all(|command| !command.starts_with("vo")));
+    }
+
+    #[test]
+    fn save_and_restore_in_order() {
+        let gpu = FakeGpu::navi21().with_fan(90, 1200);
+        gpu.set_device_file("power_dpm_force_performance_level", "manual");
+        gpu.set_device_file("pp_dpm_sclk", "0: 500Mhz\n1: 1800Mhz *\n2: 2615Mhz\n");
+        gpu.set_mon_file("pwm1_enable", "1");
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+
+        let snapshot = manipulator.snapshot();
+        assert_eq!(snapshot.device.as_deref(), Some("0x73bf"));
+        assert_eq!(snapshot.dpm_sclk, vec![1]);
+        assert_eq!(snapshot.dpm_mclk, vec![0]);
+        assert_eq!(snapshot.power_profile, Some(0));
+        let snapshot: CardSnapshot = toml::from_str(&toml::to_string(&snapshot).unwrap()).unwrap();
+
+        let planned = manipulator.restore(&snapshot, true).unwrap();
+        assert!(gpu.writes().is_empty());
+        assert_eq!(
+            planned.first(),
+            Some(&SettingWrite::Device {
+                file: "power_dpm_force_performance_level",
+                value: "manual".into()
+            })
+        );
+
+        manipulator.restore(&snapshot, false).unwrap();
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec!["s 0 500", "s 1 2615", "m 0 97", "m 1 1000", "vo 0", "c"]
+        );
+        let files: Vec<String> = gpu
+            .writes()
+            .iter()
+            .map(|write| {
+                write
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .filter(|file| file != "pp_od_clk_voltage")
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                "power_dpm_force_performance_level",
+                "pp_dpm_sclk",
+                "pp_dpm_mclk",
+                "pp_power_profile_mode",
+                "power1_cap",
+                "pwm1_enable",
+                "pwm1"
+            ]
+        );
+        assert_eq!(gpu.writes_to("pwm1"), vec!["90"]);
+    }
+
+    #[test]
+    fn other_gpu_is_rejected() {
+        let snapshot =
+            VoltageManipulator::wrap(FakeGpu::vega20().hw_mon(), &Config::default()).snapshot();
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        assert!(manipulator.restore(&snapshot, false).is_err());
+        assert!(gpu.writes().is_empty());
+    }
+}
@@ -0,0 +1,373 @@
+//! Stage several overdrive changes, validate them together and commit once.
+
+use std::collections::BTreeMap;
+
+use amdgpu::hw_mon::RootPath;
+
+use crate::change_state::{check_format, check_offset, validate, ChangeStateError};
+use crate::clock_state::{ClockState, Frequency, Voltage};
+use crate::command::{HardwareModule, VoltageManipulator};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    State {
+        index: u16,
+        freq: Frequency,
+        voltage: Option<Voltage>,
+        module: HardwareModule,
+    },
+    VoltageOffset(i32),
+}
+
+impl Change {
+    fn label(&self) -> String {
+        match self {
+            Change::State { index, module, .. } => format!("{} {}", module_name(module), index),
+            Change::VoltageOffset(_) => String::from("Voltage offset"),
+        }
+    }
+
+    fn current(&self, state: &ClockState) -> Option<String> {
+        match self {
+            Change::State {
+                index,
+                module: HardwareModule::Curve,
+                ..
+            } => state
+                .curve_labels
+                .get(*index as usize)
+                .map(|point| format!("{} {}", point.freq.mhz(), point.voltage.millivolts())),
+            Change::State { index, module, .. } => {
+                let levels = match module {
+                    HardwareModule::Memory => &state.memory_levels,
+                    _ => &state.engine_levels,
+                };
+                levels
+                    .iter()
+                    .find(|level| level.index == *index)
+                    .map(|level| match &level.voltage {
+                        Some(voltage) => format!("{} {}", level.freq.mhz(), voltage.millivolts()),
+                        None => level.freq.mhz().to_string(),
+                    })
+            }
+            Change::VoltageOffset(_) => state.voltage_offset.map(|offset| offset.to_string()),
+        }
+    }
+
+    fn value(&self) -> String {
+        match self {
+            Change::State {
+                freq,
+                voltage: Some(voltage),
+                ..
+            } => format!("{} {}", freq.mhz(), voltage.millivolts()),
+            Change::State { freq, .. } => freq.mhz().to_string(),
+            Change::VoltageOffset(offset) => offset.to_string(),
+        }
+    }
+}
+
+/// Staged change with current value from `pp_od_clk_voltage`
+#[derive(Debug, PartialEq)]
+pub struct Diff {
+    pub label: String,
+    pub current: Option<String>,
+    pub new: String,
+}
+
+impl std::fmt::Display for Diff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} -> {}",
+            self.label,
+            self.current.as_deref().unwrap_or("unknown"),
+            self.new
+        )
+    }
+}
+
+/// Changes written only after all of them are valid. Failed write restores
+/// default table with `r`
+pub struct Transaction<'manipulator, Root: RootPath> {
+    manipulator: &'manipulator VoltageManipulator<Root>,
+    changes: Vec<Change>,
+}
+
+impl<Root: RootPath> VoltageManipulator<Root> {
+    pub fn transaction(&self) -> Transaction<'_, Root> {
+        Transaction {
+            manipulator: self,
+            changes: vec![],
+        }
+    }
+}
+
+impl<'manipulator, Root: RootPath> Transaction<'manipulator, Root> {
+    pub fn write_state(
+        &mut self,
+        index: u16,
+        freq: Frequency,
+        voltage: Option<Voltage>,
+        module: HardwareModule,
+    ) -> &mut Self {
+        self.changes.push(Change::State {
+            index,
+            freq,
+            voltage,
+            module,
+        });
+        self
+    }
+
+    pub fn write_voltage_offset(&mut self, offset: i32) -> &mut Self {
+        self.changes.push(Change::VoltageOffset(offset));
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    pub fn diff(&self, state: &ClockState) -> Vec<Diff> {
+        self.changes
+            .iter()
+            .map(|change| Diff {
+                label: change.label(),
+                current: change.current(state),
+                new: change.value(),
+            })
+            .collect()
+    }
+
+    /// Check every change against `OD_RANGE` and states after all changes
+    /// against each other
+    pub fn validate(&self, state: &ClockState) -> Result<(), ChangeStateError> {
+        let format = state.format();
+        for change in self.changes.iter() {
+            match change {
+                Change::State {
+                    index,
+                    freq,
+                    voltage,
+                    module,
+                } => {
+                    check_format(format, module, voltage.as_ref())?;
+                    validate(&state.od_range, *index, module, freq, voltage.as_ref())?;
+                }
+                Change::VoltageOffset(offset) => check_offset(state, *offset)?,
+            }
+        }
+        let modules = [
+            (
+                HardwareModule::Engine,
+                state
+                    .engine_levels
+                    .iter()
+                    .map(|level| (level.index, level.freq.mhz()))
+                    .collect::<BTreeMap<_, _>>(),
+            ),
+            (
+                HardwareModule::Memory,
+                state
+                    .memory_levels
+                    .iter()
+                    .map(|level| (level.index, level.freq.mhz()))
+                    .collect(),
+            ),
+            (
+                HardwareModule::Curve,
+                state
+                    .curve_labels
+                    .iter()
+                    .enumerate()
+                    .map(|(index, point)| (index as u16, point.freq.mhz()))
+                    .collect(),
+            ),
+        ];
+        for (module, mut levels) in modules {
+            for change in self.changes.iter() {
+                if let Change::State {
+                    index,
+                    freq,
+                    module: changed,
+                    ..
+                } = change
+                {
+                    if *changed == module {
+                        levels.insert(*index, freq.mhz());
+                    }
+                }
+            }
+            let levels: Vec<_> = levels.into_iter().collect();
+            for pair in levels.windows(2) {
+                let ((previous, previous_freq), (index, freq)) = (pair[0], pair[1]);
+                if freq < previous_freq {
+                    return Err(ChangeStateError::Unordered {
+                        name: module_name(&module),
+                        index,
+                        freq,
+                        previous,
+                        previous_freq,
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Validate and write all changes without committing them
+    /// Validate all changes and compare them with current table, nothing is
+    /// written
+    pub fn preview(&self) -> crate::Result<Vec<Diff>> {
+        let state = self.manipulator.clock_states()?;
+        self.validate(&state)?;
+        Ok(self.diff(&state))
+    }
+
+    pub fn stage(&self) -> crate::Result<()> {
+        let state = self.manipulator.clock_states()?;
+        self.validate(&state)?;
+        self.write_all().map_err(|e| self.rollback(e))
+    }
+
+    /// Validate, write and commit all changes
+    pub fn commit(self) -> crate::Result<()> {
+        self.stage()?;
+        self.manipulator.write_apply().map_err(|e| self.rollback(e))
+    }
+
+    fn write_all(&self) -> crate::Result<()> {
+        for change in self.changes.iter().cloned() {
+            match change {
+                Change::State {
+                    index,
+                    freq,
+                    voltage,
+                    module,
+                } => self.manipulator.write_state(index, freq, voltage, module)?,
+                Change::VoltageOffset(offset) => self.manipulator.write_voltage_offset(offset)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn rollback(&self, error: crate::VoltageError) -> crate::VoltageError {
+        tracing::error!(
+            "Failed to write overdrive changes, restoring defaults. {}",
+            error
+        );
+        if let Err(e) = self.manipulator.write_reset() {
+            tracing::error!("Failed to restore default overdrive table. {}", e);
+        }
+        error
+    }
+}
+
+fn module_name(module: &HardwareModule) -> &'static str {
+    match module {
+        HardwareModule::Engine => "Engine",
+        HardwareModule::Memory => "Memory",
+        HardwareModule::Curve => "Curve point",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_testkit::FakeGpu;
+
+    use crate::change_state::ChangeStateError;
+    use crate::clock_state::{Frequency, Voltage};
+    use crate::command::{HardwareModule, VoltageManipulator};
+    use crate::{Config, VoltageError};
+
+    #[test]
+    fn commit_once() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut transaction = manipulator.transaction();
+        transaction
+            .write_state(0, Frequency::from_mhz(900), None, HardwareModule::Engine)
+            .write_state(
+                2,
+                Frequency::from_mhz(2050),
+                Some(Voltage::from_millivolts(1100)),
+                HardwareModule::Curve,
+            );
+
+        let diff = transaction.diff(&manipulator.clock_states().unwrap());
+        assert_eq!(diff[0].to_string(), "Engine 0: 800 -> 900");
+        assert_eq!(diff[1].to_string(), "Curve point 2: 2100 1143 -> 2050 1100");
+
+        transaction.commit().unwrap();
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec!["s 0 900", "vc 2 2050 1100", "c"]
+        );
+    }
+
+    #[test]
+    fn validated_together() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut transaction = manipulator.transaction();
+        transaction
+            .write_state(1, Frequency::from_mhz(1000), None, HardwareModule::Engine)
+            .write_state(0, Frequency::from_mhz(1200), None, HardwareModule::Engine);
+        let err = transaction.commit().unwrap_err();
+        assert!(matches!(
+            err,
+            VoltageError::ChangeStateError(ChangeStateError::Unordered {
+                index: 1,
+                freq: 1000,
+                previous: 0,
+                previous_freq: 1200,
+                ..
+            })
+        ));
+        assert!(gpu.writes().is_empty());
+
+        let mut transaction = manipulator.transaction();
+        transaction.write_state(1, Frequency::from_mhz(1000), None, HardwareModule::Engine);
+        transaction.commit().unwrap();
+    }
+
+    #[test]
+    fn preview_writes_nothing() {
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut transaction = manipulator.transaction();
+        transaction.write_voltage_offset(-50);
+        let diff = transaction.preview().unwrap();
+        assert_eq!(diff[0].to_string(), "Voltage offset: 0 -> -50");
+
+        let mut transaction = manipulator.transaction();
+        transaction.write_state(1, Frequency::from_mhz(9000), None, HardwareModule::Engine);
+        assert!(matches!(
+            transaction.preview(),
+            Err(VoltageError::ChangeStateError(
+                ChangeStateError::OutOfRange { .. }
+            ))
+        ));
+        assert!(gpu.writes().is_empty());
+    }
+
+    #[test]
+    fn rollback_after_failed_write() {
+        let gpu = FakeGpu::navi21();
+        gpu.reject_write("pp_od_clk_voltage", "vo -50");
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut transaction = manipulator.transaction();
+        transaction
+            .write_state(1, Frequency::from_mhz(2500), None, HardwareModule::Engine)
+            .write_voltage_offset(-50);
+        assert!(matches!(
+            transaction.commit(),
+            Err(VoltageError::AmdUtils(_))
+        ));
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec!["s 1 2500", "vo -50", "r"]
+        );
+    }
+}
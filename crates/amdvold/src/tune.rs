@@ -0,0 +1,707 @@
+//! Guided undervolt search.
+//!
+//! Voltage of single curve point (or voltage offset on RDNA2 and newer cards)
+//! is lowered step by step and stress command is run after every step. Report
+//! is saved before every stress run so last stable value survives GPU crash.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use amdgpu::environment::Environment;
+use amdgpu::hw_mon::RootPath;
+use amdgpu::utils::{atomic_write, hw_mons, CONFIG_BACKUPS};
+use amdgpu::Card;
+use amdgpu_config::voltage::{ClockRange, CurvePoint, Profile};
+use serde::{Deserialize, Serialize};
+
+use crate::clock_state::{Frequency, OdFormat, Voltage};
+use crate::command::{HardwareModule, VoltageManipulator};
+use crate::{Config, VoltageError};
+
+/// Lowercase parts of kernel messages about GPU hang and recovery
+static RESET_PATTERNS: &[&str] = &["gpu reset", "gpu recovery", "ring gfx", "job_timedout"];
+/// Search range when `OD_RANGE` has no limit
+const DEFAULT_DEPTH: i32 = 200;
+const DEFAULT_STEP: u32 = 10;
+static DEFAULT_REPORT: &str = "amdvold-tune.toml";
+static DEFAULT_PROFILE: &str = "tuned";
+
+#[derive(Debug, thiserror::Error)]
+pub enum TuneError {
+    #[error("No stress command was given")]
+    NoStress,
+    #[error("Card has no overdrive table, enable it with amdgpu.ppfeaturemask")]
+    NoOverdrive,
+    #[error("Curve point has to be given for cards with voltage curve")]
+    NoPoint,
+    #[error("Card has no curve point {0}")]
+    NoCurvePoint(u16),
+    #[error("Cards with {0} table can't be tuned")]
+    Unsupported(OdFormat),
+    #[error("Failed to run stress command. {0}")]
+    Stress(std::io::Error),
+    #[error("Failed to access report {path:?}. {io}")]
+    Io { io: std::io::Error, path: PathBuf },
+    #[error("Report is not valid. {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("Failed to serialize report. {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Report has no stable value")]
+    NoStableValue,
+    #[error("Voltage step must be greater than 0")]
+    InvalidStep,
+}
+
+#[derive(Debug, gumdrop::Options)]
+pub struct Tune {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Shell command stressing GPU, fails on non zero exit code")]
+    stress: Option<String>,
+    #[options(help = "Curve point to lower, cards without curve use voltage offset")]
+    point: Option<u16>,
+    #[options(help = "Clock of tuned curve point or highest engine state in MHz")]
+    clock: Option<u32>,
+    #[options(no_short, help = "Voltage step in mV greater than 0, by default 10")]
+    step: Option<u32>,
+    #[options(
+        no_short,
+        help = "Lowest voltage or offset in mV, by default OD_RANGE minimum"
+    )]
+    limit: Option<i32>,
+    #[options(help = "File checked for amdgpu reset messages, ex. /var/log/kern.log")]
+    log: Option<String>,
+    #[options(help = "Report location, by default amdvold-tune.toml")]
+    output: Option<String>,
+    #[options(no_short, help = "Add profile from report to config")]
+    import: Option<String>,
+    #[options(no_short, help = "Name of imported profile, by default tuned")]
+    name: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TuneMode {
+    /// Voltage of `OD_VDDC_CURVE` point
+    Curve,
+    /// `OD_VDDGFX_OFFSET`
+    Offset,
+}
+
+/// Result of single stress run
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Stable,
+    Failed(String),
+}
+
+pub trait StressRunner {
+    /// Stress GPU with given voltage or offset already applied
+    fn run(&mut self, value: i32) -> Result<Outcome, TuneError>;
+}
+
+/// Run shell command, it fails on non zero exit code or reset message in
+/// its output or in log file
+pub struct ShellRunner {
+    pub command: String,
+    pub log: Option<PathBuf>,
+}
+
+impl StressRunner for ShellRunner {
+    fn run(&mut self, value: i32) -> Result<Outcome, TuneError> {
+        let log_start = self
+            .log
+            .as_ref()
+            .and_then(|log| std::fs::metadata(log).ok())
+            .map(|meta| meta.len())
+            .unwrap_or_default();
+        tracing::info!("Running stress command for {}mV", value);
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(&self.command)
+            .output()
+            .map_err(TuneError::Stress)?;
+        let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+        text.push_str(&String::from_utf8_lossy(&output.stderr));
+        if let Some(log) = self.log.as_ref() {
+            text.push_str(&read_from(log, log_start));
+        }
+        if let Some(line) = find_reset(&text) {
+            return Ok(Outcome::Failed(format!("GPU reset: {}", line.trim())));
+        }
+        if !output.status.success() {
+            return Ok(Outcome::Failed(format!("Stress command {}", output.status)));
+        }
+        Ok(Outcome::Stable)
+    }
+}
+
+/// Content appended to file after given position, rotated file is read from
+/// beginning
+fn read_from(path: &Path, start: u64) -> String {
+    let mut buffer = String::new();
+    if let Ok(mut file) = std::fs::File::open(path) {
+        let len = file.metadata().map(|meta| meta.len()).unwrap_or_default();
+        let start = if len < start { 0 } else { start };
+        if file.seek(SeekFrom::Start(start)).is_ok() {
+            let mut bytes = vec![];
+            let _ = file.read_to_end(&mut bytes);
+            buffer = String::from_utf8_lossy(&bytes).into_owned();
+        }
+    }
+    buffer
+}
+
+fn find_reset(text: &str) -> Option<&str> {
+    text.lines().find(|line| {
+        let line = line.to_lowercase();
+        RESET_PATTERNS.iter().any(|pattern| line.contains(pattern))
+    })
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Step {
+    /// mV
+    pub value: i32,
+    pub stable: bool,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct TuneReport {
+    pub card: Card,
+    pub mode: TuneMode,
+    /// Tuned curve point
+    pub point: Option<u16>,
+    /// Frequency of curve point or highest engine state in MHz
+    pub clock: Option<u32>,
+    /// Frequency of highest engine state before tuning in MHz, written back
+    /// when no value was stable
+    #[serde(default)]
+    pub original_clock: Option<u32>,
+    /// Voltage or offset before tuning in mV
+    pub start: i32,
+    pub step: u32,
+    pub limit: i32,
+    /// Value under stress test, left in report when GPU crashed
+    pub testing: Option<i32>,
+    pub last_stable: Option<i32>,
+    pub finished: bool,
+    /// Curve before tuning
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub curve: Vec<CurvePoint>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<Step>,
+}
+
+impl TuneReport {
+    /// Voltage profile with last stable value
+    pub fn profile(&self, name: &str) -> Result<Profile, TuneError> {
+        let value = self.last_stable.ok_or(TuneError::NoStableValue)?;
+        let mut profile = Profile {
+            name: name.to_string(),
+            card: Some(self.card),
+            ..Default::default()
+        };
+        match self.mode {
+            TuneMode::Curve => {
+                let mut curve = self.curve.clone();
+                if let Some(point) = self.point.and_then(|point| curve.get_mut(point as usize)) {
+                    point.voltage = value as u32;
+                    if let Some(clock) = self.clock {
+                        point.freq = clock;
+                    }
+                }
+                profile.curve = curve;
+            }
+            TuneMode::Offset => {
+                profile.voltage_offset = Some(value);
+                profile.engine = self.clock.map(|clock| ClockRange {
+                    min: None,
+                    max: Some(clock),
+                });
+            }
+        }
+        Ok(profile)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, TuneError> {
+        let content = std::fs::read_to_string(path).map_err(|io| TuneError::Io {
+            io,
+            path: path.to_path_buf(),
+        })?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), TuneError> {
+        let content = toml::to_string(self)?;
+        atomic_write(path, content, 0).map_err(|io| TuneError::Io {
+            io,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl<Root: RootPath> VoltageManipulator<Root> {
+    /// Report of search starting from current voltage of card
+    pub fn tune_report(
+        &self,
+        point: Option<u16>,
+        clock: Option<u32>,
+        step: u32,
+        limit: Option<i32>,
+    ) -> crate::Result<TuneReport> {
+        if step == 0 {
+            return Err(TuneError::InvalidStep.into());
+        }
+        let state = self.clock_states()?;
+        let (mode, start, range_min) = match state.format() {
+            None => return Err(TuneError::NoOverdrive.into()),
+            Some(format @ (OdFormat::StateVoltage | OdFormat::ClockRange)) => {
+                return Err(TuneError::Unsupported(format).into())
+            }
+            Some(OdFormat::VddcCurve) => {
+                let point = point.ok_or(TuneError::NoPoint)?;
+                let current = state
+                    .curve_labels
+                    .get(point as usize)
+                    .ok_or(TuneError::NoCurvePoint(point))?;
+                let range_min = state
+                    .od_range
+                    .curve_voltage
+                    .get(&point)
+                    .map(|range| range.min as i32);
+                (
+                    TuneMode::Curve,
+                    current.voltage.millivolts() as i32,
+                    range_min,
+                )
+            }
+            Some(OdFormat::VoltageOffset) => (
+                TuneMode::Offset,
+                state.voltage_offset.unwrap_or_default(),
+                state.od_range.voltage_offset.map(|range| range.min),
+            ),
+        };
+        let clock = match mode {
+            TuneMode::Curve => clock.or_else(|| {
+                point
+                    .and_then(|point| state.curve_labels.get(point as usize))
+                    .map(|current| current.freq.mhz())
+            }),
+            TuneMode::Offset => clock,
+        };
+        Ok(TuneReport {
+            card: *self.card(),
+            mode,
+            point: point.filter(|_| mode == TuneMode::Curve),
+            clock,
+            original_clock: state
+                .engine_levels
+                .iter()
+                .find(|level| level.index == 1)
+                .map(|level| level.freq.mhz()),
+            start,
+            step,
+            limit: limit.or(range_min).unwrap_or(start - DEFAULT_DEPTH),
+            testing: None,
+            last_stable: None,
+            finished: false,
+            curve: state
+                .curve_labels
+                .iter()
+                .map(|point| CurvePoint {
+                    freq: point.freq.mhz(),
+                    voltage: point.voltage.millivolts(),
+                })
+                .collect(),
+            steps: vec![],
+        })
+    }
+
+    fn write_tuned(
+        &self,
+        report: &TuneReport,
+        value: i32,
+        clock: Option<u32>,
+    ) -> crate::Result<()> {
+        let mut transaction = self.transaction();
+        match report.mode {
+            TuneMode::Curve => {
+                let point = report.point.ok_or(TuneError::NoPoint)?;
+                let freq = clock
+                    .or_else(|| report.curve.get(point as usize).map(|point| point.freq))
+                    .ok_or(TuneError::NoCurvePoint(point))?;
+                transaction.write_state(
+                    point,
+                    Frequency::from_mhz(freq),
+                    Some(Voltage::from_millivolts(value.max(0) as u32)),
+                    HardwareModule::Curve,
+                );
+            }
+            TuneMode::Offset => {
+                if let Some(clock) = clock {
+                    transaction.write_state(
+                        1,
+                        Frequency::from_mhz(clock),
+                        None,
+                        HardwareModule::Engine,
+                    );
+                }
+                transaction.write_voltage_offset(value);
+            }
+        }
+        transaction.commit()
+    }
+
+    /// Write last stable value, without one starting value and clock from
+    /// before tuning
+    fn write_final(&self, report: &TuneReport) -> crate::Result<()> {
+        match report.last_stable {
+            Some(value) => self.write_tuned(report, value, report.clock),
+            // curve point frequency is taken from curve before tuning
+            None if report.mode == TuneMode::Curve => self.write_tuned(report, report.start, None),
+            None => self.write_tuned(
+                report,
+                report.start,
+                report.clock.and(report.original_clock),
+            ),
+        }
+    }
+
+    /// Lower value until stress fails or limit is reached, last stable value
+    /// (or starting one) is applied at the end. `save` is called before every
+    /// stress run
+    pub fn tune<R, S>(
+        &self,
+        report: &mut TuneReport,
+        runner: &mut R,
+        mut save: S,
+    ) -> crate::Result<()>
+    where
+        R: StressRunner,
+        S: FnMut(&TuneReport) -> Result<(), TuneError>,
+    {
+        let mut value = report.start - report.step as i32;
+        while value >= report.limit {
+            report.testing = Some(value);
+            save(report)?;
+            if let Err(e) = self.write_tuned(report, value, report.clock) {
+                tracing::warn!("Failed to write {}mV. {}", value, e);
+                report.steps.push(Step {
+                    value,
+                    stable: false,
+                    reason: Some(e.to_string()),
+                });
+                break;
+            }
+            let outcome = match runner.run(value) {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    self.write_final(report)?;
+                    return Err(e.into());
+                }
+            };
+            match outcome {
+                Outcome::Stable => {
+                    report.last_stable = Some(value);
+                    report.steps.push(Step {
+                        value,
+                        stable: true,
+                        reason: None,
+                    });
+                }
+                Outcome::Failed(reason) => {
+                    tracing::warn!("{}mV is not stable. {}", value, reason);
+                    report.steps.push(Step {
+                        value,
+                        stable: false,
+                        reason: Some(reason),
+                    });
+                    break;
+                }
+            }
+            value -= report.step as i32;
+        }
+        report.testing = None;
+        report.finished = true;
+        self.write_final(report)?;
+        save(report)?;
+        Ok(())
+    }
+}
+
+fn import(path: &str, name: &str, mut config: Config, config_path: &str) -> crate::Result<()> {
+    let report = TuneReport::load(Path::new(path))?;
+    if !report.finished {
+        tracing::warn!(
+            "Tuning did not finish, {:?}mV probably crashed GPU",
+            report.testing
+        );
+    }
+    config.set_profile(report.profile(name)?);
+    config.validate()?;
+    let content = config.to_toml_preserving(config_path)?;
+    atomic_write(config_path, content, CONFIG_BACKUPS)?;
+    println!("Saved profile {} to {}", name, config_path);
+    Ok(())
+}
+
+pub fn run(
+    command: Tune,
+    env: &Environment,
+    config: Config,
+    config_path: &str,
+) -> crate::Result<()> {
+    if let Some(path) = command.import.as_deref() {
+        let name = command.name.as_deref().unwrap_or(DEFAULT_PROFILE);
+        return import(path, name, config, config_path);
+    }
+    let stress = command.stress.ok_or(TuneError::NoStress)?;
+    let output = PathBuf::from(command.output.as_deref().unwrap_or(DEFAULT_REPORT));
+    if let Ok(previous) = TuneReport::load(&output) {
+        if !previous.finished {
+            tracing::warn!(
+                "Previous tuning did not finish, {:?}mV probably crashed GPU, last stable value was {:?}mV",
+                previous.testing,
+                previous.last_stable
+            );
+        }
+    }
+
+    let mut mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, &config);
+    if mons.is_empty() {
+        return Err(VoltageError::NoAmdGpu);
+    }
+    let mon = mons.remove(0);
+    let mut report = mon.tune_report(
+        command.point,
+        command.clock,
+        command.step.unwrap_or(DEFAULT_STEP),
+        command.limit,
+    )?;
+    let mut runner = ShellRunner {
+        command: stress,
+        log: command.log.map(PathBuf::from),
+    };
+    mon.tune(&mut report, &mut runner, |report| report.save(&output))?;
+    match report.last_stable {
+        Some(value) => println!(
+            "Last stable value {}mV, report saved to {}",
+            value,
+            output.display()
+        ),
+        None => println!("No stable value below {}mV was found", report.start),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use amdgpu_testkit::FakeGpu;
+
+    use super::{find_reset, Outcome, ShellRunner, StressRunner, TuneError, TuneMode};
+    use crate::clock_state::OdFormat;
+    use crate::command::VoltageManipulator;
+    use crate::{Config, VoltageError};
+
+    /// Outcomes given in advance, runs past script are stable
+    struct ScriptedRunner {
+        outcomes: VecDeque<Outcome>,
+        values: Vec<i32>,
+    }
+
+    impl ScriptedRunner {
+        fn new(outcomes: Vec<Outcome>) -> Self {
+            Self {
+                outcomes: outcomes.into(),
+                values: vec![],
+            }
+        }
+    }
+
+    impl StressRunner for ScriptedRunner {
+        fn run(&mut self, value: i32) -> Result<Outcome, TuneError> {
+            self.values.push(value);
+            Ok(self.outcomes.pop_front().unwrap_or(Outcome::Stable))
+        }
+    }
+
+    #[test]
+    fn curve_stops_at_first_failure() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut report = manipulator.tune_report(Some(2), None, 20, None).unwrap();
+        assert_eq!(report.mode, TuneMode::Curve);
+        assert_eq!(
+            (report.start, report.limit, report.clock),
+            (1143, 750, Some(2100))
+        );
+
+        let mut runner = ScriptedRunner::new(vec![
+            Outcome::Stable,
+            Outcome::Stable,
+            Outcome::Failed(String::from("GPU reset")),
+        ]);
+        let mut saved = vec![];
+        manipulator
+            .tune(&mut report, &mut runner, |report| {
+                saved.push(report.clone());
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(runner.values, vec![1123, 1103, 1083]);
+        assert_eq!(report.last_stable, Some(1103));
+        assert!(report.finished);
+        // crash during third run leaves previous stable value in report
+        assert_eq!(saved[2].testing, Some(1083));
+        assert_eq!(saved[2].last_stable, Some(1103));
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec![
+                "vc 2 2100 1123",
+                "c",
+                "vc 2 2100 1103",
+                "c",
+                "vc 2 2100 1083",
+                "c",
+                "vc 2 2100 1103",
+                "c"
+            ]
+        );
+
+        let profile = report.profile("tuned").unwrap();
+        assert_eq!(profile.curve.len(), 3);
+        assert_eq!(profile.curve[2].voltage, 1103);
+        assert_eq!(profile.curve[1].voltage, 772);
+    }
+
+    #[test]
+    fn offset_stops_at_limit() {
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut report = manipulator
+            .tune_report(None, Some(2500), 10, Some(-30))
+            .unwrap();
+        assert_eq!(report.mode, TuneMode::Offset);
+
+        let mut runner = ScriptedRunner::new(vec![]);
+        manipulator
+            .tune(&mut report, &mut runner, |_| Ok(()))
+            .unwrap();
+        assert_eq!(runner.values, vec![-10, -20, -30]);
+        assert_eq!(report.last_stable, Some(-30));
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage")
+                .last()
+                .map(String::as_str),
+            Some("c")
+        );
+        assert!(gpu.writes_to("pp_od_clk_voltage").ends_with(&[
+            "s 1 2500".to_string(),
+            "vo -30".to_string(),
+            "c".to_string()
+        ]));
+
+        let report: super::TuneReport = toml::from_str(&toml::to_string(&report).unwrap()).unwrap();
+        let profile = report.profile("tuned").unwrap();
+        assert_eq!(profile.voltage_offset, Some(-30));
+        assert_eq!(profile.engine.unwrap().max, Some(2500));
+    }
+
+    #[test]
+    fn zero_step_is_rejected() {
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        assert!(matches!(
+            manipulator.tune_report(None, None, 0, None),
+            Err(VoltageError::Tune(TuneError::InvalidStep))
+        ));
+    }
+
+    #[test]
+    fn clock_range_is_rejected() {
+        let gpu = FakeGpu::navi21();
+        gpu.set_device_file(
+            "pp_od_clk_voltage",
+            "OD_SCLK:\n0: 500Mhz\n1: 2615Mhz\nOD_RANGE:\nSCLK: 500Mhz 4000Mhz\n",
+        );
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        assert!(matches!(
+            manipulator.tune_report(None, None, 10, None),
+            Err(VoltageError::Tune(TuneError::Unsupported(
+                OdFormat::ClockRange
+            )))
+        ));
+    }
+
+    #[test]
+    fn unstable_start_restores_original_value() {
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut report = manipulator.tune_report(None, None, 25, None).unwrap();
+        let mut runner = ScriptedRunner::new(vec![Outcome::Failed(String::from("exit 1"))]);
+        manipulator
+            .tune(&mut report, &mut runner, |_| Ok(()))
+            .unwrap();
+        assert_eq!(report.last_stable, None);
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec!["vo -25", "c", "vo 0", "c"]
+        );
+        assert!(matches!(
+            report.profile("tuned"),
+            Err(TuneError::NoStableValue)
+        ));
+    }
+
+    #[test]
+    fn unstable_start_restores_original_clock() {
+        let gpu = FakeGpu::navi21();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let mut report = manipulator.tune_report(None, Some(2500), 25, None).unwrap();
+        assert_eq!(report.original_clock, Some(2615));
+        let mut runner = ScriptedRunner::new(vec![Outcome::Failed(String::from("exit 1"))]);
+        manipulator
+            .tune(&mut report, &mut runner, |_| Ok(()))
+            .unwrap();
+        assert_eq!(report.last_stable, None);
+        assert_eq!(
+            gpu.writes_to("pp_od_clk_voltage"),
+            vec!["s 1 2500", "vo -25", "c", "s 1 2615", "vo 0", "c"]
+        );
+    }
+
+    #[test]
+    fn shell_runner_detects_resets() {
+        assert_eq!(
+            find_reset("ok\n[  812.1] amdgpu 0000:03:00.0: amdgpu: GPU reset begin!\n"),
+            Some("[  812.1] amdgpu 0000:03:00.0: amdgpu: GPU reset begin!")
+        );
+        assert_eq!(
+            find_reset("[drm:amdgpu_job_timedout [amdgpu]] *ERROR* ring gfx_0.0.0 timeout"),
+            Some("[drm:amdgpu_job_timedout [amdgpu]] *ERROR* ring gfx_0.0.0 timeout")
+        );
+        assert_eq!(find_reset("Score: 4521"), None);
+
+        let gpu = FakeGpu::navi21();
+        let log = gpu.path().join("kern.log");
+        std::fs::write(&log, "amdgpu: GPU reset begin!\n").unwrap();
+        let mut runner = ShellRunner {
+            command: String::from("true"),
+            log: Some(log.clone()),
+        };
+        // only messages written during stress run count
+        assert_eq!(runner.run(-10).unwrap(), Outcome::Stable);
+
+        runner.command = format!("echo 'amdgpu: GPU reset begin!' >> {}", log.display());
+        assert!(matches!(runner.run(-10).unwrap(), Outcome::Failed(_)));
+
+        runner.command = String::from("exit 3");
+        assert!(matches!(runner.run(-10).unwrap(), Outcome::Failed(_)));
+    }
+}
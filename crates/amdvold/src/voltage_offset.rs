@@ -0,0 +1,141 @@
+use amdgpu::environment::Environment;
+use amdgpu::hw_mon::RootPath;
+use amdgpu::utils::hw_mons;
+
+use crate::change_state::ChangeStateError;
+use crate::command::VoltageManipulator;
+use crate::{Config, VoltageError};
+
+#[derive(Debug, gumdrop::Options)]
+pub struct VoltageOffset {
+    #[options(help = "Help message")]
+    help: bool,
+    #[options(help = "Offset of GPU voltage in mV, ex. -50")]
+    offset: Option<i32>,
+    #[options(help = "Apply changes immediately after change")]
+    apply_immediately: bool,
+    #[options(help = "Print changes without writing them")]
+    dry_run: bool,
+}
+
+pub fn run(command: VoltageOffset, env: &Environment, config: &Config) -> crate::Result<()> {
+    let mut mons = VoltageManipulator::wrap_all(hw_mons(env, false)?, config);
+    if mons.is_empty() {
+        return Err(VoltageError::NoAmdGpu);
+    }
+    let mon = mons.remove(0);
+    set_voltage_offset(&mon, command)
+}
+
+fn set_voltage_offset<Root: RootPath>(
+    mon: &VoltageManipulator<Root>,
+    command: VoltageOffset,
+) -> crate::Result<()> {
+    let offset = command.offset.ok_or(ChangeStateError::Offset)?;
+    let mut transaction = mon.transaction();
+    transaction.write_voltage_offset(offset);
+    if command.dry_run {
+        for diff in transaction.preview()? {
+            println!("{} {}", mon.card(), diff);
+        }
+        Ok(())
+    } else if command.apply_immediately {
+        transaction.commit()
+    } else {
+        transaction.stage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use amdgpu_testkit::FakeGpu;
+
+    use super::{set_voltage_offset, VoltageOffset};
+    use crate::change_state::ChangeStateError;
+    use crate::command::VoltageManipulator;
+    use crate::{Config, VoltageError};
+
+    fn command(offset: i32) -> VoltageOffset {
+        VoltageOffset {
+            help: false,
+            offset: Some(offset),
+            apply_immediately: true,
+            dry_run: false,
+        }
+    }
+
+    #[test]
+    fn write_offset() {
+        let gpu = FakeGpu::navi21();
+        gpu.set_device_file(
+            "pp_od_clk_voltage",
+            "OD_SCLK:\n0: 500Mhz\n1: 3150Mhz\nOD_VDDGFX_OFFSET:\n0mV\nOD_RANGE:\nSCLK: 500Mhz 5000Mhz\nVDDGFX_OFFSET: -450mv 0mv\n",
+        );
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+
+        assert!(matches!(
+            set_voltage_offset(&manipulator, command(-500)),
+            Err(VoltageError::ChangeStateError(
+                ChangeStateError::OffsetOutOfRange {
+                    min: -450,
+                    max: 0,
+                    ..
+                }
+            ))
+        ));
+        set_voltage_offset(&manipulator, command(-50)).unwrap();
+        assert_eq!(gpu.writes_to("pp_od_clk_voltage"), vec!["vo -50", "c"]);
+    }
+
+    #[test]
+    fn dry_run_writes_nothing() {
+        let gpu = FakeGpu::navi21();
+        gpu.set_device_file(
+            "pp_od_clk_voltage",
+            "OD_SCLK:\n0: 500Mhz\n1: 3150Mhz\nOD_VDDGFX_OFFSET:\n0mV\nOD_RANGE:\nSCLK: 500Mhz 5000Mhz\nVDDGFX_OFFSET: -450mv 0mv\n",
+        );
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        let dry_run = |offset| VoltageOffset {
+            dry_run: true,
+            ..command(offset)
+        };
+        set_voltage_offset(&manipulator, dry_run(-50)).unwrap();
+        assert!(matches!(
+            set_voltage_offset(&manipulator, dry_run(-1000)),
+            Err(VoltageError::ChangeStateError(
+                ChangeStateError::OffsetOutOfRange { .. }
+            ))
+        ));
+        assert!(gpu.writes().is_empty());
+    }
+
+    #[test]
+    fn older_cards_have_no_offset() {
+        let gpu = FakeGpu::vega20();
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        assert!(matches!(
+            set_voltage_offset(&manipulator, command(-50)),
+            Err(VoltageError::ChangeStateError(
+                ChangeStateError::UnsupportedOffset
+            ))
+        ));
+        assert!(gpu.writes().is_empty());
+    }
+
+    #[test]
+    fn clock_range_has_no_offset() {
+        let gpu = FakeGpu::navi21();
+        gpu.set_device_file(
+            "pp_od_clk_voltage",
+            "OD_SCLK:\n0: 500Mhz\n1: 2615Mhz\nOD_RANGE:\nSCLK: 500Mhz 4000Mhz\n",
+        );
+        let manipulator = VoltageManipulator::wrap(gpu.hw_mon(), &Config::default());
+        assert!(matches!(
+            set_voltage_offset(&manipulator, command(-50)),
+            Err(VoltageError::ChangeStateError(
+                ChangeStateError::UnsupportedOffset
+            ))
+        ));
+        assert!(gpu.writes().is_empty());
+    }
+}